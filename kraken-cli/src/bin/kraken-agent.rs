@@ -0,0 +1,82 @@
+//! Reference signing agent: holds a key and secret and signs the requests
+//! of `kraken-cli --agent <socket>` so the secret never enters the cli.
+
+#![allow(clippy::needless_return)]
+
+use anyhow::{anyhow, Context, Result};
+use clap::{App, Arg};
+use data_encoding::BASE64;
+use kraken::signer::HmacSigner;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+fn load_key_pair(
+    key: Option<&str>,
+    secret: Option<&str>,
+    credentials: Option<&str>,
+) -> Result<(String, Zeroizing<String>)> {
+    if let (Some(key), Some(secret)) = (key, secret) {
+        return Ok((key.to_string(), Zeroizing::new(secret.to_string())));
+    }
+    let path = credentials.ok_or(anyhow!("missing key and secret or credential file"))?;
+    let content = Zeroizing::new(
+        std::fs::read_to_string(path).map_err(|e| anyhow!("cannot open {} ({})", path, e))?,
+    );
+    // same format as the cli: <API_KEY>\n<API_SECRET>\n
+    let mut lines = content.lines();
+    match (lines.next(), lines.next()) {
+        (Some(key), Some(secret)) => Ok((key.to_string(), Zeroizing::new(secret.to_string()))),
+        _ => Err(anyhow!("invalid credential file")),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let matches = App::new("kraken-agent")
+        .version("1.0.1")
+        .about("Signs private requests for kraken-cli --agent.")
+        .arg(
+            Arg::with_name("socket")
+                .long("socket")
+                .env("KRAKEN_AGENT")
+                .takes_value(true)
+                .required(true)
+                .help("path of the unix socket to listen on"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .env("KRAKEN_KEY")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("secret")
+                .long("secret")
+                .env("KRAKEN_SECRET")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("credentials")
+                .long("credentials")
+                .env("CREDENTIALS")
+                .takes_value(true)
+                .help("path of file that contains your key and secret"),
+        )
+        .get_matches();
+    let (key, secret) = load_key_pair(
+        matches.value_of("key"),
+        matches.value_of("secret"),
+        matches.value_of("credentials"),
+    )?;
+    let secret = Zeroizing::new(
+        BASE64
+            .decode(secret.as_bytes())
+            .context("cannot decode secret")?,
+    );
+    let socket = Path::new(matches.value_of("socket").unwrap());
+    eprintln!("listening on {}", socket.display());
+    kraken::signer::serve_agent(socket, &key, HmacSigner::new(&secret))
+        .await
+        .map_err(|e| anyhow!("{} ({:?})", e, e))?;
+    return Ok(());
+}
@@ -0,0 +1,196 @@
+use anyhow::{anyhow, Result};
+use kraken::permissions::Permission;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Permissions a profile can carry and the subcommands needing them;
+/// `edit` reads the order it changes and needs two. Subcommands that
+/// are not listed only use public endpoints, except
+/// `tui` which shows what the key can read and only needs `cancel` to
+/// cancel an order, and `fee-estimate` which only needs `query-funds`
+/// to fetch the 30-day volume.
+pub const PERMISSIONS: &[(Permission, &[&str])] = &[
+    (
+        Permission::QueryFunds,
+        &[
+            "balance",
+            "balance-ex",
+            "trade-balance",
+            "trade-volume",
+            "portfolio",
+            "alert",
+        ],
+    ),
+    (
+        Permission::QueryOrders,
+        &[
+            "open-orders",
+            "closed-orders",
+            "query-orders",
+            "trades-history",
+            "query-trades",
+            "open-positions",
+            "pnl",
+            "edit",
+        ],
+    ),
+    (
+        Permission::QueryLedger,
+        &["ledgers", "query-ledgers", "reconcile", "tax-report"],
+    ),
+    (Permission::Trade, &["buy", "sell", "edit", "twap"]),
+    (Permission::Cancel, &["cancel", "cancel-all"]),
+];
+
+/// Returns the permissions needed to run a subcommand.
+pub fn required_permissions(command: &str) -> Vec<Permission> {
+    return PERMISSIONS
+        .iter()
+        .filter(|(_, commands)| commands.contains(&command))
+        .map(|(permission, _)| *permission)
+        .collect();
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    pub key: Option<String>,
+    pub secret: Option<String>,
+    /// Path of a credential file, used when key and secret are not set.
+    pub credentials: Option<String>,
+    /// Name of an entry of the encrypted credential store, used instead
+    /// of the fields above.
+    pub stored: Option<String>,
+    /// Socket of a signing agent, used instead of all the fields above.
+    pub agent: Option<String>,
+    pub base_url: Option<String>,
+    /// Output format used when `--output` is not given.
+    pub output: Option<String>,
+    /// Pair used when `--pair` is not given.
+    pub pair: Option<String>,
+    /// State file of the paper trading simulator, used instead of the
+    /// account when `--paper` is not given.
+    pub paper: Option<String>,
+    /// What the key of the profile is allowed to do. Profiles without
+    /// permissions are not checked.
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+}
+
+impl Profile {
+    /// Fails when the labels of the profile do not allow `command`.
+    pub fn check_permission(&self, name: &str, command: &str) -> Result<()> {
+        if self.permissions.is_empty() {
+            return Ok(());
+        }
+        match required_permissions(command)
+            .into_iter()
+            .find(|permission| !self.permissions.contains(permission))
+        {
+            Some(permission) => Err(anyhow!(
+                "profile {} is not allowed to {} ({} permission needed)",
+                name,
+                command,
+                permission
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Named profiles read from a TOML file:
+///
+/// ```toml
+/// default_profile = "readonly"
+///
+/// [profile.readonly]
+/// key = "..."
+/// secret = "..."
+/// permissions = ["query-funds", "query-orders"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    pub default_profile: Option<String>,
+    #[serde(rename = "profile", default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// `~/.config/kraken/config.toml` on Linux.
+pub fn default_path() -> Option<PathBuf> {
+    return dirs::config_dir().map(|dir| dir.join("kraken").join("config.toml"));
+}
+
+impl Config {
+    pub fn from_toml(content: &str) -> Result<Self> {
+        return Ok(toml::from_str(content)?);
+    }
+
+    /// Loads the config at `path` or, when no path is given, at the
+    /// default path if it exists.
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let path = match (path, default_path()) {
+            (Some(path), _) => PathBuf::from(path),
+            (None, Some(path)) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        };
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("cannot open {} ({})", path.display(), e))?;
+        return Self::from_toml(&content)
+            .map_err(|e| anyhow!("invalid config {} ({})", path.display(), e));
+    }
+
+    /// Returns the named profile or, when no name is given, the default
+    /// profile if any.
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<(&str, &Profile)>> {
+        let name = match name.or(self.default_profile.as_deref()) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        return match self.profiles.get_key_value(name) {
+            Some((name, profile)) => Ok(Some((name.as_str(), profile))),
+            None => Err(anyhow!("unknown profile {}", name)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Profile};
+    use kraken::permissions::Permission;
+
+    #[test]
+    fn profiles_test() {
+        let config = Config::from_toml(
+            r#"
+            default_profile = "readonly"
+
+            [profile.readonly]
+            key = "KEY"
+            secret = "SECRET"
+            permissions = ["query-funds"]
+
+            [profile.trading]
+            credentials = "/tmp/trading"
+            pair = "XBTUSD"
+            "#,
+        )
+        .unwrap();
+        let (name, profile) = config.profile(None).unwrap().unwrap();
+        assert_eq!(name, "readonly");
+        assert!(profile.check_permission(name, "balance").is_ok());
+        assert!(profile.check_permission(name, "ticker").is_ok());
+        assert!(profile.check_permission(name, "tui").is_ok());
+        assert!(profile.check_permission(name, "buy").is_err());
+        let (name, profile) = config.profile(Some("trading")).unwrap().unwrap();
+        assert_eq!(profile.pair.as_deref(), Some("XBTUSD"));
+        assert!(profile.check_permission(name, "buy").is_ok());
+        // edit reads the order it changes.
+        let trader = Profile {
+            permissions: vec![Permission::Trade],
+            ..Default::default()
+        };
+        assert!(trader.check_permission("trader", "edit").is_err());
+        assert!(config.profile(Some("missing")).is_err());
+        assert!(Config::from_toml("[profile.a]\npermissions = [\"withdraw\"]").is_err());
+    }
+}
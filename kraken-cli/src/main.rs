@@ -1,25 +1,40 @@
+#![allow(clippy::needless_return)]
+
+mod config;
+mod output;
+mod store;
+mod tui;
+
 use std::str::FromStr;
 
 use anyhow::{anyhow, Context, Result};
-use clap::{App, Arg, SubCommand};
+use chrono::NaiveDate;
+use clap::{App, Arg, ArgMatches, SubCommand};
 use data_encoding::BASE64;
-use kraken;
-use serde::Serialize;
-use serde_json::to_string_pretty;
-
-fn display<T>(output: T)
-where
-    T: Serialize,
-{
-    if let Ok(out) = to_string_pretty(&output) {
-        println!("{}", out);
-    }
-}
+use kraken::algo::{self, Execution};
+use kraken::exchange::{Exchange, Live};
+use kraken::fees::{self, Liquidity};
+use kraken::paper::Paper;
+use kraken::permissions::Access;
+use kraken::pnl::{Method, PnlEngine};
+use kraken::private::{NewOrder, OrderEdit};
+use kraken::registry::AssetRegistry;
+use kraken::tax;
+use kraken::transport::{Recorder, Replay};
+use kraken::validation::{Validator, ORDER_TYPES};
+use config::{required_permissions, Config, Profile};
+use output::{display_table, Filter, Format, Output, Selection, FORMATS};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use store::{Secret, Store};
+use std::time::Duration;
+use zeroize::Zeroizing;
 
 fn load_credentials_from_args(key: Option<&str>, secret: Option<&str>) -> Option<(String, String)> {
     let key = key.unwrap_or("");
     let secret = secret.unwrap_or("");
-    if key == "" || secret == "" {
+    if key.is_empty() || secret.is_empty() {
         return None;
     }
     Some((key.to_string(), secret.to_string()))
@@ -27,13 +42,13 @@ fn load_credentials_from_args(key: Option<&str>, secret: Option<&str>) -> Option
 
 async fn load_credentials_from_file(credentials: Option<&str>) -> Result<Option<(String, String)>> {
     let credentials = credentials.unwrap_or("");
-    if credentials == "" {
+    if credentials.is_empty() {
         return Ok(None);
     }
     let content = tokio::fs::read_to_string(credentials)
         .await
         .map_err(|e| anyhow!("cannot open {} ({})", credentials, e))?;
-    let lines: Vec<&str> = content.lines().map(|l| l).collect();
+    let lines: Vec<&str> = content.lines().collect();
     // ensure extra lines at the end of the file won't cause any error.
     // the credential file must be generated as:
     // <API_KEY>\n
@@ -41,7 +56,7 @@ async fn load_credentials_from_file(credentials: Option<&str>) -> Result<Option<
     if lines.len() < 2 {
         return Err(anyhow!("invalid credential file"));
     }
-    let key = *lines.get(0).unwrap();
+    let key = *lines.first().unwrap();
     let secret = *lines.get(1).unwrap();
     Ok(Some((key.to_string(), secret.to_string())))
 }
@@ -60,10 +75,131 @@ async fn build_credentials(
     }
     let (key, secret) = key_pair.unwrap();
     let secret = secret.as_bytes();
-    let secret = BASE64.decode(secret).context("cannot decode secret")?;
+    let secret = Zeroizing::new(BASE64.decode(secret).context("cannot decode secret")?);
     return Ok(Some(kraken::Credential::new(&key, &secret)));
 }
 
+/// Credentials signed by the agent listening on `socket`, see kraken-agent.
+#[cfg(unix)]
+async fn agent_credentials(socket: &str) -> Result<kraken::Credential> {
+    return kraken::Credential::from_agent(socket)
+        .await
+        .map_err(pretty_error)
+        .with_context(|| format!("cannot reach signing agent {}", socket));
+}
+
+#[cfg(not(unix))]
+async fn agent_credentials(_socket: &str) -> Result<kraken::Credential> {
+    return Err(anyhow!("signing agents are only supported on unix"));
+}
+
+fn store_path(path: Option<&str>) -> Result<PathBuf> {
+    return path
+        .map(PathBuf::from)
+        .or_else(store::default_path)
+        .ok_or(anyhow!("cannot locate the credential store"));
+}
+
+/// Reads a passphrase from `env` or from the terminal, twice when
+/// `confirm` is set.
+fn passphrase(prompt: &str, env: &str, confirm: bool) -> Result<Zeroizing<String>> {
+    if let Ok(val) = std::env::var(env) {
+        return Ok(Zeroizing::new(val));
+    }
+    let val = Zeroizing::new(rpassword::prompt_password(format!("{}: ", prompt))?);
+    if confirm {
+        let again = Zeroizing::new(rpassword::prompt_password(format!("{} (again): ", prompt))?);
+        if val != again {
+            return Err(anyhow!("passphrases do not match"));
+        }
+    }
+    if val.is_empty() {
+        return Err(anyhow!("empty passphrase"));
+    }
+    return Ok(val);
+}
+
+/// Decrypts a credential of the store.
+fn load_stored_credentials(path: &Path, name: &str) -> Result<kraken::Credential> {
+    let store = Store::load(path)?;
+    let master = store.unlock(&passphrase("passphrase", "KRAKEN_PASSPHRASE", false)?)?;
+    let secret = store.get(&master, name)?;
+    let decoded = Zeroizing::new(BASE64.decode(secret.secret.as_bytes()).context("cannot decode secret")?);
+    return Ok(kraken::Credential::new(&secret.key, &decoded));
+}
+
+fn credentials_command(cmd: &ArgMatches, path: &Path, output: &Output) -> Result<()> {
+    match cmd.subcommand() {
+        ("add", Some(cmd)) => {
+            let name = cmd.value_of("name").unwrap();
+            let (mut store, master) = if path.exists() {
+                let store = Store::load(path)?;
+                let master = store.unlock(&passphrase("passphrase", "KRAKEN_PASSPHRASE", false)?)?;
+                (store, master)
+            } else {
+                let store = Store::new();
+                let master = store.unlock(&passphrase("new passphrase", "KRAKEN_PASSPHRASE", true)?)?;
+                (store, master)
+            };
+            if store.entries.contains_key(name) && !cmd.is_present("force") {
+                return Err(anyhow!("{} already exists, use --force to replace it", name));
+            }
+            let secret = Secret {
+                key: rpassword::prompt_password("API key: ")?.trim().to_string(),
+                secret: rpassword::prompt_password("API secret: ")?.trim().to_string(),
+            };
+            BASE64
+                .decode(secret.secret.as_bytes())
+                .map(Zeroizing::new)
+                .context("cannot decode secret")?;
+            store.insert(&master, name, &secret)?;
+            store.save(path)?;
+            eprintln!("{} stored in {}", name, path.display());
+        }
+        ("list", _) => {
+            let store = Store::load(path)?;
+            let date = |ts: i64| {
+                chrono::DateTime::from_timestamp(ts, 0)
+                    .unwrap_or_default()
+                    .naive_utc()
+                    .to_string()
+            };
+            let entries: Vec<serde_json::Value> = store
+                .entries
+                .iter()
+                .map(|(name, entry)| {
+                    serde_json::json!({
+                        "name": name,
+                        "created": date(entry.created),
+                        "updated": date(entry.updated),
+                    })
+                })
+                .collect();
+            output.display(entries);
+        }
+        ("remove", Some(cmd)) => {
+            let name = cmd.value_of("name").unwrap();
+            let mut store = Store::load(path)?;
+            store
+                .entries
+                .remove(name)
+                .ok_or(anyhow!("unknown credential {}", name))?;
+            store.save(path)?;
+            eprintln!("{} removed", name);
+        }
+        ("rotate", _) => {
+            let store = Store::load(path)?;
+            let master = store.unlock(&passphrase("current passphrase", "KRAKEN_PASSPHRASE", false)?)?;
+            let new = passphrase("new passphrase", "KRAKEN_NEW_PASSPHRASE", true)?;
+            let (rotated, _) = store.rotate(&master, &new)?;
+            rotated.save(path)?;
+            eprintln!("{} entries encrypted with the new passphrase", rotated.entries.len());
+        }
+        _ => return Err(anyhow!("expected one of add, list, remove or rotate")),
+    }
+    return Ok(());
+}
+
 fn parse_number_option<T>(val: Option<&str>) -> Result<Option<T>, anyhow::Error>
 where
     T: FromStr,
@@ -78,11 +214,423 @@ where
     return Ok(val);
 }
 
+/// Parses a date (YYYY-MM-DD) or a unix timestamp and returns it in seconds.
+fn parse_time(val: &str) -> Result<i64> {
+    if let Ok(ts) = val.parse::<i64>() {
+        return Ok(ts);
+    }
+    let date = NaiveDate::parse_from_str(val, "%Y-%m-%d")
+        .map_err(|_| anyhow!("invalid date {}, expected YYYY-MM-DD", val))?;
+    return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp());
+}
+
+/// Parses a duration in seconds, optionally suffixed with s, m, h or d.
+fn parse_duration(val: &str) -> Result<u64> {
+    let invalid = || anyhow!("invalid duration {}, expected e.g. 90s, 30m or 2h", val);
+    let (number, unit) = match val.chars().last() {
+        Some(unit) if unit.is_ascii_alphabetic() => (&val[..val.len() - 1], unit),
+        _ => (val, 's'),
+    };
+    let scale = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 24 * 3600,
+        _ => return Err(invalid()),
+    };
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    return Ok(number * scale);
+}
+
+fn parse_time_option(val: Option<&str>) -> Result<Option<i64>> {
+    return val.map(parse_time).transpose();
+}
+
+/// Returns the value of a global argument, which clap only exposes on
+/// the subcommand when it is given after it.
+fn global_value<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    let sub = matches.subcommand().1.and_then(|cmd| cmd.value_of(name));
+    return sub.or_else(|| matches.value_of(name));
+}
+
+/// Whether a global argument is given on the command line rather than
+/// through its environment variable.
+fn global_given(matches: &ArgMatches, name: &str) -> bool {
+    let sub = matches.subcommand().1.map(|cmd| cmd.occurrences_of(name));
+    return sub.unwrap_or(0) + matches.occurrences_of(name) > 0;
+}
+
+/// Arguments giving credentials outside of a profile.
+const CREDENTIAL_ARGS: &[&str] = &["agent", "stored", "key", "secret", "credentials"];
+
+/// Whether the credentials come from the arguments rather than from a
+/// profile. The command line wins over the environment, which wins over
+/// the default profile; a profile and credentials given at the same level
+/// are refused.
+fn credentials_from_args(matches: &ArgMatches) -> Result<bool> {
+    let given = CREDENTIAL_ARGS.iter().any(|name| global_given(matches, name));
+    if global_given(matches, "profile") {
+        if given {
+            return Err(anyhow!("--profile cannot be combined with --{}", CREDENTIAL_ARGS.join(", --")));
+        }
+        return Ok(false);
+    }
+    if given {
+        return Ok(true);
+    }
+    let from_env = CREDENTIAL_ARGS.iter().any(|name| global_value(matches, name).is_some());
+    if from_env && global_value(matches, "profile").is_some() {
+        return Err(anyhow!("KRAKEN_PROFILE cannot be combined with credentials of the environment"));
+    }
+    return Ok(from_env);
+}
+
+fn global_values(matches: &ArgMatches, name: &str) -> Vec<String> {
+    let sub = matches.subcommand().1.and_then(|cmd| cmd.values_of(name));
+    return match sub.or_else(|| matches.values_of(name)) {
+        Some(values) => values.map(|v| v.to_string()).collect(),
+        None => vec![],
+    };
+}
+
+/// Subcommands that place orders or write files, which are never re-run
+/// by `--watch`.
+const UNWATCHABLE: &[&str] = &[
+    "", "alert", "backfill", "buy", "cancel", "cancel-all", "credentials", "edit", "paper", "sell",
+    "tax-report", "tui", "twap",
+];
+
+/// Subcommands answered by a single endpoint, whose body `--output raw`
+/// prints as received.
+const RAW: &[&str] = &[
+    "asset-pair", "assets", "balance", "balance-ex", "closed-orders", "depth", "ledgers", "ohlc",
+    "open-orders", "open-positions", "query-ledgers", "query-orders", "query-trades", "spread",
+    "system-status", "ticker", "time", "trade-balance", "trade-volume", "trades", "trades-history",
+];
+
+/// Delay between the public trades requests pricing a tax report.
+const TAX_PRICES_DELAY: Duration = Duration::from_millis(1500);
+
+/// Subcommands answered by the paper trading simulator with `--paper`.
+const PAPER: &[&str] = &[
+    "balance", "buy", "cancel", "cancel-all", "closed-orders", "open-orders", "paper", "sell",
+    "trades-history", "twap",
+];
+
+fn order_subcommand<'a, 'b>(name: &'a str, about: &'a str) -> App<'a, 'b> {
+    return SubCommand::with_name(name)
+        .arg(
+            Arg::with_name("pair")
+                .long("pair")
+                .takes_value(true)
+                .help("defaults to the pair of the profile"),
+        )
+        .arg(
+            Arg::with_name("volume")
+                .long("volume")
+                .takes_value(true)
+                .required(true)
+                .help("order volume in the base currency"),
+        )
+        .arg(
+            Arg::with_name("price")
+                .long("price")
+                .takes_value(true)
+                .help("limit price, trigger price for stop-loss and take-profit orders"),
+        )
+        .arg(
+            Arg::with_name("price2")
+                .long("price2")
+                .takes_value(true)
+                .help("limit price of stop-loss-limit and take-profit-limit orders"),
+        )
+        .arg(
+            Arg::with_name("type")
+                .long("type")
+                .takes_value(true)
+                .default_value("limit")
+                .possible_values(ORDER_TYPES),
+        )
+        .arg(Arg::with_name("leverage").long("leverage").takes_value(true))
+        .arg(Arg::with_name("post-only").long("post-only"))
+        .arg(Arg::with_name("userref").long("userref").takes_value(true))
+        .arg(
+            Arg::with_name("validate")
+                .long("validate")
+                .help("only let the exchange validate the order"),
+        )
+        .arg(
+            Arg::with_name("yes")
+                .long("yes")
+                .short("y")
+                .help("do not ask for confirmation"),
+        )
+        .about(about);
+}
+
+/// Prints what is about to be done and asks for a confirmation unless
+/// `yes` is set.
+fn confirm(summary: &[(&str, String)], yes: bool) -> Result<()> {
+    let width = summary.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+    for (key, val) in summary {
+        eprintln!("{:width$}  {}", key, val, width = width);
+    }
+    if yes {
+        return Ok(());
+    }
+    eprint!("proceed? [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        return Ok(());
+    }
+    return Err(anyhow!("aborted"));
+}
+
+/// Returns the estimated cost and fee of an order. Market and trailing
+/// orders are estimated at the last traded price.
+async fn order_estimate(
+    cred: &kraken::Credential,
+    registry: &AssetRegistry,
+    order: &NewOrder,
+) -> Result<Vec<(&'static str, String)>> {
+    let asset_pair = registry
+        .pair(&order.pair)
+        .ok_or(anyhow!("unknown pair {}", order.pair))?;
+    let limit = match order.ordertype.as_str() {
+        "market" | "trailing-stop" | "trailing-stop-limit" => None,
+        _ => order.price,
+    };
+    let (price, at) = match limit {
+        Some(price) => (price, price.to_string()),
+        None => {
+            let tickers = kraken::public::ticker(&order.pair).await.map_err(pretty_error)?;
+            let info = tickers
+                .0
+                .values()
+                .next()
+                .ok_or(anyhow!("no ticker for {}", order.pair))?;
+            (info.c.0, format!("~{} (last)", info.c.0))
+        }
+    };
+    let notional = order.volume * price;
+    let liquidity = match order.oflags.as_deref() {
+        Some(oflags) if oflags.contains("post") => Liquidity::Maker,
+        _ => Liquidity::Taker,
+    };
+    // the account volume needs the query-funds permission, without it
+    // the fee of the first tier is shown.
+    let trade_volume = match kraken::private::trade_volume(cred, Some(&[&order.pair]), Some(true)).await {
+        Ok(trade_volume) => Some(trade_volume),
+        Err(e) if is_denied(&e) => None,
+        Err(e) => return Err(pretty_error(e)),
+    };
+    let (estimate, tier) = match trade_volume {
+        Some(trade_volume) => (
+            fees::estimate_for_account(&order.pair, asset_pair, liquidity, &trade_volume, notional),
+            "",
+        ),
+        None => (fees::estimate(asset_pair, liquidity, Decimal::ZERO, notional), ", first tier"),
+    };
+    return Ok(vec![
+        ("cost", format!("{} {} at {}", notional.round_dp(8), asset_pair.quote, at)),
+        (
+            "fee",
+            format!("{} {} ({}%{})", estimate.fee.round_dp(8), asset_pair.quote, estimate.rate, tier),
+        ),
+    ]);
+}
+
+/// Whether a request was refused for a permission the key lacks, by the
+/// library or by the exchange.
+fn is_denied(e: &kraken::Error) -> bool {
+    return match e {
+        kraken::Error::Permission { .. } => true,
+        kraken::Error::API(e) => e.contains("EGeneral:Permission denied"),
+        _ => false,
+    };
+}
+
+/// Returns `--pair` or the default pair of the profile.
+fn pair_value<'a>(cmd: &'a ArgMatches, profile: &'a Profile) -> Result<&'a str> {
+    return cmd
+        .value_of("pair")
+        .or(profile.pair.as_deref())
+        .ok_or(anyhow!("missing --pair"));
+}
+
+/// Builds and validates the order of a buy or sell subcommand.
+fn new_order(cmd: &ArgMatches, side: &str, registry: &AssetRegistry, profile: &Profile) -> Result<NewOrder> {
+    let pair = pair_value(cmd, profile)?;
+    let pair_id = registry.pair_id(pair).ok_or(anyhow!("unknown pair {}", pair))?;
+    let order = NewOrder {
+        pair: pair_id.to_string(),
+        type_: side.to_string(),
+        ordertype: cmd.value_of("type").unwrap().to_string(),
+        volume: parse_number_option(cmd.value_of("volume"))?.unwrap(),
+        price: parse_number_option(cmd.value_of("price"))?,
+        price2: parse_number_option(cmd.value_of("price2"))?,
+        leverage: parse_number_option(cmd.value_of("leverage"))?,
+        oflags: cmd.is_present("post-only").then(|| "post".to_string()),
+        userref: parse_number_option(cmd.value_of("userref"))?,
+    };
+    return Validator::new(registry.pair(pair_id).unwrap())
+        .validate(&order)
+        .map_err(|e| anyhow!("{}", e));
+}
+
+/// Runs a subcommand of `PAPER` against the paper trading simulator,
+/// whose state is kept in `path` between runs. Orders are filled against
+/// the book and the trades fetched at each run.
+async fn paper_command(matches: &ArgMatches<'_>, path: &Path, profile: &Profile, output: &Output) -> Result<()> {
+    let registry = AssetRegistry::shared().await.map_err(pretty_error)?;
+    let command = matches.subcommand_name().unwrap();
+    let cmd = matches.subcommand_matches(command).unwrap();
+    if command == "paper" {
+        let mut paper = Paper::new(registry);
+        for spec in cmd.values_of("balance").unwrap() {
+            let (asset, amount) = spec
+                .split_once('=')
+                .ok_or(anyhow!("invalid --balance {}, expected <asset>=<amount>", spec))?;
+            let amount = Decimal::from_str(amount).map_err(|_| anyhow!("invalid amount {}", amount))?;
+            paper = paper.with_balance(asset, amount);
+        }
+        paper.save(path).map_err(pretty_error)?;
+        output.display(paper.balance().await.map_err(pretty_error)?);
+        return Ok(());
+    }
+    let paper = Paper::load(registry, path)
+        .map_err(pretty_error)
+        .with_context(|| format!("cannot load paper trading state {}, see paper", path.display()))?;
+    let order = match command {
+        "buy" | "sell" => Some(new_order(cmd, command, registry, profile)?),
+        _ => None,
+    };
+    if command == "twap" {
+        // saved whatever the result, with the fills of the children and
+        // those left open when they could not be canceled.
+        let result = twap_command(cmd, &paper, registry, profile, output).await;
+        paper.save(path).map_err(pretty_error)?;
+        return result;
+    }
+    let mut pairs = paper.active_pairs();
+    pairs.extend(order.iter().map(|o| o.pair.clone()));
+    pairs.dedup();
+    for pair in pairs {
+        paper.poll(&pair).await.map_err(pretty_error)?;
+    }
+    match (command, order) {
+        ("balance", _) => output.display(paper.balance().await.map_err(pretty_error)?),
+        ("open-orders", _) => output.display(paper.open_orders().await.map_err(pretty_error)?),
+        ("closed-orders", _) => output.display(paper.closed_orders().await.map_err(pretty_error)?),
+        ("trades-history", _) => output.display(paper.trades_history().await.map_err(pretty_error)?),
+        (_, Some(order)) => output.display(
+            paper
+                .add_order(&order, cmd.is_present("validate"))
+                .await
+                .map_err(pretty_error)?,
+        ),
+        ("cancel", _) => output.display(
+            paper
+                .cancel_order(cmd.value_of("txid").unwrap())
+                .await
+                .map_err(pretty_error)?,
+        ),
+        _ => output.display(paper.cancel_all().await.map_err(pretty_error)?),
+    }
+    paper.save(path).map_err(pretty_error)?;
+    return Ok(());
+}
+
+/// Executes the parent order of the twap subcommand as child orders on
+/// `exchange` until done or interrupted, and cancels the resting child.
+async fn twap_command(
+    cmd: &ArgMatches<'_>,
+    exchange: &dyn Exchange,
+    registry: &AssetRegistry,
+    profile: &Profile,
+    output: &Output,
+) -> Result<()> {
+    let pair = pair_value(cmd, profile)?;
+    let pair_id = registry.pair_id(pair).ok_or(anyhow!("unknown pair {}", pair))?;
+    let asset_pair = registry.pair(pair_id).unwrap();
+    let side = cmd.value_of("type").unwrap();
+    let volume: Decimal = parse_number_option(cmd.value_of("volume"))?.unwrap();
+    let duration = cmd.value_of("duration").map(parse_duration).transpose()?;
+    let schedule = match (duration, cmd.value_of("vwap")) {
+        (None, _) => algo::iceberg(volume),
+        (Some(duration), Some(interval)) => {
+            let interval = interval
+                .parse::<u64>()
+                .map_err(|_| anyhow!("invalid interval {}", interval))?;
+            let candles = kraken::public::ohcl(pair_id, Some(interval), None)
+                .await
+                .map_err(pretty_error)?;
+            let start = chrono::Utc::now().timestamp() as u64;
+            algo::vwap(volume, start, duration, &kraken::backfill::from_ohlc(&candles))
+        }
+        (Some(duration), None) => {
+            let slices = parse_number_option(cmd.value_of("slices"))?;
+            algo::twap(volume, duration, slices.unwrap_or(duration / 60))
+        }
+    };
+    let interval = parse_number_option(cmd.value_of("interval"))?.unwrap();
+    let mut summary = vec![
+        ("pair", pair_id.to_string()),
+        ("side", side.to_string()),
+        ("volume", volume.to_string()),
+        match duration {
+            Some(duration) => ("slices", format!("{} over {}s", schedule.len(), duration)),
+            None => ("slices", "at once".to_string()),
+        },
+    ];
+    let mut execution = Execution::new(asset_pair, side, schedule).with_interval(Duration::from_secs(interval));
+    if let Some(duration) = duration {
+        execution = execution.with_deadline(duration);
+    }
+    if let Some(visible) = parse_number_option(cmd.value_of("visible"))? {
+        summary.push(("visible", format!("{}", visible)));
+        execution = execution.with_visible(visible);
+    }
+    if let Some(limit) = parse_number_option(cmd.value_of("limit"))? {
+        summary.push(("limit", format!("{}", limit)));
+        execution = execution.with_limit(limit);
+    }
+    confirm(&summary, cmd.is_present("yes"))?;
+    let progress = |e: &Execution| {
+        eprintln!("{} / {} executed, {} child orders", e.executed(), e.volume(), e.children())
+    };
+    let result = tokio::select! {
+        result = execution.run(exchange, progress) => result.map_err(pretty_error),
+        _ = tokio::signal::ctrl_c() => Err(anyhow!("interrupted")),
+    };
+    // an interrupted round may have sent a child without an answer, the
+    // reconciliation finds it before every open child is canceled.
+    execution.abort(exchange).await.map_err(pretty_error)?;
+    output.display(serde_json::json!({
+        "pair": pair_id,
+        "side": side,
+        "volume": execution.volume(),
+        "executed": execution.executed(),
+        "cost": execution.cost(),
+        "fee": execution.fee(),
+        "average_price": execution.average_price(),
+        "children": execution.children(),
+    }));
+    return result;
+}
+
 fn pretty_error(e: kraken::Error) -> anyhow::Error {
     match e {
         kraken::Error::API(e) => anyhow!("[API] {}", e),
         kraken::Error::JSON(e) => anyhow!("[JSON DECODE] {}", e),
         kraken::Error::Request(e) => anyhow!("[CLIENT] {}", e),
+        kraken::Error::IO(e) => anyhow!("[IO] {}", e),
+        kraken::Error::Storage(e) => anyhow!("[STORAGE] {}", e),
+        kraken::Error::Invalid(e) => anyhow!("invalid {}", e),
+        kraken::Error::Command(e) => anyhow!("[COMMAND] {}", e),
+        e @ kraken::Error::Permission { .. } => anyhow!("[PERMISSION] {}", e),
+        e => anyhow!("{}", e),
     }
 }
 
@@ -113,6 +661,119 @@ async fn main() -> Result<(), anyhow::Error> {
                 .global(true)
                 .help("path of file that contains your key and secret"),
         )
+        .arg(
+            Arg::with_name("stored")
+                .long("stored")
+                .env("KRAKEN_STORED")
+                .takes_value(true)
+                .global(true)
+                .help("name of the encrypted credential to use, see credentials"),
+        )
+        .arg(
+            Arg::with_name("store-file")
+                .long("store-file")
+                .env("KRAKEN_STORE")
+                .takes_value(true)
+                .global(true)
+                .help("path of the credential store [default: ~/.config/kraken/credentials.json]"),
+        )
+        .arg(
+            Arg::with_name("agent")
+                .long("agent")
+                .env("KRAKEN_AGENT")
+                .takes_value(true)
+                .global(true)
+                .help("socket of a signing agent holding the key and secret, see kraken-agent"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .env("KRAKEN_RECORD")
+                .takes_value(true)
+                .global(true)
+                .conflicts_with("replay")
+                .help("write every request and response to fixture files of this directory"),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .env("KRAKEN_REPLAY")
+                .takes_value(true)
+                .global(true)
+                .help("answer requests with the fixture files of this directory, without network"),
+        )
+        .arg(
+            Arg::with_name("paper")
+                .long("paper")
+                .env("KRAKEN_PAPER")
+                .takes_value(true)
+                .global(true)
+                .help("simulate orders and balances in this state file instead of the account, see paper"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .env("KRAKEN_PROFILE")
+                .takes_value(true)
+                .global(true)
+                .help("profile of the config file to use"),
+        )
+        .arg(
+            Arg::with_name("config-file")
+                .long("config-file")
+                .env("KRAKEN_CONFIG")
+                .takes_value(true)
+                .global(true)
+                .help("path of the profiles file [default: ~/.config/kraken/config.toml]"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .global(true)
+                .possible_values(FORMATS)
+                .help("json by default, table for portfolio and pnl"),
+        )
+        .arg(
+            Arg::with_name("fields")
+                .long("fields")
+                .takes_value(true)
+                .global(true)
+                .use_delimiter(true)
+                .help("comma separated columns to output"),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .takes_value(true)
+                .global(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("keep rows matching field(=|!=|<|<=|>|>=|~)value, can be repeated"),
+        )
+        .arg(
+            Arg::with_name("sort")
+                .long("sort")
+                .takes_value(true)
+                .global(true)
+                .help("sort rows by a field, descending when prefixed with -"),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .takes_value(true)
+                .global(true)
+                .help("re-run the subcommand every <watch> seconds and only print changes"),
+        )
+        .arg(
+            Arg::with_name("exit-on")
+                .long("exit-on")
+                .takes_value(true)
+                .global(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("exit with an error once a row matches the filter, can be repeated"),
+        )
         .subcommand(SubCommand::with_name("time").about("Get the server's time.").display_order(1))
         .subcommand(SubCommand::with_name("system-status").about("Get the current system status or trading mode.").display_order(1))
         .subcommand(
@@ -151,7 +812,7 @@ async fn main() -> Result<(), anyhow::Error> {
                 Arg::with_name("pair")
                     .long("pair")
                     .takes_value(true)
-                    .required(true),
+                    .help("defaults to the pair of the profile"),
             )
             .about("Today's prices start at midnight UTC.")
             .display_order(1),
@@ -162,7 +823,7 @@ async fn main() -> Result<(), anyhow::Error> {
                     Arg::with_name("pair")
                         .long("pair")
                         .takes_value(true)
-                        .required(true),
+                        .help("defaults to the pair of the profile"),
                 )
                 .arg(
                     Arg::with_name("interval")
@@ -184,7 +845,7 @@ async fn main() -> Result<(), anyhow::Error> {
                     Arg::with_name("pair")
                         .long("pair")
                         .takes_value(true)
-                        .required(true),
+                        .help("defaults to the pair of the profile"),
                 )
                 .arg(Arg::with_name("count").long("count").takes_value(true))
                 .about("Get Order book.")
@@ -196,7 +857,7 @@ async fn main() -> Result<(), anyhow::Error> {
                     Arg::with_name("pair")
                         .long("pair")
                         .takes_value(true)
-                        .required(true),
+                        .help("defaults to the pair of the profile"),
                 )
                 .arg(Arg::with_name("count").long("count").takes_value(true))
                 .about("Get recent trades.")
@@ -208,14 +869,269 @@ async fn main() -> Result<(), anyhow::Error> {
                     Arg::with_name("pair")
                         .long("pair")
                         .takes_value(true)
-                        .required(true),
+                        .help("defaults to the pair of the profile"),
                 )
                 .arg(Arg::with_name("count").long("count").takes_value(true))
                 .about("Get recent spreads.")
                 .display_order(1),
         )
+        .subcommand(
+            SubCommand::with_name("backfill")
+                .arg(
+                    Arg::with_name("pair")
+                        .long("pair")
+                        .takes_value(true)
+                        .help("defaults to the pair of the profile"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .help("YYYY-MM-DD or unix timestamp to start from, refused when resuming"),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("directory where trades, cursor and candles are stored"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .multiple(true)
+                        .help("build candles of the given interval (minutes) from stored trades"),
+                )
+                .arg(
+                    Arg::with_name("delay")
+                        .long("delay")
+                        .takes_value(true)
+                        .default_value("1500")
+                        .help("delay between requests in milliseconds"),
+                )
+                .about("Download trade history to disk and build candles from it. Resumes where it stopped.")
+                .display_order(1),
+        )
+        .subcommand(
+            SubCommand::with_name("fee-estimate")
+                .arg(
+                    Arg::with_name("pair")
+                        .long("pair")
+                        .takes_value(true)
+                        .help("defaults to the pair of the profile"),
+                )
+                .arg(
+                    Arg::with_name("notional")
+                        .long("notional")
+                        .takes_value(true)
+                        .required(true)
+                        .help("order value in the quote currency"),
+                )
+                .arg(
+                    Arg::with_name("liquidity")
+                        .long("liquidity")
+                        .takes_value(true)
+                        .default_value("taker")
+                        .possible_values(&["maker", "taker"]),
+                )
+                .arg(
+                    Arg::with_name("volume")
+                        .long("volume")
+                        .takes_value(true)
+                        .help("30-day volume, fetched with trade-volume when credentials are set"),
+                )
+                .about("Estimate the fee of an order and the volume needed to reach the next tier.")
+                .display_order(1),
+        )
+        .subcommand(
+            SubCommand::with_name("credentials")
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .arg(
+                            Arg::with_name("name")
+                                .long("name")
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(Arg::with_name("force").long("force").help("replace an existing entry"))
+                        .about("Encrypt a key and secret read from the terminal."),
+                )
+                .subcommand(SubCommand::with_name("list").about("List stored credentials."))
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .arg(
+                            Arg::with_name("name")
+                                .long("name")
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .about("Remove a stored credential."),
+                )
+                .subcommand(
+                    SubCommand::with_name("rotate")
+                        .about("Encrypt all stored credentials with a new passphrase."),
+                )
+                .about("Manage the encrypted credential store.")
+                .display_order(1),
+        )
+        .subcommand(
+            SubCommand::with_name("whoami")
+                .about("Detect the permissions of the key and compare them with the profile.")
+                .display_order(1),
+        )
+        .subcommand(
+            SubCommand::with_name("paper")
+                .arg(
+                    Arg::with_name("balance")
+                        .long("balance")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true)
+                        .help("<asset>=<amount> credited to the simulated account, can be repeated"),
+                )
+                .about("Reset the paper trading state file given with --paper.")
+                .display_order(1),
+        )
+        .subcommand(
+            SubCommand::with_name("profiles")
+                .about("List the profiles of the config file, without their secrets.")
+                .display_order(1),
+        )
+        .subcommand(
+            SubCommand::with_name("alert")
+                .arg(
+                    Arg::with_name("config")
+                        .long("config")
+                        .takes_value(true)
+                        .required(true)
+                        .help("TOML file of alert rules"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .help("seconds between evaluations, overrides the config [default: 60]"),
+                )
+                .arg(
+                    Arg::with_name("once")
+                        .long("once")
+                        .help("evaluate the rules once and exit"),
+                )
+                .about("Evaluate price, spread, balance and order rules and run their actions.")
+                .display_order(1),
+        )
+        .subcommand(
+            SubCommand::with_name("tui")
+                .arg(
+                    Arg::with_name("pair")
+                        .long("pair")
+                        .takes_value(true)
+                        .help("defaults to the pair of the profile"),
+                )
+                .arg(
+                    Arg::with_name("refresh")
+                        .long("refresh")
+                        .takes_value(true)
+                        .default_value("2")
+                        .help("seconds between refreshes"),
+                )
+                .about("Live ticker, order book, trades and, with credentials, balances and open orders of a pair.")
+                .display_order(1),
+        )
         .subcommand(SubCommand::with_name("balance").about("(private) Retrieve all cash balances, net of pending withdrawals."))
         .subcommand(SubCommand::with_name("balance-ex").about("(private) Retrieve all cash balances, net of pending withdrawals and hold trades."))
+        .subcommand(
+            SubCommand::with_name("pnl")
+                .arg(
+                    Arg::with_name("method")
+                        .long("method")
+                        .takes_value(true)
+                        .default_value("fifo")
+                        .possible_values(&["fifo", "lifo", "average"]),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .help("YYYY-MM-DD or unix timestamp"),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .takes_value(true)
+                        .help("YYYY-MM-DD or unix timestamp"),
+                )
+                .about("(private) Compute realized and unrealized profit and loss per pair from trades history."),
+        )
+        .subcommand(
+            SubCommand::with_name("portfolio")
+                .arg(
+                    Arg::with_name("quote")
+                        .long("quote")
+                        .takes_value(true)
+                        .default_value("USD"),
+                )
+                .arg(
+                    Arg::with_name("exclude-hold")
+                        .long("exclude-hold")
+                        .help("leave out amounts held by open orders"),
+                )
+                .about("(private) Value all balances in a quote currency."),
+        )
+        .subcommand(
+            SubCommand::with_name("reconcile")
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .help("YYYY-MM-DD or unix timestamp, disables the comparison with current balances"),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .takes_value(true)
+                        .help("YYYY-MM-DD or unix timestamp, disables the comparison with current balances"),
+                )
+                .about("(private) Replay ledger entries and check them against current balances."),
+        )
+        .subcommand(
+            SubCommand::with_name("tax-report")
+                .arg(
+                    Arg::with_name("method")
+                        .long("method")
+                        .takes_value(true)
+                        .default_value("fifo")
+                        .possible_values(&["fifo", "same-day"]),
+                )
+                .arg(
+                    Arg::with_name("currency")
+                        .long("currency")
+                        .takes_value(true)
+                        .default_value("USD")
+                        .help("reporting currency"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .help("YYYY-MM-DD or unix timestamp"),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .takes_value(true)
+                        .help("YYYY-MM-DD or unix timestamp"),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .takes_value(true)
+                        .help("CSV file to write, stdout by default"),
+                )
+                .about("(private) Build a capital gains CSV report from ledger entries, priced with public trades."),
+        )
         .subcommand(
             SubCommand::with_name("trade-balance")
                 .arg(Arg::with_name("asset").long("asset").takes_value(true))
@@ -358,29 +1274,253 @@ async fn main() -> Result<(), anyhow::Error> {
                 )
                 .arg(Arg::with_name("fee-info").long("fee-info").hidden(false))
                 .about("(private)"),
+        )
+        .subcommand(order_subcommand("buy", "(private) Place a buy order."))
+        .subcommand(order_subcommand("sell", "(private) Place a sell order."))
+        .subcommand(
+            SubCommand::with_name("twap")
+                .arg(
+                    Arg::with_name("pair")
+                        .long("pair")
+                        .takes_value(true)
+                        .help("defaults to the pair of the profile"),
+                )
+                .arg(
+                    Arg::with_name("type")
+                        .long("type")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["buy", "sell"]),
+                )
+                .arg(
+                    Arg::with_name("volume")
+                        .long("volume")
+                        .takes_value(true)
+                        .required(true)
+                        .help("total volume in the base currency"),
+                )
+                .arg(
+                    Arg::with_name("duration")
+                        .long("duration")
+                        .takes_value(true)
+                        .help("spread the volume over 90s, 30m, 2h..., at once when missing"),
+                )
+                .arg(
+                    Arg::with_name("slices")
+                        .long("slices")
+                        .takes_value(true)
+                        .conflicts_with("vwap")
+                        .help("number of equal slices [default: one per minute]"),
+                )
+                .arg(
+                    Arg::with_name("vwap")
+                        .long("vwap")
+                        .takes_value(true)
+                        .requires("duration")
+                        .help("slice by the volume traded at the same time of day in candles of this interval (minutes)"),
+                )
+                .arg(
+                    Arg::with_name("visible")
+                        .long("visible")
+                        .takes_value(true)
+                        .help("largest child order, hiding the rest of the volume"),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .long("limit")
+                        .takes_value(true)
+                        .help("worst price of the child orders"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("seconds between two checks of the book"),
+                )
+                .arg(Arg::with_name("yes").long("yes").short("y").help("do not ask for confirmation"))
+                .about("(private) Execute an order as child limit orders over time, following the book."),
+        )
+        .subcommand(
+            SubCommand::with_name("cancel")
+                .arg(
+                    Arg::with_name("txid")
+                        .long("txid")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(Arg::with_name("yes").long("yes").short("y").help("do not ask for confirmation"))
+                .about("(private) Cancel an open order."),
+        )
+        .subcommand(
+            SubCommand::with_name("cancel-all")
+                .arg(Arg::with_name("yes").long("yes").short("y").help("do not ask for confirmation"))
+                .about("(private) Cancel all open orders."),
+        )
+        .subcommand(
+            SubCommand::with_name("edit")
+                .arg(
+                    Arg::with_name("txid")
+                        .long("txid")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(Arg::with_name("volume").long("volume").takes_value(true))
+                .arg(Arg::with_name("price").long("price").takes_value(true))
+                .arg(Arg::with_name("price2").long("price2").takes_value(true))
+                .arg(Arg::with_name("userref").long("userref").takes_value(true))
+                .arg(
+                    Arg::with_name("validate")
+                        .long("validate")
+                        .help("only let the exchange validate the changes"),
+                )
+                .arg(Arg::with_name("yes").long("yes").short("y").help("do not ask for confirmation"))
+                .about("(private) Replace an open order with a new volume or price."),
         );
 
     let mut help = app.clone();
     let matches = &app.get_matches();
-    let cred = build_credentials(
-        matches.value_of("key"),
-        matches.value_of("secret"),
-        matches.value_of("credentials"),
-    )
-    .await?;
+    let command = matches.subcommand_name().unwrap_or("");
+    let config = Config::load(global_value(matches, "config-file"))?;
+    let profile_name = global_value(matches, "profile");
+    let from_args = credentials_from_args(matches)?;
+    // the settings of a profile only apply to its own credentials.
+    let (name, profile) = match config.profile(profile_name)? {
+        Some((name, profile)) if !from_args => (name, profile.clone()),
+        _ => ("", Profile::default()),
+    };
+    let paper = global_value(matches, "paper").or(profile.paper.as_deref());
+    // the other private subcommands would reach the real account, as
+    // would the balances and orders shown by tui.
+    let private = !required_permissions(command).is_empty() || command == "tui";
+    if paper.is_some() && !PAPER.contains(&command) && private {
+        return Err(anyhow!("{} is not available with --paper", command));
+    }
+    // paper trading never touches the account.
+    if paper.is_none() || !PAPER.contains(&command) {
+        profile.check_permission(name, command)?;
+    }
+    if let Some(base_url) = &profile.base_url {
+        kraken::set_base_url(base_url);
+    }
+    if let Some(dir) = global_value(matches, "record") {
+        kraken::set_transport(Recorder::new(dir));
+    }
+    if let Some(dir) = global_value(matches, "replay") {
+        kraken::set_transport(Replay::load(dir).map_err(pretty_error)?);
+    }
+    let (agent, stored, key, secret, cred_file) = if from_args {
+        (
+            global_value(matches, "agent"),
+            global_value(matches, "stored"),
+            global_value(matches, "key"),
+            global_value(matches, "secret"),
+            global_value(matches, "credentials"),
+        )
+    } else {
+        (
+            profile.agent.as_deref(),
+            profile.stored.as_deref(),
+            profile.key.as_deref(),
+            profile.secret.as_deref(),
+            profile.credentials.as_deref(),
+        )
+    };
+    let cred = match (agent, stored) {
+        (Some(socket), _) if command != "credentials" => Some(agent_credentials(socket).await?),
+        // the credentials subcommand unlocks the store itself.
+        (_, Some(name)) if command != "credentials" => {
+            let path = store_path(global_value(matches, "store-file"))?;
+            Some(load_stored_credentials(&path, name)?)
+        }
+        _ => build_credentials(key, secret, cred_file).await?,
+    };
+    // requests needing a permission the profile does not declare are
+    // refused by the library too.
+    let cred = if profile.permissions.is_empty() {
+        cred
+    } else {
+        cred.map(|c| c.with_permissions(Some(profile.permissions.clone())))
+    };
+    let format = match global_value(matches, "output").or(profile.output.as_deref()) {
+        Some(format) => format.parse()?,
+        None if command == "portfolio" || command == "pnl" => Format::Table,
+        None => Format::Json,
+    };
+    if format == Format::Raw && (!RAW.contains(&command) || paper.is_some()) {
+        return Err(anyhow!("raw output is only available for commands calling a single endpoint"));
+    }
+    let mut filters = global_values(matches, "filter")
+        .iter()
+        .map(|f| f.parse())
+        .collect::<Result<Vec<Filter>>>()?;
+    let mut exit_on = global_values(matches, "exit-on")
+        .iter()
+        .map(|f| f.parse())
+        .collect::<Result<Vec<Filter>>>()?;
+    if filters.iter().chain(exit_on.iter()).any(|f| f.needs_registry()) {
+        let registry = AssetRegistry::shared().await.map_err(pretty_error)?;
+        filters = filters.into_iter().map(|f| f.with_registry(registry)).collect();
+        exit_on = exit_on.into_iter().map(|f| f.with_registry(registry)).collect();
+    }
+    let selection = Selection {
+        fields: global_values(matches, "fields"),
+        filters,
+        sort: global_value(matches, "sort").map(|s| s.to_string()),
+    };
+    let watch: Option<u64> = parse_number_option(global_value(matches, "watch"))?;
+    let output = Output::new(format, command, selection)
+        .watch(watch.is_some())
+        .exit_on(exit_on);
+    let interval = match watch {
+        Some(interval) => Duration::from_secs(interval),
+        None => {
+            run(matches, cred, &profile, &config, &output, &mut help).await?;
+            if output.condition_met() {
+                return Err(anyhow!("exit condition met"));
+            }
+            return Ok(());
+        }
+    };
+    if UNWATCHABLE.contains(&command) {
+        return Err(anyhow!("{} cannot be watched", command));
+    }
+    loop {
+        // failures are reported and the command is run again.
+        if let Err(e) = run(matches, cred.clone(), &profile, &config, &output, &mut help).await {
+            eprintln!("Error: {}", e);
+        }
+        if output.condition_met() {
+            return Err(anyhow!("exit condition met"));
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Runs the selected subcommand once.
+async fn run(
+    matches: &ArgMatches<'_>,
+    cred: Option<kraken::Credential>,
+    profile: &Profile,
+    config: &Config,
+    output: &Output,
+    help: &mut App<'_, '_>,
+) -> Result<()> {
+    let command = matches.subcommand_name().unwrap_or("");
+    if let Some(path) = global_value(matches, "paper").or(profile.paper.as_deref()) {
+        if PAPER.contains(&command) {
+            return paper_command(matches, Path::new(path), profile, output).await;
+        }
+    }
     match matches.subcommand_name() {
-        Some("time") => display(kraken::public::time().await.map_err(pretty_error)?),
-        Some("system-status") => display(kraken::public::time().await.map_err(pretty_error)?),
+        Some("time") => output.display(kraken::public::time().await.map_err(pretty_error)?),
+        Some("system-status") => output.display(kraken::public::time().await.map_err(pretty_error)?),
         Some("assets") => {
             let cmd = matches.subcommand_matches("assets").unwrap();
-            let asset: Option<Vec<&str>> = if let Some(items) = cmd.values_of("asset") {
-                Some(items.map(|v| v).collect())
-            } else {
-                None
-            };
+            let asset: Option<Vec<&str>> = cmd.values_of("asset").map(|items| items.collect());
 
             let aclass = cmd.value_of("aclass");
-            display(
+            output.display(
                 kraken::public::assets(asset.as_deref(), aclass)
                     .await
                     .map_err(pretty_error)?,
@@ -390,7 +1530,7 @@ async fn main() -> Result<(), anyhow::Error> {
             let cmd = matches.subcommand_matches("asset-pair").unwrap();
             let pair = cmd.values_of("pair").unwrap().collect::<Vec<&str>>();
             let info = cmd.value_of("info");
-            display(
+            output.display(
                 kraken::public::asset_pair(&pair, info)
                     .await
                     .map_err(pretty_error)?,
@@ -398,18 +1538,18 @@ async fn main() -> Result<(), anyhow::Error> {
         }
         Some("ticker") => {
             let cmd = matches.subcommand_matches("ticker").unwrap();
-            display(
-                kraken::public::ticker(cmd.value_of("pair").unwrap())
+            output.display(
+                kraken::public::ticker(pair_value(cmd, profile)?)
                     .await
                     .map_err(pretty_error)?,
             )
         }
         Some("ohlc") => {
             let cmd = matches.subcommand_matches("ohlc").unwrap();
-            let pair = cmd.value_of("pair").unwrap();
+            let pair = pair_value(cmd, profile)?;
             let interval = parse_number_option(cmd.value_of("interval"))?;
             let since = parse_number_option(cmd.value_of("since"))?;
-            display(
+            output.display(
                 kraken::public::ohcl(pair, interval, since)
                     .await
                     .map_err(pretty_error)?,
@@ -417,9 +1557,9 @@ async fn main() -> Result<(), anyhow::Error> {
         }
         Some("depth") => {
             let cmd = matches.subcommand_matches("depth").unwrap();
-            let pair = cmd.value_of("pair").unwrap();
+            let pair = pair_value(cmd, profile)?;
             let count = parse_number_option(cmd.value_of("count"))?;
-            display(
+            output.display(
                 kraken::public::depth(pair, count)
                     .await
                     .map_err(pretty_error)?,
@@ -427,9 +1567,9 @@ async fn main() -> Result<(), anyhow::Error> {
         }
         Some("trades") => {
             let cmd = matches.subcommand_matches("trades").unwrap();
-            let pair = cmd.value_of("pair").unwrap();
+            let pair = pair_value(cmd, profile)?;
             let count = parse_number_option(cmd.value_of("count"))?;
-            display(
+            output.display(
                 kraken::public::trades(pair, count)
                     .await
                     .map_err(pretty_error)?,
@@ -437,18 +1577,182 @@ async fn main() -> Result<(), anyhow::Error> {
         }
         Some("spread") => {
             let cmd = matches.subcommand_matches("spread").unwrap();
-            let pair = cmd.value_of("pair").unwrap();
+            let pair = pair_value(cmd, profile)?;
             let count = parse_number_option(cmd.value_of("count"))?;
-            display(
+            output.display(
                 kraken::public::spread(pair, count)
                     .await
                     .map_err(pretty_error)?,
             )
         }
+        Some("backfill") => {
+            let cmd = matches.subcommand_matches("backfill").unwrap();
+            let pair = pair_value(cmd, profile)?;
+            let out = Path::new(cmd.value_of("out").unwrap());
+            // the trades endpoint expects a nanosecond cursor.
+            let since = parse_time_option(cmd.value_of("since"))?.map(|s| s * 1_000_000_000);
+            let delay = parse_number_option(cmd.value_of("delay"))?.unwrap();
+            let stored = kraken::backfill::backfill_trades(
+                pair,
+                out,
+                since,
+                Duration::from_millis(delay),
+                |count, cursor| eprintln!("{} trades stored, cursor {}", count, cursor),
+            )
+            .await
+            .map_err(pretty_error)?;
+            eprintln!("{} new trades stored", stored);
+            if let Some(intervals) = cmd.values_of("interval") {
+                for interval in intervals {
+                    let interval = interval
+                        .parse::<u64>()
+                        .map_err(|_| anyhow!("invalid interval {}", interval))?;
+                    let count = kraken::backfill::write_candles(out, pair, interval)
+                        .map_err(pretty_error)?;
+                    eprintln!("{} candles of {} minutes written", count, interval);
+                }
+            }
+        }
+        Some("fee-estimate") => {
+            let cmd = matches.subcommand_matches("fee-estimate").unwrap();
+            let pair = pair_value(cmd, profile)?;
+            let notional: Decimal = parse_number_option(cmd.value_of("notional"))?.unwrap();
+            let volume: Option<Decimal> = parse_number_option(cmd.value_of("volume"))?;
+            let liquidity = match cmd.value_of("liquidity") {
+                Some("maker") => Liquidity::Maker,
+                _ => Liquidity::Taker,
+            };
+            let pairs = kraken::public::asset_pair(&[pair], None)
+                .await
+                .map_err(pretty_error)?;
+            let (pair_id, asset_pair) = pairs
+                .0
+                .iter()
+                .next()
+                .ok_or(anyhow!("unknown pair {}", pair))?;
+            let estimate = match (volume, cred) {
+                (Some(volume), _) => fees::estimate(asset_pair, liquidity, volume, notional),
+                (None, Some(cred)) => {
+                    let trade_volume = kraken::private::trade_volume(&cred, Some(&[pair]), Some(true))
+                        .await
+                        .map_err(pretty_error)?;
+                    fees::estimate_for_account(pair_id, asset_pair, liquidity, &trade_volume, notional)
+                }
+                (None, None) => fees::estimate(asset_pair, liquidity, Decimal::ZERO, notional),
+            };
+            output.display(estimate);
+        }
+        Some("credentials") => {
+            let cmd = matches.subcommand_matches("credentials").unwrap();
+            let path = store_path(global_value(matches, "store-file"))?;
+            credentials_command(cmd, &path, output)?;
+        }
+        Some("profiles") => {
+            let profiles: Vec<serde_json::Value> = config
+                .profiles
+                .iter()
+                .map(|(name, p)| {
+                    let credentials = match (&p.agent, &p.stored, &p.key, &p.credentials) {
+                        (Some(_), _, _, _) => "agent",
+                        (None, Some(_), _, _) => "stored",
+                        (None, None, Some(_), _) => "key",
+                        (None, None, None, Some(_)) => "file",
+                        (None, None, None, None) => "",
+                    };
+                    serde_json::json!({
+                        "name": name,
+                        "default": config.default_profile.as_deref() == Some(name),
+                        "credentials": credentials,
+                        "base_url": p.base_url,
+                        "output": p.output,
+                        "pair": p.pair,
+                        "paper": p.paper,
+                        "permissions": p.permissions.iter().map(|p| p.label()).collect::<Vec<_>>().join(","),
+                    })
+                })
+                .collect();
+            output.display(profiles);
+        }
+        Some("whoami") => {
+            let cred = cred.ok_or(anyhow!("missing credentials"))?;
+            let access = kraken::permissions::probe(&cred).await.map_err(pretty_error)?;
+            let rows: Vec<serde_json::Value> = access
+                .into_iter()
+                .map(|(permission, access)| {
+                    let declared = profile.permissions.contains(&permission);
+                    // least privilege: the key should not hold more than
+                    // the profile declares.
+                    let note = match (&access, declared, profile.permissions.is_empty()) {
+                        (_, _, true) => "",
+                        (Access::Granted, false, _) => "granted but not declared",
+                        (Access::Denied, true, _) => "declared but denied",
+                        _ => "",
+                    };
+                    let (access, message) = match access {
+                        Access::Granted => ("granted", String::new()),
+                        Access::Denied => ("denied", String::new()),
+                        Access::Unknown(message) => ("unknown", message),
+                    };
+                    serde_json::json!({
+                        "permission": permission.label(),
+                        "access": access,
+                        "declared": declared,
+                        "note": note,
+                        "message": message,
+                    })
+                })
+                .collect();
+            output.display(rows);
+        }
+        Some("alert") => {
+            let cmd = matches.subcommand_matches("alert").unwrap();
+            let path = cmd.value_of("config").unwrap();
+            let content = std::fs::read_to_string(path).map_err(|e| anyhow!("cannot open {} ({})", path, e))?;
+            let config = kraken::alerts::Config::from_toml(&content).map_err(pretty_error)?;
+            let interval = parse_number_option(cmd.value_of("interval"))?
+                .or(config.interval)
+                .unwrap_or(60);
+            let mut monitor = kraken::alerts::Monitor::new(config.rules);
+            loop {
+                // a failing round is reported and retried at the next interval.
+                match monitor.observe(cred.as_ref()).await {
+                    Ok(mut observations) => {
+                        for (rules, e) in std::mem::take(&mut observations.errors) {
+                            eprintln!("{}: {}", rules, pretty_error(e));
+                        }
+                        for (idx, alert) in monitor.evaluate(&observations) {
+                            let actions = match monitor.rules()[idx].actions.as_slice() {
+                                [] => &[kraken::alerts::Action::Log][..],
+                                actions => actions,
+                            };
+                            for action in actions {
+                                if let Err(e) = kraken::alerts::fire(action, &alert).await {
+                                    eprintln!("{}: {}", alert.rule, pretty_error(e));
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => eprintln!("{}", pretty_error(e)),
+                }
+                if cmd.is_present("once") {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_secs(interval)).await;
+            }
+        }
+        Some("tui") => {
+            let cmd = matches.subcommand_matches("tui").unwrap();
+            let registry = AssetRegistry::shared().await.map_err(pretty_error)?;
+            let pair = pair_value(cmd, profile)?;
+            let pair_id = registry.pair_id(pair).ok_or(anyhow!("unknown pair {}", pair))?;
+            let altname = &registry.pair(pair_id).unwrap().altname;
+            let refresh: u64 = parse_number_option(cmd.value_of("refresh"))?.unwrap();
+            tui::run(pair_id, altname, cred, Duration::from_secs(refresh)).await?;
+        }
         // private endpoints
         Some("balance") => {
             let cred = cred.ok_or(anyhow!("missing credentials"))?;
-            display(
+            output.display(
                 kraken::private::balance(&cred)
                     .await
                     .map_err(pretty_error)?,
@@ -456,16 +1760,152 @@ async fn main() -> Result<(), anyhow::Error> {
         }
         Some("balance-ex") => {
             let cred = cred.ok_or(anyhow!("missing credentials"))?;
-            display(
+            output.display(
                 kraken::private::balance_ex(&cred)
                     .await
                     .map_err(pretty_error)?,
             );
         }
+        Some("pnl") => {
+            let cmd = matches.subcommand_matches("pnl").unwrap();
+            let cred = cred.ok_or(anyhow!("missing credentials"))?;
+            let method: Method = cmd.value_of("method").unwrap().parse().map_err(|e| anyhow!("{}", e))?;
+            let start = parse_time_option(cmd.value_of("since"))?;
+            let end = parse_time_option(cmd.value_of("until"))?;
+            // earlier trades are needed for the cost of the lots sold after `since`.
+            let trades = kraken::private::trades_history_all(&cred, None, None, end)
+                .await
+                .map_err(pretty_error)?;
+            let mut engine = PnlEngine::new(method);
+            if let Some(start) = start {
+                engine = engine.with_since(start as f64);
+            }
+            engine.add_all(trades.values());
+            let open_pairs = engine.open_pairs();
+            let mut marks = HashMap::new();
+            if !open_pairs.is_empty() {
+                let tickers = kraken::public::ticker(&open_pairs.join(","))
+                    .await
+                    .map_err(pretty_error)?;
+                for (pair, info) in tickers.0 {
+                    marks.insert(pair, info.c.0);
+                }
+            }
+            let report = engine.report(&marks);
+            if !output.is_plain_table() {
+                output.display(report);
+                return Ok(());
+            }
+            let format = |val: Option<Decimal>| val.map(|v| v.round_dp(8).to_string()).unwrap_or_default();
+            let rows: Vec<Vec<String>> = report
+                .iter()
+                .map(|p| {
+                    vec![
+                        p.pair.clone(),
+                        p.trades.to_string(),
+                        p.fees.round_dp(8).to_string(),
+                        p.realized.round_dp(8).to_string(),
+                        p.position.to_string(),
+                        format(p.average_price),
+                        format(p.mark),
+                        format(p.unrealized),
+                    ]
+                })
+                .collect();
+            display_table(
+                &["PAIR", "TRADES", "FEES", "REALIZED", "POSITION", "AVG PRICE", "MARK", "UNREALIZED"],
+                &rows,
+            );
+        }
+        Some("portfolio") => {
+            let cmd = matches.subcommand_matches("portfolio").unwrap();
+            let cred = cred.ok_or(anyhow!("missing credentials"))?;
+            let quote = cmd.value_of("quote").unwrap();
+            let portfolio = kraken::portfolio::valuate(&cred, quote, cmd.is_present("exclude-hold"))
+                .await
+                .map_err(pretty_error)?;
+            if !output.is_plain_table() {
+                output.display(portfolio);
+                return Ok(());
+            }
+            let format = |val: Option<Decimal>| val.map(|v| v.round_dp(8).to_string()).unwrap_or_default();
+            let rows: Vec<Vec<String>> = portfolio
+                .holdings
+                .iter()
+                .map(|h| {
+                    vec![
+                        h.asset.clone(),
+                        h.amount.to_string(),
+                        format(h.price),
+                        format(h.value.map(|v| v.round_dp(2))),
+                        h.route.join(">"),
+                    ]
+                })
+                .collect();
+            display_table(&["ASSET", "AMOUNT", "PRICE", "VALUE", "ROUTE"], &rows);
+            println!();
+            println!("TOTAL {} {}", portfolio.total.round_dp(2), portfolio.quote);
+            if !portfolio.unpriced.is_empty() {
+                println!("UNPRICED {}", portfolio.unpriced.join(", "));
+            }
+        }
+        Some("reconcile") => {
+            let cmd = matches.subcommand_matches("reconcile").unwrap();
+            let cred = cred.ok_or(anyhow!("missing credentials"))?;
+            let start = parse_time_option(cmd.value_of("since"))?;
+            let end = parse_time_option(cmd.value_of("until"))?;
+            let ledgers = kraken::private::ledgers_all(&cred, None, None, start, end)
+                .await
+                .map_err(pretty_error)?;
+            // assets without entries in the range would be reported as mismatches.
+            let balances = match (start, end) {
+                (None, None) => Some(kraken::private::balance(&cred).await.map_err(pretty_error)?),
+                _ => None,
+            };
+            let reconciliation = kraken::reconcile::reconcile(&ledgers, balances.as_ref());
+            let issues = reconciliation.issues.len();
+            output.display(reconciliation);
+            if issues > 0 {
+                return Err(anyhow!("{} issues found", issues));
+            }
+        }
+        Some("tax-report") => {
+            let cmd = matches.subcommand_matches("tax-report").unwrap();
+            let cred = cred.ok_or(anyhow!("missing credentials"))?;
+            let registry = AssetRegistry::shared().await.map_err(pretty_error)?;
+            let currency = cmd.value_of("currency").unwrap();
+            let currency = registry
+                .asset_id(currency)
+                .ok_or(anyhow!("unknown currency {}", currency))?;
+            let start = parse_time_option(cmd.value_of("since"))?;
+            let end = parse_time_option(cmd.value_of("until"))?;
+            // earlier acquisitions are needed for the cost basis of the disposals after `since`.
+            let ledgers = kraken::private::ledgers_all(&cred, None, None, None, end)
+                .await
+                .map_err(pretty_error)?;
+            let prices = tax::MarketPrices::fetch(registry, &ledgers, currency, TAX_PRICES_DELAY)
+                .await
+                .map_err(pretty_error)?;
+            let events = tax::events(&ledgers, currency, &prices);
+            let mut disposals = match cmd.value_of("method") {
+                Some("same-day") => tax::disposals(&events, &tax::SameDay),
+                _ => tax::disposals(&events, &tax::Fifo),
+            };
+            if let Some(start) = start {
+                disposals.retain(|d| d.time >= start as f64);
+            }
+            match cmd.value_of("out") {
+                Some(path) => {
+                    let file = std::fs::File::create(path).map_err(|e| anyhow!("cannot create {} ({})", path, e))?;
+                    tax::write_csv(&disposals, file).map_err(pretty_error)?;
+                }
+                None => tax::write_csv(&disposals, std::io::stdout()).map_err(pretty_error)?,
+            }
+        }
         Some("trade-balance") => {
             let cmd = matches.subcommand_matches("trade-balance").unwrap();
             let cred = cred.ok_or(anyhow!("missing credentials"))?;
-            display(
+            output.display(
                 kraken::private::trade_balance(&cred, cmd.value_of("asset"))
                     .await
                     .map_err(pretty_error)?,
@@ -476,7 +1916,7 @@ async fn main() -> Result<(), anyhow::Error> {
             let cred = cred.ok_or(anyhow!("missing credentials"))?;
             let trades = Some(cmd.is_present("trades"));
             let userref = parse_number_option(cmd.value_of("userref"))?;
-            display(
+            output.display(
                 kraken::private::open_orders(&cred, trades, userref)
                     .await
                     .map_err(pretty_error)?,
@@ -491,7 +1931,7 @@ async fn main() -> Result<(), anyhow::Error> {
             let end = parse_number_option(cmd.value_of("end"))?;
             let ofs = parse_number_option(cmd.value_of("ofs"))?;
             let closetime = cmd.value_of("closetime");
-            display(
+            output.display(
                 kraken::private::closed_orders(&cred, trades, userref, start, end, ofs, closetime)
                     .await
                     .map_err(pretty_error)?,
@@ -502,8 +1942,8 @@ async fn main() -> Result<(), anyhow::Error> {
             let cred = cred.ok_or(anyhow!("missing credentials"))?;
             let trades = Some(cmd.is_present("trades"));
             let userref = parse_number_option(cmd.value_of("userref"))?;
-            let txid: Vec<&str> = cmd.values_of("txid").unwrap().map(|f| f).collect();
-            display(
+            let txid: Vec<&str> = cmd.values_of("txid").unwrap().collect();
+            output.display(
                 kraken::private::query_orders(&cred, trades, userref, &txid)
                     .await
                     .map_err(pretty_error)?,
@@ -517,7 +1957,7 @@ async fn main() -> Result<(), anyhow::Error> {
             let start = parse_number_option(cmd.value_of("start"))?;
             let end = parse_number_option(cmd.value_of("end"))?;
             let ofs = parse_number_option(cmd.value_of("ofs"))?;
-            display(
+            output.display(
                 kraken::private::trades_history(&cred, type_, trades, start, end, ofs)
                     .await
                     .map_err(pretty_error)?,
@@ -527,8 +1967,8 @@ async fn main() -> Result<(), anyhow::Error> {
             let cmd = matches.subcommand_matches("query-trades").unwrap();
             let cred = cred.ok_or(anyhow!("missing credentials"))?;
             let trades = Some(cmd.is_present("trades"));
-            let txid: Vec<&str> = cmd.values_of("txid").unwrap().map(|f| f).collect();
-            display(
+            let txid: Vec<&str> = cmd.values_of("txid").unwrap().collect();
+            output.display(
                 kraken::private::query_trades(&cred, &txid, trades)
                     .await
                     .map_err(pretty_error)?,
@@ -537,10 +1977,10 @@ async fn main() -> Result<(), anyhow::Error> {
         Some("open-positions") => {
             let cmd = matches.subcommand_matches("open-positions").unwrap();
             let cred = cred.ok_or(anyhow!("missing credentials"))?;
-            let txid: Vec<&str> = cmd.values_of("txid").unwrap().map(|f| f).collect();
+            let txid: Vec<&str> = cmd.values_of("txid").unwrap().collect();
             let docalcs = Some(cmd.is_present("docalcs"));
             let consolidation = cmd.value_of("consolidation").unwrap();
-            display(
+            output.display(
                 kraken::private::open_positions(&cred, &txid, docalcs, consolidation)
                     .await
                     .map_err(pretty_error)?,
@@ -551,13 +1991,13 @@ async fn main() -> Result<(), anyhow::Error> {
             let cred = cred.ok_or(anyhow!("missing credentials"))?;
             let asset: Option<Vec<&str>> = cmd
                 .values_of("asset")
-                .map(|f| f.into_iter().map(|v| v).collect());
+                .map(|f| f.into_iter().collect());
             let aclass = cmd.value_of("aclass");
             let type_ = cmd.value_of("type");
             let start = parse_number_option(cmd.value_of("start"))?;
             let end = parse_number_option(cmd.value_of("end"))?;
             let ofs = parse_number_option(cmd.value_of("ofs"))?;
-            display(
+            output.display(
                 kraken::private::ledgers(&cred, asset.as_deref(), aclass, type_, start, end, ofs)
                     .await
                     .map_err(pretty_error)?,
@@ -566,9 +2006,9 @@ async fn main() -> Result<(), anyhow::Error> {
         Some("query-ledgers") => {
             let cmd = matches.subcommand_matches("query-ledgers").unwrap();
             let cred = cred.ok_or(anyhow!("missing credentials"))?;
-            let id: Vec<&str> = cmd.values_of("id").unwrap().map(|f| f).collect();
+            let id: Vec<&str> = cmd.values_of("id").unwrap().collect();
             let trades = Some(cmd.is_present("trades"));
-            display(
+            output.display(
                 kraken::private::query_ledgers(&cred, &id, trades)
                     .await
                     .map_err(pretty_error)?,
@@ -579,21 +2019,157 @@ async fn main() -> Result<(), anyhow::Error> {
             let cred = cred.ok_or(anyhow!("missing credentials"))?;
             let pair: Option<Vec<&str>> = cmd
                 .values_of("pair")
-                .map(|f| f.into_iter().map(|v| v).collect());
+                .map(|f| f.into_iter().collect());
             let fee_info = Some(cmd.is_present("fee-info"));
-            display(
+            output.display(
                 kraken::private::trade_volume(&cred, pair.as_deref(), fee_info)
                     .await
                     .map_err(pretty_error)?,
             );
         }
+        Some(side @ ("buy" | "sell")) => {
+            let cmd = matches.subcommand_matches(side).unwrap();
+            let cred = cred.ok_or(anyhow!("missing credentials"))?;
+            let registry = AssetRegistry::shared().await.map_err(pretty_error)?;
+            let order = new_order(cmd, side, registry, profile)?;
+            let validate = cmd.is_present("validate");
+            if !validate {
+                let mut summary = vec![
+                    ("order", format!("{} {} {} {}", side, order.volume, order.pair, order.ordertype)),
+                ];
+                if let Some(price) = order.price {
+                    summary.push(("price", price.to_string()));
+                }
+                if let Some(price2) = order.price2 {
+                    summary.push(("price2", price2.to_string()));
+                }
+                summary.extend(order_estimate(&cred, registry, &order).await?);
+                if let Some(leverage) = order.leverage {
+                    summary.push(("leverage", leverage.to_string()));
+                }
+                if let Some(oflags) = &order.oflags {
+                    summary.push(("flags", oflags.clone()));
+                }
+                confirm(&summary, cmd.is_present("yes"))?;
+            }
+            output.display(
+                kraken::private::add_order(&cred, &order, validate)
+                    .await
+                    .map_err(pretty_error)?,
+            );
+        }
+        Some("cancel") => {
+            let cmd = matches.subcommand_matches("cancel").unwrap();
+            let cred = cred.ok_or(anyhow!("missing credentials"))?;
+            let txid = cmd.value_of("txid").unwrap();
+            let mut summary = vec![("cancel", txid.to_string())];
+            // keys allowed to cancel but not to query orders only see the txid.
+            match kraken::private::query_orders(&cred, None, None, &[txid]).await {
+                Ok(orders) => {
+                    let order = orders.0.get(txid).ok_or(anyhow!("unknown order {}", txid))?;
+                    summary.push(("order", order.descr.order.clone()));
+                    summary.push(("status", order.status.clone()));
+                    summary.push(("executed", format!("{} / {}", order.vol_exec, order.vol)));
+                }
+                Err(e) if is_denied(&e) => {}
+                Err(e) => return Err(pretty_error(e)),
+            }
+            confirm(&summary, cmd.is_present("yes"))?;
+            output.display(
+                kraken::private::cancel_order(&cred, txid)
+                    .await
+                    .map_err(pretty_error)?,
+            );
+        }
+        Some("twap") => {
+            let cmd = matches.subcommand_matches("twap").unwrap();
+            let cred = cred.ok_or(anyhow!("missing credentials"))?;
+            let registry = AssetRegistry::shared().await.map_err(pretty_error)?;
+            twap_command(cmd, &Live::new(cred), registry, profile, output).await?;
+        }
+        Some("cancel-all") => {
+            let cmd = matches.subcommand_matches("cancel-all").unwrap();
+            let cred = cred.ok_or(anyhow!("missing credentials"))?;
+            let summary = match kraken::private::open_orders(&cred, None, None).await {
+                Ok(orders) => {
+                    let mut summary = vec![("cancel", format!("{} open orders", orders.open.len()))];
+                    let mut open: Vec<_> = orders.open.iter().collect();
+                    open.sort_by(|a, b| a.1.opentm.total_cmp(&b.1.opentm));
+                    for (txid, order) in open {
+                        summary.push(("order", format!("{} {}", txid, order.descr.order)));
+                    }
+                    summary
+                }
+                // keys allowed to cancel but not to query orders.
+                Err(e) if is_denied(&e) => vec![("cancel", "all open orders".to_string())],
+                Err(e) => return Err(pretty_error(e)),
+            };
+            confirm(&summary, cmd.is_present("yes"))?;
+            output.display(kraken::private::cancel_all(&cred).await.map_err(pretty_error)?);
+        }
+        Some("edit") => {
+            let cmd = matches.subcommand_matches("edit").unwrap();
+            let cred = cred.ok_or(anyhow!("missing credentials"))?;
+            let registry = AssetRegistry::shared().await.map_err(pretty_error)?;
+            let txid = cmd.value_of("txid").unwrap();
+            let edit = OrderEdit {
+                volume: parse_number_option(cmd.value_of("volume"))?,
+                price: parse_number_option(cmd.value_of("price"))?,
+                price2: parse_number_option(cmd.value_of("price2"))?,
+                oflags: None,
+                userref: parse_number_option(cmd.value_of("userref"))?,
+            };
+            let orders = kraken::private::query_orders(&cred, None, None, &[txid])
+                .await
+                .map_err(pretty_error)?;
+            let current = orders.0.get(txid).ok_or(anyhow!("unknown order {}", txid))?;
+            let descr = &current.descr;
+            let pair_id = registry
+                .pair_id(&descr.pair)
+                .ok_or(anyhow!("unknown pair {}", descr.pair))?;
+            // the order as it will be once edited, checked like a new one.
+            let order = NewOrder {
+                pair: pair_id.to_string(),
+                type_: descr.type_.clone(),
+                ordertype: descr.ordertype.clone(),
+                volume: edit.volume.unwrap_or(current.vol),
+                // market orders report a zero price, only check the price being edited.
+                price: edit.price.or((!descr.price.is_zero()).then_some(descr.price)),
+                price2: edit.price2.or((!descr.price2.is_zero()).then_some(descr.price2)),
+                ..Default::default()
+            };
+            Validator::new(registry.pair(pair_id).unwrap())
+                .validate(&order)
+                .map_err(|e| anyhow!("{}", e))?;
+            let validate = cmd.is_present("validate");
+            if !validate {
+                let mut summary = vec![("edit", txid.to_string()), ("order", descr.order.clone())];
+                if let Some(volume) = edit.volume {
+                    summary.push(("volume", format!("{} -> {}", current.vol, volume)));
+                }
+                if let Some(price) = edit.price {
+                    summary.push(("price", format!("{} -> {}", descr.price, price)));
+                }
+                if let Some(price2) = edit.price2 {
+                    summary.push(("price2", format!("{} -> {}", descr.price2, price2)));
+                }
+                summary.extend(order_estimate(&cred, registry, &order).await?);
+                confirm(&summary, cmd.is_present("yes"))?;
+            }
+            output.display(
+                kraken::private::edit_order(&cred, txid, &descr.pair, &edit, validate)
+                    .await
+                    .map_err(pretty_error)?,
+            );
+        }
+        Some("paper") => return Err(anyhow!("missing --paper state file")),
         Some(&_) => {
             help.print_long_help()?;
-            println!("");
+            println!();
         }
         None => {
             help.print_long_help()?;
-            println!("");
+            println!();
         }
     }
 
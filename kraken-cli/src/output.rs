@@ -0,0 +1,768 @@
+use anyhow::{anyhow, Result};
+use kraken::registry::AssetRegistry;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Ndjson,
+    Csv,
+    Table,
+    Raw,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        return match s {
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            "table" => Ok(Format::Table),
+            "raw" => Ok(Format::Raw),
+            _ => Err(anyhow!("unknown output format {}", s)),
+        };
+    }
+}
+
+pub const FORMATS: &[&str] = &["json", "ndjson", "csv", "table", "raw"];
+
+/// Describes how a response is turned into rows.
+#[derive(Debug, Default)]
+pub struct Layout {
+    /// Column names given to the keys of nested maps, from the outermost
+    /// one. An empty name descends into the map without adding a column
+    /// (e.g. the `open` wrapper of open-orders).
+    pub keys: &'static [&'static str],
+    /// Column names of tuple rows such as order book levels.
+    pub columns: &'static [&'static str],
+    /// Maps of scalars produce one row per entry (e.g. balances).
+    pub scalar_rows: bool,
+}
+
+/// Returns the layout of the response of a subcommand.
+pub fn layout(command: &str) -> Layout {
+    let keys: &'static [&'static str] = match command {
+        "balance" | "balance-ex" | "assets" => &["asset"],
+        "asset-pair" | "ticker" | "ohlc" | "trades" | "spread" => &["pair"],
+        "depth" => &["pair", "side"],
+        "open-orders" | "closed-orders" | "trades-history" => &["", "txid"],
+        "query-orders" | "query-trades" | "open-positions" => &["txid"],
+        "ledgers" => &["", "id"],
+        "query-ledgers" => &["id"],
+        "trade-volume" => &["schedule", "pair"],
+        "portfolio" => &[""],
+        "reconcile" => &["section"],
+        _ => &[],
+    };
+    let columns: &'static [&'static str] = match command {
+        "ohlc" => &[
+            "time", "open", "high", "low", "close", "vwap", "volume", "count",
+        ],
+        "depth" => &["price", "volume", "timestamp"],
+        "trades" => &["price", "volume", "time", "side", "ordertype", "misc"],
+        "spread" => &["time", "bid", "ask"],
+        _ => &[],
+    };
+    return Layout {
+        keys,
+        columns,
+        scalar_rows: command == "balance",
+    };
+}
+
+pub type Row = Vec<(String, Value)>;
+
+fn is_scalar(value: &Value) -> bool {
+    return !matches!(value, Value::Object(_) | Value::Array(_));
+}
+
+/// A value holding records rather than being one: a map or a list of
+/// maps or tuples.
+fn is_collection(value: &Value) -> bool {
+    return match value {
+        Value::Object(map) => !map.is_empty(),
+        Value::Array(items) => items.iter().any(|item| !is_scalar(item)),
+        _ => false,
+    };
+}
+
+/// Flattens nested objects and arrays into dotted column names.
+fn flatten(value: &Value, prefix: &str, row: &mut Row) {
+    let name = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", prefix, key)
+        }
+    };
+    match value {
+        Value::Object(map) => {
+            for (key, val) in map {
+                flatten(val, &name(key), row);
+            }
+        }
+        Value::Array(items) => {
+            for (idx, val) in items.iter().enumerate() {
+                flatten(val, &name(&idx.to_string()), row);
+            }
+        }
+        _ => row.push((prefix.to_string(), value.clone())),
+    }
+}
+
+fn collect(value: &Value, depth: usize, prefix: &Row, layout: &Layout, rows: &mut Vec<Row>) {
+    match value {
+        Value::Array(items) if is_collection(value) => {
+            for item in items {
+                let mut row = prefix.clone();
+                match item {
+                    Value::Array(tuple) => {
+                        for (idx, val) in tuple.iter().enumerate() {
+                            let name = match layout.columns.get(idx) {
+                                Some(name) => name.to_string(),
+                                None => idx.to_string(),
+                            };
+                            flatten(val, &name, &mut row);
+                        }
+                    }
+                    _ => flatten(item, "", &mut row),
+                }
+                rows.push(row);
+            }
+        }
+        Value::Object(map) if depth < layout.keys.len() => {
+            let key = layout.keys[depth];
+            if layout.scalar_rows && map.values().all(is_scalar) {
+                for (name, val) in map {
+                    let mut row = prefix.clone();
+                    row.push((key.to_string(), Value::String(name.clone())));
+                    row.push(("value".to_string(), val.clone()));
+                    rows.push(row);
+                }
+                return;
+            }
+            // scalars next to collections (count, last...) are left out.
+            for (name, val) in map.iter().filter(|(_, val)| is_collection(val)) {
+                let mut prefix = prefix.clone();
+                if !key.is_empty() {
+                    prefix.push((key.to_string(), Value::String(name.clone())));
+                }
+                collect(val, depth + 1, &prefix, layout, rows);
+            }
+        }
+        _ => {
+            let mut row = prefix.clone();
+            flatten(value, "", &mut row);
+            rows.push(row);
+        }
+    }
+}
+
+/// Turns a response into flat rows following `layout`.
+pub fn rows(value: &Value, layout: &Layout) -> Vec<Row> {
+    let mut rows = vec![];
+    collect(value, 0, &vec![], layout, &mut rows);
+    return rows;
+}
+
+/// Column names of all rows, in order of appearance.
+fn headers(rows: &[Row]) -> Vec<String> {
+    let mut headers: Vec<String> = vec![];
+    for row in rows {
+        for (name, _) in row {
+            if !headers.contains(name) {
+                headers.push(name.clone());
+            }
+        }
+    }
+    return headers;
+}
+
+fn cell(value: &Value) -> String {
+    return match value {
+        Value::String(val) => val.clone(),
+        Value::Null => String::new(),
+        val => val.to_string(),
+    };
+}
+
+fn cells(headers: &[String], row: &Row) -> Vec<String> {
+    return headers
+        .iter()
+        .map(|header| {
+            row.iter()
+                .find(|(name, _)| name == header)
+                .map(|(_, val)| cell(val))
+                .unwrap_or_default()
+        })
+        .collect();
+}
+
+/// Prints rows as a left aligned table.
+pub fn display_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (idx, cell) in row.iter().enumerate() {
+            widths[idx] = widths[idx].max(cell.len());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let cells: Vec<String> = cells
+            .iter()
+            .enumerate()
+            .map(|(idx, cell)| format!("{:width$}", cell, width = widths[idx]))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    };
+    line(headers.to_vec());
+    for row in rows {
+        line(row.iter().map(|c| c.as_str()).collect());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+/// Operators by parsing priority, two characters ones first.
+const OPERATORS: &[(&str, Operator)] = &[
+    ("!=", Operator::Ne),
+    ("<=", Operator::Le),
+    (">=", Operator::Ge),
+    ("=", Operator::Eq),
+    ("<", Operator::Lt),
+    (">", Operator::Gt),
+    ("~", Operator::Contains),
+];
+
+/// A `--filter` expression such as `status=closed` or `amount<0`.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    field: String,
+    operator: Operator,
+    value: String,
+    /// Resolves the spellings of pairs and assets, see `with_registry`.
+    registry: Option<&'static AssetRegistry>,
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // the operator is the leftmost one, preferring two characters ones.
+        let found = OPERATORS
+            .iter()
+            .filter_map(|(token, operator)| s.find(token).map(|idx| (idx, *token, *operator)))
+            .min_by_key(|(idx, token, _)| (*idx, usize::MAX - token.len()));
+        let (idx, token, operator) = found.ok_or(anyhow!("invalid filter {}", s))?;
+        let field = s[..idx].trim();
+        if field.is_empty() {
+            return Err(anyhow!("invalid filter {}", s));
+        }
+        return Ok(Filter {
+            field: field.to_string(),
+            operator,
+            value: s[idx + token.len()..].trim().to_string(),
+            registry: None,
+        });
+    }
+}
+
+/// Compares numerically when both sides are numbers, as strings otherwise.
+fn compare(left: &str, right: &str) -> Ordering {
+    return match (Decimal::from_str(left), Decimal::from_str(right)) {
+        (Ok(left), Ok(right)) => left.cmp(&right),
+        _ => left.cmp(right),
+    };
+}
+
+/// Finds a column by its full name or, for nested values, by its last
+/// segment (`pair` matches `descr.pair`).
+fn lookup<'a>(row: &'a Row, field: &str) -> Option<&'a Value> {
+    if let Some((_, val)) = row.iter().find(|(name, _)| name == field) {
+        return Some(val);
+    }
+    let suffix = format!(".{}", field);
+    return row
+        .iter()
+        .find(|(name, _)| name.ends_with(&suffix))
+        .map(|(_, val)| val);
+}
+
+impl Filter {
+    /// Whether the filter compares pairs or assets, which endpoints spell
+    /// differently (`XXBTZUSD`, `XBTUSD`).
+    pub fn needs_registry(&self) -> bool {
+        return matches!(self.field.rsplit('.').next(), Some("pair" | "asset"));
+    }
+
+    /// Compares pairs and assets by their canonical identifier.
+    pub fn with_registry(mut self, registry: &'static AssetRegistry) -> Self {
+        self.registry = Some(registry);
+        self.value = self.canonical(&self.value);
+        return self;
+    }
+
+    fn canonical(&self, value: &str) -> String {
+        let id = match (self.registry, self.field.rsplit('.').next()) {
+            (Some(registry), Some("pair")) => registry.pair_id(value),
+            (Some(registry), Some("asset")) => registry.asset_id(value),
+            _ => None,
+        };
+        return id.unwrap_or(value).to_string();
+    }
+
+    fn matches(&self, row: &Row) -> bool {
+        let value = match lookup(row, &self.field) {
+            Some(val) => self.canonical(&cell(val)),
+            None => return false,
+        };
+        let ordering = compare(&value, &self.value);
+        return match self.operator {
+            Operator::Eq => ordering == Ordering::Equal,
+            Operator::Ne => ordering != Ordering::Equal,
+            Operator::Lt => ordering == Ordering::Less,
+            Operator::Le => ordering != Ordering::Greater,
+            Operator::Gt => ordering == Ordering::Greater,
+            Operator::Ge => ordering != Ordering::Less,
+            Operator::Contains => value.contains(&self.value),
+        };
+    }
+}
+
+/// Rows selection given with `--fields`, `--filter` and `--sort`.
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub fields: Vec<String>,
+    pub filters: Vec<Filter>,
+    /// Field to sort by, descending when prefixed with `-`.
+    pub sort: Option<String>,
+}
+
+impl Selection {
+    pub fn is_empty(&self) -> bool {
+        return self.fields.is_empty() && self.filters.is_empty() && self.sort.is_none();
+    }
+
+    pub fn apply(&self, rows: Vec<Row>) -> Vec<Row> {
+        let mut rows: Vec<Row> = rows
+            .into_iter()
+            .filter(|row| self.filters.iter().all(|f| f.matches(row)))
+            .collect();
+        if let Some(sort) = &self.sort {
+            let (field, descending) = match sort.strip_prefix('-') {
+                Some(field) => (field, true),
+                None => (sort.as_str(), false),
+            };
+            rows.sort_by(|a, b| {
+                let ordering = match (lookup(a, field), lookup(b, field)) {
+                    (Some(a), Some(b)) => compare(&cell(a), &cell(b)),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                };
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+        }
+        if !self.fields.is_empty() {
+            rows = rows
+                .into_iter()
+                .map(|row| {
+                    self.fields
+                        .iter()
+                        .map(|field| {
+                            let val = lookup(&row, field).cloned().unwrap_or(Value::Null);
+                            (field.clone(), val)
+                        })
+                        .collect()
+                })
+                .collect();
+        }
+        return rows;
+    }
+}
+
+/// Key of a row when diffing: the values of the layout key columns or,
+/// for layouts without keys, the position of the row.
+fn row_key(row: &Row, keys: &[&str], idx: usize) -> Vec<Value> {
+    let key: Vec<Value> = keys
+        .iter()
+        .filter(|key| !key.is_empty())
+        .filter_map(|key| lookup(row, key).cloned())
+        .collect();
+    if key.is_empty() {
+        return vec![Value::from(idx)];
+    }
+    return key;
+}
+
+/// Returns the rows of `current` that are not in `previous` (change
+/// `added`), the rows that disappeared (`removed`) and, for the rows that
+/// changed, their key columns and changed fields (`changed`). Numeric
+/// fields come with their delta, other fields with their previous value.
+fn diff(previous: &[Row], current: &[Row], keys: &[&str]) -> Vec<Row> {
+    let change = |kind: &str| ("change".to_string(), Value::from(kind));
+    let previous: Vec<(Vec<Value>, &Row)> = previous
+        .iter()
+        .enumerate()
+        .map(|(idx, row)| (row_key(row, keys, idx), row))
+        .collect();
+    let mut changes = vec![];
+    let mut seen = vec![];
+    for (idx, row) in current.iter().enumerate() {
+        let key = row_key(row, keys, idx);
+        let before = previous.iter().find(|(k, _)| *k == key).map(|(_, row)| *row);
+        seen.push(key);
+        let before = match before {
+            Some(before) => before,
+            None => {
+                let mut added = vec![change("added")];
+                added.extend(row.iter().cloned());
+                changes.push(added);
+                continue;
+            }
+        };
+        let mut changed = vec![];
+        for (name, val) in row {
+            let old = lookup(before, name).cloned().unwrap_or(Value::Null);
+            if old == *val {
+                continue;
+            }
+            changed.push((name.clone(), val.clone()));
+            match (Decimal::from_str(&cell(&old)), Decimal::from_str(&cell(val))) {
+                (Ok(old), Ok(new)) => {
+                    let delta = (new - old).normalize().to_string();
+                    changed.push((format!("{}.delta", name), Value::from(delta)));
+                }
+                _ => changed.push((format!("{}.previous", name), old)),
+            }
+        }
+        if changed.is_empty() {
+            continue;
+        }
+        let mut row: Row = vec![change("changed")];
+        row.extend(
+            before
+                .iter()
+                .filter(|(name, _)| keys.contains(&name.as_str()))
+                .cloned(),
+        );
+        row.extend(changed);
+        changes.push(row);
+    }
+    for (key, row) in previous {
+        if !seen.contains(&key) {
+            let mut removed = vec![change("removed")];
+            removed.extend(row.iter().cloned());
+            changes.push(removed);
+        }
+    }
+    return changes;
+}
+
+/// Prints responses in the format selected with `--output`.
+pub struct Output {
+    pub format: Format,
+    pub layout: Layout,
+    pub selection: Selection,
+    /// Rows of the last response when watching, only changes are
+    /// printed after the first one.
+    watched: Option<RefCell<Option<Vec<Row>>>>,
+    exit_on: Vec<Filter>,
+    matched: Cell<bool>,
+}
+
+impl Output {
+    pub fn new(format: Format, command: &str, selection: Selection) -> Self {
+        if format == Format::Raw {
+            kraken::set_observer(|_, body| println!("{}", body));
+        }
+        Self {
+            format,
+            layout: layout(command),
+            selection,
+            watched: None,
+            exit_on: vec![],
+            matched: Cell::new(false),
+        }
+    }
+
+    /// Only prints what changed since the previous response.
+    pub fn watch(mut self, watch: bool) -> Self {
+        self.watched = watch.then(|| RefCell::new(None));
+        return self;
+    }
+
+    /// Rows matching all of `filters` set `condition_met`.
+    pub fn exit_on(mut self, filters: Vec<Filter>) -> Self {
+        self.exit_on = filters;
+        return self;
+    }
+
+    pub fn condition_met(&self) -> bool {
+        return self.matched.get();
+    }
+
+    /// Whether the command may print its own table rather than rows.
+    pub fn is_plain_table(&self) -> bool {
+        return self.format == Format::Table
+            && self.selection.is_empty()
+            && self.watched.is_none();
+    }
+
+    pub fn display<T>(&self, output: T)
+    where
+        T: Serialize,
+    {
+        if let Err(e) = self.write(output) {
+            eprintln!("{}", e);
+        }
+    }
+
+    fn rows(&self, value: &Value) -> Vec<Row> {
+        return self.selection.apply(rows(value, &self.layout));
+    }
+
+    fn write<T>(&self, output: T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let value = serde_json::to_value(&output)?;
+        if !self.exit_on.is_empty() {
+            let matched = rows(&value, &self.layout)
+                .iter()
+                .any(|row| self.exit_on.iter().all(|f| f.matches(row)));
+            self.matched.set(matched);
+        }
+        if let Some(watched) = &self.watched {
+            let rows = self.rows(&value);
+            let previous = watched.replace(Some(rows.clone()));
+            if let Some(previous) = previous {
+                if self.format != Format::Raw {
+                    self.print_rows(diff(&previous, &rows, self.layout.keys))?;
+                }
+                return Ok(());
+            }
+        }
+        match self.format {
+            Format::Json if self.selection.is_empty() => {
+                println!("{}", serde_json::to_string_pretty(&value)?)
+            }
+            // the body has already been printed by the observer.
+            Format::Raw => {}
+            _ => self.print_rows(self.rows(&value))?,
+        }
+        return Ok(());
+    }
+
+    fn print_rows(&self, rows: Vec<Row>) -> Result<()> {
+        match self.format {
+            // a selection only applies to rows, print them as a list.
+            Format::Json => {
+                let rows: Vec<Map<String, Value>> = rows
+                    .into_iter()
+                    .map(|row| row.into_iter().collect())
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&rows)?)
+            }
+            Format::Raw => {}
+            Format::Ndjson => {
+                for row in rows {
+                    let object: Map<String, Value> = row.into_iter().collect();
+                    println!("{}", serde_json::to_string(&object)?);
+                }
+            }
+            Format::Csv => {
+                let headers = headers(&rows);
+                let mut writer = csv::Writer::from_writer(std::io::stdout());
+                writer.write_record(&headers)?;
+                for row in rows.iter() {
+                    writer.write_record(cells(&headers, row))?;
+                }
+                writer.flush()?;
+            }
+            Format::Table => {
+                let headers = headers(&rows);
+                let cells: Vec<Vec<String>> = rows.iter().map(|row| cells(&headers, row)).collect();
+                let headers: Vec<&str> = headers.iter().map(|h| h.as_str()).collect();
+                display_table(&headers, &cells);
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, layout, rows, Filter, Selection};
+    use kraken::registry::AssetRegistry;
+    use serde_json::json;
+
+    #[test]
+    fn orders_rows_test() {
+        let value = json!({"open": {
+            "O1": {"status": "open", "descr": {"pair": "XBTUSD"}, "vol": "1"},
+            "O2": {"status": "open", "descr": {"pair": "ETHUSD"}, "vol": "2"}
+        }});
+        let rows = rows(&value, &layout("open-orders"));
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0][0], ("txid".to_string(), json!("O1")));
+        assert!(rows[1].contains(&("descr.pair".to_string(), json!("ETHUSD"))));
+    }
+
+    #[test]
+    fn depth_rows_test() {
+        let value = json!({"XXBTZUSD": {
+            "asks": [["101", "1", 1], ["102", "2", 2]],
+            "bids": [["100", "3", 3]]
+        }});
+        let rows = rows(&value, &layout("depth"));
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            rows[2],
+            vec![
+                ("pair".to_string(), json!("XXBTZUSD")),
+                ("side".to_string(), json!("bids")),
+                ("price".to_string(), json!("100")),
+                ("volume".to_string(), json!("3")),
+                ("timestamp".to_string(), json!(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn balance_rows_test() {
+        let value = json!({"ZUSD": "10.5", "XXBT": "1"});
+        let rows = rows(&value, &layout("balance"));
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0][1], ("value".to_string(), json!("1")));
+    }
+
+    #[test]
+    fn selection_test() {
+        let value = json!({"ledger": {
+            "L1": {"asset": "ZUSD", "amount": "-10.5", "type": "trade"},
+            "L2": {"asset": "XXBT", "amount": "0.1", "type": "trade"},
+            "L3": {"asset": "ZUSD", "amount": "-2", "type": "withdrawal"}
+        }, "count": 3});
+        let selection = Selection {
+            fields: vec!["id".to_string(), "amount".to_string()],
+            filters: vec!["amount<0".parse().unwrap()],
+            sort: Some("-amount".to_string()),
+        };
+        let rows = selection.apply(rows(&value, &layout("ledgers")));
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    ("id".to_string(), json!("L3")),
+                    ("amount".to_string(), json!("-2"))
+                ],
+                vec![
+                    ("id".to_string(), json!("L1")),
+                    ("amount".to_string(), json!("-10.5"))
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn filter_parse_test() {
+        let filter: Filter = "descr.pair!=XXBTZUSD".parse().unwrap();
+        assert_eq!(filter.field, "descr.pair");
+        assert_eq!(filter.value, "XXBTZUSD");
+        assert!("=closed".parse::<Filter>().is_err());
+        assert!("status".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn filter_registry_test() {
+        let assets = serde_json::from_str(
+            r#"{
+                "XXBT": {"aclass": "currency", "altname": "XBT", "decimals": 10, "display_decimals": 5},
+                "ZUSD": {"aclass": "currency", "altname": "USD", "decimals": 4, "display_decimals": 2}
+            }"#,
+        )
+        .unwrap();
+        let pairs = serde_json::from_str(
+            r#"{"XXBTZUSD": {"altname": "XBTUSD", "wsname": "XBT/USD", "aclass_base": "currency",
+            "base": "XXBT", "aclass_quote": "currency", "quote": "ZUSD", "lot": "unit",
+            "pair_decimals": 1, "lot_decimals": 8, "lot_multiplier": 1, "leverage_buy": [],
+            "leverage_sell": [], "fees": [], "fees_maker": [], "fee_volume_currency": "ZUSD",
+            "margin_call": 80, "margin_stop": 40, "ordermin": "0.0001"}}"#,
+        )
+        .unwrap();
+        let registry = Box::leak(Box::new(AssetRegistry::new(assets, pairs)));
+        let value = json!({"open": {
+            "O1": {"status": "open", "descr": {"pair": "XBTUSD"}, "vol": "1"},
+            "O2": {"status": "open", "descr": {"pair": "ETHUSD"}, "vol": "2"}
+        }});
+        let rows = rows(&value, &layout("open-orders"));
+        let filter: Filter = "pair=XXBTZUSD".parse().unwrap();
+        assert!(filter.needs_registry());
+        assert!(!filter.matches(&rows[0]));
+        let filter = filter.with_registry(registry);
+        assert!(filter.matches(&rows[0]));
+        assert!(!filter.matches(&rows[1]));
+        let filter: Filter = "descr.pair=btc/usd".parse().unwrap();
+        assert!(filter.with_registry(registry).matches(&rows[0]));
+        let filter: Filter = "asset=BTC".parse().unwrap();
+        let balance = super::rows(&json!({"XXBT": "1"}), &layout("balance"));
+        assert!(filter.with_registry(registry).matches(&balance[0]));
+    }
+
+    #[test]
+    fn diff_test() {
+        let before = json!({"XXBT": {"balance": "1.5", "hold_trade": "0"},
+            "ZUSD": {"balance": "100", "hold_trade": "0"}});
+        let after = json!({"XXBT": {"balance": "1.25", "hold_trade": "0"},
+            "XETH": {"balance": "2", "hold_trade": "0"}});
+        let layout = layout("balance-ex");
+        let changes = diff(
+            &rows(&before, &layout),
+            &rows(&after, &layout),
+            layout.keys,
+        );
+        assert_eq!(
+            changes,
+            vec![
+                vec![
+                    ("change".to_string(), json!("added")),
+                    ("asset".to_string(), json!("XETH")),
+                    ("balance".to_string(), json!("2")),
+                    ("hold_trade".to_string(), json!("0")),
+                ],
+                vec![
+                    ("change".to_string(), json!("changed")),
+                    ("asset".to_string(), json!("XXBT")),
+                    ("balance".to_string(), json!("1.25")),
+                    ("balance.delta".to_string(), json!("-0.25")),
+                ],
+                vec![
+                    ("change".to_string(), json!("removed")),
+                    ("asset".to_string(), json!("ZUSD")),
+                    ("balance".to_string(), json!("100")),
+                    ("hold_trade".to_string(), json!("0")),
+                ],
+            ]
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use anyhow::{anyhow, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use chrono::Utc;
+use data_encoding::BASE64;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Parameters of the Argon2id key derivation, kept in the store so they
+/// can be raised without breaking existing stores.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Kdf {
+    pub salt: String,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+/// A key and secret encrypted with XChaCha20-Poly1305. The name of the
+/// entry is authenticated with it so entries cannot be swapped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub nonce: String,
+    pub ciphertext: String,
+    pub created: i64,
+    pub updated: i64,
+}
+
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct Secret {
+    pub key: String,
+    /// Base64 encoded, as given by Kraken.
+    pub secret: String,
+}
+
+/// Encrypted credentials, stored as JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Store {
+    pub kdf: Kdf,
+    pub entries: BTreeMap<String, Entry>,
+}
+
+/// Key derived from the passphrase, wiped on drop.
+pub type MasterKey = Zeroizing<[u8; 32]>;
+
+/// `~/.config/kraken/credentials.json` on Linux.
+pub fn default_path() -> Option<PathBuf> {
+    return dirs::config_dir().map(|dir| dir.join("kraken").join("credentials.json"));
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    return bytes;
+}
+
+impl Store {
+    /// Creates an empty store using the default Argon2id parameters.
+    pub fn new() -> Self {
+        return Self::with_params(
+            Params::DEFAULT_M_COST,
+            Params::DEFAULT_T_COST,
+            Params::DEFAULT_P_COST,
+        );
+    }
+
+    pub fn with_params(m_cost: u32, t_cost: u32, p_cost: u32) -> Self {
+        return Self {
+            kdf: Kdf {
+                salt: BASE64.encode(&random::<SALT_LEN>()),
+                m_cost,
+                t_cost,
+                p_cost,
+            },
+            entries: BTreeMap::new(),
+        };
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("cannot open {} ({})", path.display(), e))?;
+        return Ok(serde_json::from_str(&content)?);
+    }
+
+    /// Writes the store readable by its owner only, replacing the file
+    /// at once so an interrupted write cannot lose entries.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&tmp)?;
+        serde_json::to_writer_pretty(file, self)?;
+        std::fs::rename(&tmp, path)?;
+        return Ok(());
+    }
+
+    /// Derives the encryption key and checks it against an existing
+    /// entry, if any.
+    pub fn unlock(&self, passphrase: &str) -> Result<MasterKey> {
+        let salt = BASE64.decode(self.kdf.salt.as_bytes())?;
+        let params = Params::new(self.kdf.m_cost, self.kdf.t_cost, self.kdf.p_cost, Some(32))
+            .map_err(|e| anyhow!("invalid key derivation parameters ({})", e))?;
+        let mut key = Zeroizing::new([0u8; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, key.as_mut())
+            .map_err(|e| anyhow!("cannot derive key ({})", e))?;
+        if let Some(name) = self.entries.keys().next() {
+            self.get(&key, name)?;
+        }
+        return Ok(key);
+    }
+
+    /// Adds or replaces an entry.
+    pub fn insert(&mut self, master: &MasterKey, name: &str, secret: &Secret) -> Result<()> {
+        let cipher = XChaCha20Poly1305::new(master.as_ref().into());
+        let nonce = random::<NONCE_LEN>();
+        let plaintext = Zeroizing::new(serde_json::to_vec(secret)?);
+        let payload = Payload {
+            msg: &plaintext,
+            aad: name.as_bytes(),
+        };
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| anyhow!("cannot encrypt {}", name))?;
+        let now = Utc::now().timestamp();
+        let created = self.entries.get(name).map(|e| e.created).unwrap_or(now);
+        self.entries.insert(
+            name.to_string(),
+            Entry {
+                nonce: BASE64.encode(&nonce),
+                ciphertext: BASE64.encode(&ciphertext),
+                created,
+                updated: now,
+            },
+        );
+        return Ok(());
+    }
+
+    pub fn get(&self, master: &MasterKey, name: &str) -> Result<Secret> {
+        let entry = self
+            .entries
+            .get(name)
+            .ok_or(anyhow!("unknown credential {}", name))?;
+        let cipher = XChaCha20Poly1305::new(master.as_ref().into());
+        let nonce = BASE64.decode(entry.nonce.as_bytes())?;
+        if nonce.len() != NONCE_LEN {
+            return Err(anyhow!("invalid nonce for {}", name));
+        }
+        let ciphertext = BASE64.decode(entry.ciphertext.as_bytes())?;
+        let payload = Payload {
+            msg: &ciphertext,
+            aad: name.as_bytes(),
+        };
+        let plaintext = cipher
+            .decrypt(XNonce::from_slice(&nonce), payload)
+            .map(Zeroizing::new)
+            .map_err(|_| anyhow!("wrong passphrase or corrupted entry {}", name))?;
+        return Ok(serde_json::from_slice(&plaintext)?);
+    }
+
+    /// Returns a copy of the store encrypted with a new passphrase, with
+    /// a new salt and new nonces.
+    pub fn rotate(&self, master: &MasterKey, passphrase: &str) -> Result<(Self, MasterKey)> {
+        let mut store = Self::with_params(self.kdf.m_cost, self.kdf.t_cost, self.kdf.p_cost);
+        let new_master = store.unlock(passphrase)?;
+        for (name, entry) in self.entries.iter() {
+            let secret = self.get(master, name)?;
+            store.insert(&new_master, name, &secret)?;
+            let rotated = store.entries.get_mut(name).unwrap();
+            rotated.created = entry.created;
+            rotated.updated = entry.updated;
+        }
+        return Ok((store, new_master));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Secret, Store};
+
+    fn secret(key: &str) -> Secret {
+        return Secret {
+            key: key.to_string(),
+            secret: "c2VjcmV0".to_string(),
+        };
+    }
+
+    #[test]
+    fn store_test() {
+        // cheap parameters, the defaults take a while in debug builds.
+        let mut store = Store::with_params(64, 1, 1);
+        let master = store.unlock("passphrase").unwrap();
+        store.insert(&master, "trading", &secret("K1")).unwrap();
+        store.insert(&master, "readonly", &secret("K2")).unwrap();
+        assert_eq!(store.get(&master, "trading").unwrap().key, "K1");
+        assert!(store.unlock("wrong").is_err());
+
+        // entries are bound to their name.
+        let mut swapped = store.clone();
+        let trading = swapped.entries.remove("trading").unwrap();
+        swapped.entries.insert("readonly".to_string(), trading);
+        assert!(swapped.get(&master, "readonly").is_err());
+
+        let (rotated, new_master) = store.rotate(&master, "new passphrase").unwrap();
+        assert_ne!(rotated.kdf.salt, store.kdf.salt);
+        assert_eq!(rotated.get(&new_master, "readonly").unwrap().key, "K2");
+        assert!(rotated.unlock("passphrase").is_err());
+    }
+}
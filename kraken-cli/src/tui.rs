@@ -0,0 +1,453 @@
+use crate::pretty_error;
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use kraken::permissions::Permission;
+use kraken::private::{BalanceResponse, Order};
+use kraken::public::{AssetTickerInfo, OrderBook, Trade};
+use kraken::Credential;
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
+use ratatui::{Frame, Terminal};
+use rust_decimal::Decimal;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+
+/// Number of price levels shown on each side of the ladder.
+const DEPTH: usize = 10;
+
+/// How long a key press is waited for before pending updates are drawn.
+const INPUT_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+struct Snapshot {
+    ticker: Option<AssetTickerInfo>,
+    book: Option<OrderBook>,
+    /// (price, volume, time, side) of the most recent trades first.
+    trades: Vec<(Decimal, Decimal, f64, String)>,
+    balances: Option<BalanceResponse>,
+    /// Open orders of the pair, oldest first.
+    orders: Vec<(String, Order)>,
+    errors: Vec<String>,
+}
+
+enum Message {
+    Snapshot(Box<Snapshot>),
+    Status(String),
+}
+
+/// A row of the order book ladder, asks above bids.
+#[derive(Debug, PartialEq)]
+struct Level {
+    side: &'static str,
+    price: Decimal,
+    volume: Decimal,
+    total: Decimal,
+}
+
+/// Returns up to `depth` levels per side, the best prices next to each
+/// other in the middle, with the volume accumulated from the spread.
+fn ladder(book: &OrderBook, depth: usize) -> Vec<Level> {
+    let mut levels = vec![];
+    let mut total = Decimal::ZERO;
+    for (price, volume, _) in book.asks.iter().take(depth) {
+        total += volume;
+        levels.push(Level {
+            side: "ask",
+            price: *price,
+            volume: *volume,
+            total,
+        });
+    }
+    levels.reverse();
+    let mut total = Decimal::ZERO;
+    for (price, volume, _) in book.bids.iter().take(depth) {
+        total += volume;
+        levels.push(Level {
+            side: "bid",
+            price: *price,
+            volume: *volume,
+            total,
+        });
+    }
+    return levels;
+}
+
+async fn fetch(pair: &str, altname: &str, cred: Option<&Credential>) -> Snapshot {
+    let mut snapshot = Snapshot::default();
+    let (ticker, depth, trades) = tokio::join!(
+        kraken::public::ticker(pair),
+        kraken::public::depth(pair, Some(DEPTH as i64)),
+        kraken::public::trades(pair, None),
+    );
+    match ticker {
+        Ok(ticker) => snapshot.ticker = ticker.0.into_values().next(),
+        Err(e) => snapshot.errors.push(format!("ticker: {}", pretty_error(e))),
+    }
+    match depth {
+        Ok(depth) => snapshot.book = depth.0.into_values().next(),
+        Err(e) => snapshot.errors.push(format!("depth: {}", pretty_error(e))),
+    }
+    match trades {
+        Ok(trades) => {
+            for data in trades.0.into_values() {
+                if let Trade::Pair(data) = data {
+                    snapshot.trades = data
+                        .0
+                        .into_iter()
+                        .rev()
+                        .map(|(price, volume, time, side, _, _)| (price, volume, time, side))
+                        .collect();
+                }
+            }
+        }
+        Err(e) => snapshot.errors.push(format!("trades: {}", pretty_error(e))),
+    }
+    let cred = match cred {
+        Some(cred) => cred,
+        None => return snapshot,
+    };
+    let (balances, orders) = tokio::join!(
+        kraken::private::balance(cred),
+        kraken::private::open_orders(cred, None, None),
+    );
+    match balances {
+        Ok(balances) => snapshot.balances = Some(balances),
+        Err(e) => snapshot.errors.push(format!("balance: {}", pretty_error(e))),
+    }
+    match orders {
+        Ok(orders) => {
+            let mut orders: Vec<(String, Order)> = orders
+                .open
+                .into_iter()
+                .filter(|(_, order)| order.descr.pair == altname)
+                .collect();
+            orders.sort_by(|a, b| a.1.opentm.total_cmp(&b.1.opentm));
+            snapshot.orders = orders;
+        }
+        Err(e) => snapshot.errors.push(format!("open-orders: {}", pretty_error(e))),
+    }
+    return snapshot;
+}
+
+struct App {
+    pair: String,
+    snapshot: Snapshot,
+    orders: TableState,
+    /// Order waiting for the cancellation to be confirmed.
+    cancelling: Option<String>,
+    status: String,
+}
+
+impl App {
+    fn selected(&self) -> Option<&String> {
+        let idx = self.orders.selected()?;
+        return self.snapshot.orders.get(idx).map(|(txid, _)| txid);
+    }
+
+    fn select(&mut self, offset: isize) {
+        let count = self.snapshot.orders.len();
+        if count == 0 {
+            self.orders.select(None);
+            return;
+        }
+        let current = self.orders.selected().unwrap_or(0) as isize;
+        let idx = (current + offset).clamp(0, count as isize - 1);
+        self.orders.select(Some(idx as usize));
+    }
+
+    fn update(&mut self, snapshot: Snapshot) {
+        self.status = snapshot.errors.join(" | ");
+        self.snapshot = snapshot;
+        // keeps the selection within the orders that are still open.
+        self.select(0);
+    }
+}
+
+fn ticker_line(ticker: Option<&AssetTickerInfo>) -> String {
+    let ticker = match ticker {
+        Some(ticker) => ticker,
+        None => return "waiting for ticker...".to_string(),
+    };
+    return format!(
+        "last {}  bid {}  ask {}  high {}  low {}  volume {}  vwap {}",
+        ticker.c.0, ticker.b.0, ticker.a.0, ticker.h.1, ticker.l.1, ticker.v.1, ticker.p.1
+    );
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(DEPTH as u16 * 2 + 3),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .split(frame.size());
+    let ticker = Paragraph::new(ticker_line(app.snapshot.ticker.as_ref()))
+        .block(Block::default().borders(Borders::ALL).title(app.pair.as_str()));
+    frame.render_widget(ticker, rows[0]);
+
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage(40),
+            Constraint::Percentage(35),
+            Constraint::Percentage(25),
+        ])
+        .split(rows[1]);
+    draw_book(frame, app, columns[0]);
+    draw_trades(frame, app, columns[1]);
+    draw_balances(frame, app, columns[2]);
+    draw_orders(frame, app, rows[2]);
+
+    let footer = match &app.cancelling {
+        Some(txid) => Line::styled(
+            format!("cancel {}? y/n", txid),
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        None if !app.status.is_empty() => {
+            Line::styled(app.status.clone(), Style::default().fg(Color::Yellow))
+        }
+        None => Line::raw("q quit  up/down select  c cancel selected order  r refresh"),
+    };
+    frame.render_widget(Paragraph::new(footer), rows[3]);
+}
+
+fn draw_book(frame: &mut Frame, app: &App, area: Rect) {
+    let levels = match &app.snapshot.book {
+        Some(book) => ladder(book, DEPTH),
+        None => vec![],
+    };
+    let rows = levels.iter().map(|level| {
+        let color = match level.side {
+            "ask" => Color::Red,
+            _ => Color::Green,
+        };
+        Row::new(vec![
+            level.price.to_string(),
+            level.volume.to_string(),
+            level.total.to_string(),
+        ])
+        .style(Style::default().fg(color))
+    });
+    let table = Table::new(rows, [Constraint::Ratio(1, 3); 3])
+        .header(Row::new(vec!["PRICE", "VOLUME", "TOTAL"]))
+        .block(Block::default().borders(Borders::ALL).title("book"));
+    frame.render_widget(table, area);
+}
+
+fn draw_trades(frame: &mut Frame, app: &App, area: Rect) {
+    let rows = app.snapshot.trades.iter().map(|(price, volume, time, side)| {
+        let color = match side.as_str() {
+            "s" => Color::Red,
+            _ => Color::Green,
+        };
+        let time = chrono::DateTime::from_timestamp(*time as i64, 0).unwrap_or_default();
+        Row::new(vec![
+            time.format("%H:%M:%S").to_string(),
+            price.to_string(),
+            volume.to_string(),
+        ])
+        .style(Style::default().fg(color))
+    });
+    let table = Table::new(rows, [Constraint::Ratio(1, 3); 3])
+        .header(Row::new(vec!["TIME", "PRICE", "VOLUME"]))
+        .block(Block::default().borders(Borders::ALL).title("trades"));
+    frame.render_widget(table, area);
+}
+
+fn draw_balances(frame: &mut Frame, app: &App, area: Rect) {
+    let mut balances: Vec<(&String, &Decimal)> = match &app.snapshot.balances {
+        Some(balances) => balances.0.iter().filter(|(_, b)| !b.is_zero()).collect(),
+        None => vec![],
+    };
+    balances.sort();
+    let rows = balances
+        .into_iter()
+        .map(|(asset, balance)| Row::new(vec![asset.clone(), balance.to_string()]));
+    let table = Table::new(rows, [Constraint::Ratio(1, 3), Constraint::Ratio(2, 3)])
+        .header(Row::new(vec!["ASSET", "BALANCE"]))
+        .block(Block::default().borders(Borders::ALL).title("balances"));
+    frame.render_widget(table, area);
+}
+
+fn draw_orders(frame: &mut Frame, app: &mut App, area: Rect) {
+    let rows = app.snapshot.orders.iter().map(|(txid, order)| {
+        Row::new(vec![
+            txid.clone(),
+            order.descr.order.clone(),
+            format!("{} / {}", order.vol_exec, order.vol),
+            order.status.clone(),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(20),
+            Constraint::Min(30),
+            Constraint::Length(25),
+            Constraint::Length(10),
+        ],
+    )
+    .header(Row::new(vec!["TXID", "ORDER", "EXECUTED", "STATUS"]))
+    .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+    .block(Block::default().borders(Borders::ALL).title("open orders"));
+    frame.render_stateful_widget(table, area, &mut app.orders);
+}
+
+/// Reads a key press, if any, without blocking the other tasks.
+fn next_key() -> Result<Option<KeyCode>> {
+    let ready = tokio::task::block_in_place(|| event::poll(INPUT_TIMEOUT))?;
+    if !ready {
+        return Ok(None);
+    }
+    return match event::read()? {
+        Event::Key(key) if key.kind == KeyEventKind::Press => Ok(Some(key.code)),
+        _ => Ok(None),
+    };
+}
+
+async fn run_app<B: ratatui::backend::Backend>(
+    terminal: &mut Terminal<B>,
+    pair: &str,
+    altname: &str,
+    cred: Option<Credential>,
+    refresh: Duration,
+) -> Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let wake = Arc::new(Notify::new());
+    let fetcher = {
+        let (tx, wake) = (tx.clone(), wake.clone());
+        let (pair, altname, cred) = (pair.to_string(), altname.to_string(), cred.clone());
+        tokio::spawn(async move {
+            loop {
+                let snapshot = fetch(&pair, &altname, cred.as_ref()).await;
+                if tx.send(Message::Snapshot(Box::new(snapshot))).is_err() {
+                    return;
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(refresh) => {}
+                    _ = wake.notified() => {}
+                }
+            }
+        })
+    };
+    let mut app = App {
+        pair: pair.to_string(),
+        snapshot: Snapshot::default(),
+        orders: TableState::default(),
+        cancelling: None,
+        status: String::new(),
+    };
+    loop {
+        while let Ok(message) = rx.try_recv() {
+            match message {
+                Message::Snapshot(snapshot) => app.update(*snapshot),
+                Message::Status(status) => app.status = status,
+            }
+        }
+        terminal.draw(|frame| draw(frame, &mut app))?;
+        let key = match next_key()? {
+            Some(key) => key,
+            None => continue,
+        };
+        if let Some(txid) = app.cancelling.take() {
+            if key != KeyCode::Char('y') {
+                continue;
+            }
+            let cred = match &cred {
+                Some(cred) => cred.clone(),
+                None => continue,
+            };
+            let (tx, wake) = (tx.clone(), wake.clone());
+            app.status = format!("cancelling {}...", txid);
+            tokio::spawn(async move {
+                let status = match kraken::private::cancel_order(&cred, &txid).await {
+                    Ok(_) => format!("{} cancelled", txid),
+                    Err(e) => format!("cannot cancel {}: {}", txid, pretty_error(e)),
+                };
+                let _ = tx.send(Message::Status(status));
+                wake.notify_one();
+            });
+            continue;
+        }
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => break,
+            KeyCode::Up | KeyCode::Char('k') => app.select(-1),
+            KeyCode::Down | KeyCode::Char('j') => app.select(1),
+            KeyCode::Char('r') => wake.notify_one(),
+            KeyCode::Char('c') => match cred.as_ref().map(|c| c.permissions()) {
+                None => app.status = "credentials are needed to cancel orders".to_string(),
+                // the library would refuse it, say so before asking.
+                Some(Some(permissions)) if !permissions.contains(&Permission::Cancel) => {
+                    app.status = "the cancel permission is not declared for this key".to_string()
+                }
+                Some(_) => app.cancelling = app.selected().cloned(),
+            },
+            _ => {}
+        }
+    }
+    fetcher.abort();
+    return Ok(());
+}
+
+/// Shows a live view of a pair until `q` is pressed. Balances and open
+/// orders are only shown when credentials are given.
+pub async fn run(
+    pair: &str,
+    altname: &str,
+    cred: Option<Credential>,
+    refresh: Duration,
+) -> Result<()> {
+    enable_raw_mode()?;
+    let mut stdout = std::io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+    let result = run_app(&mut terminal, pair, altname, cred, refresh).await;
+    // the terminal is restored even when the app failed.
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ladder, Level};
+    use kraken::public::OrderBook;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn ladder_test() {
+        let book: OrderBook = serde_json::from_value(serde_json::json!({
+            "asks": [["101", "1", 0], ["102", "2", 0], ["103", "3", 0]],
+            "bids": [["100", "1", 0], ["99", "4", 0]]
+        }))
+        .unwrap();
+        let levels = ladder(&book, 2);
+        let level = |side, price: i64, volume: i64, total: i64| Level {
+            side,
+            price: Decimal::from(price),
+            volume: Decimal::from(volume),
+            total: Decimal::from(total),
+        };
+        assert_eq!(
+            levels,
+            vec![
+                level("ask", 102, 2, 3),
+                level("ask", 101, 1, 1),
+                level("bid", 100, 1, 1),
+                level("bid", 99, 4, 5),
+            ]
+        );
+    }
+}
@@ -0,0 +1,233 @@
+#![allow(clippy::needless_return)]
+
+use std::process::Command;
+
+/// Runs the cli against the fixtures of the library, without network nor
+/// local config.
+fn kraken_cli(args: &[&str]) -> (bool, String) {
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/../kraken/tests/fixtures");
+    let output = Command::new(env!("CARGO_BIN_EXE_kraken-cli"))
+        .args(["--replay", fixtures, "--config-file", "/dev/null"])
+        .args(["--key", "KEY", "--secret", "U0VDUkVU"])
+        .args(args)
+        .env_clear()
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    return (output.status.success(), stdout);
+}
+
+#[test]
+fn balance_test() {
+    let (success, stdout) = kraken_cli(&["balance"]);
+    assert!(success);
+    let balance: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(balance["ZUSD"], "171288.6158");
+}
+
+#[test]
+fn whoami_test() {
+    let (success, stdout) =
+        kraken_cli(&["whoami", "--output", "csv", "--fields", "permission,access"]);
+    assert!(success);
+    assert!(stdout.contains("query-ledger,denied"));
+    assert!(stdout.contains("trade,granted"));
+}
+
+#[test]
+fn missing_fixture_test() {
+    let (success, _) = kraken_cli(&["ticker", "--pair", "ETHUSD"]);
+    assert!(!success);
+}
+
+#[test]
+fn history_test() {
+    let (success, stdout) = kraken_cli(&["ledgers", "--output", "csv", "--fields", "asset,amount"]);
+    assert!(success);
+    assert!(stdout.contains("XXBT,0.3750000000"));
+    let (success, stdout) = kraken_cli(&["query-orders", "--txid", "OQCLML-BW3P3-BUCMWZ"]);
+    assert!(success);
+    let orders: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(orders["OQCLML-BW3P3-BUCMWZ"]["status"], "open");
+}
+
+#[test]
+fn paper_test() {
+    let paper = std::env::temp_dir().join(format!("kraken-cli-paper-{}.json", std::process::id()));
+    let paper = paper.to_str().unwrap();
+    // answered by the account without --paper, never with it.
+    let args = ["query-orders", "--txid", "OQCLML-BW3P3-BUCMWZ"];
+    assert!(kraken_cli(&args).0);
+    assert!(!kraken_cli(&[&["--paper", paper][..], &args].concat()).0);
+}
+
+#[test]
+fn order_test() {
+    let (success, stdout) = kraken_cli(&[
+        "buy", "--pair", "XBTUSD", "--volume", "0.01", "--price", "30000", "--yes",
+    ]);
+    assert!(success);
+    assert!(stdout.contains("OUF4EM-FRGI2-MQMWZD"));
+    let (success, stdout) = kraken_cli(&[
+        "edit",
+        "--txid",
+        "OQCLML-BW3P3-BUCMWZ",
+        "--price",
+        "30100",
+        "--yes",
+    ]);
+    assert!(success);
+    assert!(stdout.contains("OFVXHJ-KPQ3B-VS7ELA"));
+    let (success, _) = kraken_cli(&["cancel-all", "--yes"]);
+    assert!(success);
+}
+
+#[test]
+fn raw_output_test() {
+    let (success, stdout) = kraken_cli(&["time", "--output", "raw"]);
+    assert!(success);
+    assert!(stdout.contains("\"error\""));
+    // commands calling several endpoints have no single body to print.
+    let (success, _) = kraken_cli(&["whoami", "--output", "raw"]);
+    assert!(!success);
+}
+
+#[test]
+fn profile_credentials_test() {
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/../kraken/tests/fixtures");
+    let config =
+        std::env::temp_dir().join(format!("kraken-cli-profile-{}.toml", std::process::id()));
+    std::fs::write(
+        &config,
+        "default_profile = \"readonly\"\n\n[profile.readonly]\nkey = \"KEY\"\nsecret = \"U0VDUkVU\"\npermissions = [\"query-orders\"]\n\n[profile.funds]\nkey = \"KEY\"\nsecret = \"U0VDUkVU\"\n",
+    )
+    .unwrap();
+    let run = |args: &[&str], env: &[(&str, &str)]| {
+        return Command::new(env!("CARGO_BIN_EXE_kraken-cli"))
+            .args([
+                "--replay",
+                fixtures,
+                "--config-file",
+                config.to_str().unwrap(),
+            ])
+            .args(args)
+            .env_clear()
+            .envs(env.iter().copied())
+            .output()
+            .unwrap()
+            .status
+            .success();
+    };
+    // the default profile does not declare query-funds.
+    assert!(!run(&["balance"], &[]));
+    // its permissions do not apply to credentials given outside of it.
+    assert!(run(
+        &["--key", "KEY", "--secret", "U0VDUkVU", "balance"],
+        &[]
+    ));
+    assert!(run(
+        &["balance"],
+        &[("KRAKEN_KEY", "KEY"), ("KRAKEN_SECRET", "U0VDUkVU")]
+    ));
+    assert!(!run(
+        &[
+            "--profile",
+            "readonly",
+            "--key",
+            "KEY",
+            "--secret",
+            "U0VDUkVU",
+            "balance"
+        ],
+        &[]
+    ));
+    // an explicit profile wins over the environment.
+    assert!(!run(
+        &["--profile", "readonly", "balance"],
+        &[("KRAKEN_KEY", "KEY"), ("KRAKEN_SECRET", "U0VDUkVU")]
+    ));
+    // nor over a signing agent of the environment.
+    assert!(run(
+        &["--profile", "funds", "balance"],
+        &[("KRAKEN_AGENT", "/nonexistent/kraken.sock")]
+    ));
+    std::fs::remove_file(&config).unwrap();
+}
+
+#[test]
+fn fee_estimate_test() {
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/../kraken/tests/fixtures");
+    let config = std::env::temp_dir().join(format!("kraken-cli-fee-{}.toml", std::process::id()));
+    std::fs::write(
+        &config,
+        "[profile.orders]\nkey = \"KEY\"\nsecret = \"U0VDUkVU\"\npermissions = [\"query-orders\"]\n",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        return Command::new(env!("CARGO_BIN_EXE_kraken-cli"))
+            .args([
+                "--replay",
+                fixtures,
+                "--config-file",
+                config.to_str().unwrap(),
+            ])
+            .args(["--profile", "orders", "fee-estimate", "--pair", "XBTUSD"])
+            .args(["--notional", "1000"])
+            .args(args)
+            .env_clear()
+            .output()
+            .unwrap();
+    };
+    // a given volume needs nothing from the account.
+    let output = run(&["--volume", "0"]);
+    assert!(output.status.success());
+    let estimate: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(estimate["fee"], "2.60");
+    // fetching it needs query-funds.
+    let output = run(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("query-funds"));
+    std::fs::remove_file(&config).unwrap();
+}
+
+#[test]
+fn cancel_only_test() {
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/../kraken/tests/fixtures");
+    let config =
+        std::env::temp_dir().join(format!("kraken-cli-cancel-{}.toml", std::process::id()));
+    std::fs::write(
+        &config,
+        "[profile.cancel]\nkey = \"KEY\"\nsecret = \"U0VDUkVU\"\npermissions = [\"cancel\"]\n",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        return Command::new(env!("CARGO_BIN_EXE_kraken-cli"))
+            .args([
+                "--replay",
+                fixtures,
+                "--config-file",
+                config.to_str().unwrap(),
+            ])
+            .args(["--profile", "cancel"])
+            .args(args)
+            .env_clear()
+            .output()
+            .unwrap();
+    };
+    // the orders cannot be read, the confirmation shows the txid only.
+    let output = run(&["cancel", "--txid", "OQCLML-BW3P3-BUCMWZ", "--yes"]);
+    assert!(output.status.success());
+    assert!(run(&["cancel-all", "--yes"]).status.success());
+    // editing an order reads it first.
+    let output = run(&[
+        "edit",
+        "--txid",
+        "OQCLML-BW3P3-BUCMWZ",
+        "--price",
+        "30100",
+        "--yes",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("query-orders permission needed"));
+    std::fs::remove_file(&config).unwrap();
+}
@@ -0,0 +1,496 @@
+#![allow(clippy::needless_return)]
+
+//! Local stand-in for the Kraken REST API. It serves the endpoints of the
+//! `kraken` crate with canned data, keeps the orders placed through it,
+//! checks `API-Sign` and nonces like the exchange and fails on demand.
+
+use chrono::Utc;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use kraken::signer::sign_postdata;
+use kraken::transport::Fixture;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+const DEFAULTS: &str = include_str!("../data/defaults.json");
+
+/// Failure returned instead of the response of an endpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Error of the `error` field, e.g. `EAPI:Invalid nonce`.
+    Error(String),
+    /// `EAPI:Rate limit exceeded`.
+    RateLimit,
+    /// Plain text response with this status, e.g. 502.
+    Status(u16),
+    /// Truncated JSON.
+    Malformed,
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    /// Parses `rate-limit`, `malformed`, a status code or an error string.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "rate-limit" => Ok(Fault::RateLimit),
+            "malformed" => Ok(Fault::Malformed),
+            _ if s.contains(':') => Ok(Fault::Error(s.to_string())),
+            _ => match s.parse::<u16>() {
+                Ok(status) if StatusCode::from_u16(status).is_ok() => Ok(Fault::Status(status)),
+                _ => Err(format!("invalid fault {}", s)),
+            },
+        };
+    }
+}
+
+/// A request received by the mock.
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub method: String,
+    pub path: String,
+    /// Query of public requests, form of private requests.
+    pub params: BTreeMap<String, String>,
+}
+
+struct Injected {
+    path: String,
+    fault: Fault,
+    remaining: usize,
+}
+
+struct State {
+    credentials: HashMap<String, Vec<u8>>,
+    nonces: HashMap<String, u64>,
+    /// `result` of each endpoint, keyed by path.
+    results: HashMap<String, Value>,
+    faults: Vec<Injected>,
+    received: Vec<Received>,
+    next_id: u64,
+}
+
+/// A mock server and its data. Clones share the same data so a test can
+/// change it while the server runs.
+#[derive(Clone)]
+pub struct Mock {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for Mock {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+type Outcome = Result<Value, String>;
+
+fn param<'a>(params: &'a BTreeMap<String, String>, name: &str) -> Result<&'a str, String> {
+    return params
+        .get(name)
+        .map(|v| v.as_str())
+        .ok_or_else(|| format!("EGeneral:Invalid arguments:{}", name));
+}
+
+fn ids(params: &BTreeMap<String, String>, name: &str) -> Vec<String> {
+    return params
+        .get(name)
+        .map(|v| v.split(',').map(|id| id.to_string()).collect())
+        .unwrap_or_default();
+}
+
+impl Mock {
+    /// A mock serving the data of `data/defaults.json`, without
+    /// credentials.
+    pub fn new() -> Self {
+        let defaults: Map<String, Value> = serde_json::from_str(DEFAULTS).unwrap();
+        let state = State {
+            credentials: HashMap::new(),
+            nonces: HashMap::new(),
+            results: defaults.into_iter().collect(),
+            faults: vec![],
+            received: vec![],
+            next_id: 0,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Accepts private requests signed with `secret` (decoded).
+    pub fn with_credential(self, key: &str, secret: &[u8]) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .credentials
+            .insert(key.to_string(), secret.to_vec());
+        return self;
+    }
+
+    /// Replaces the `result` of an endpoint. Open orders are kept in the
+    /// result of `/0/private/OpenOrders`.
+    pub fn set_result(&self, path: &str, result: Value) {
+        self.state
+            .lock()
+            .unwrap()
+            .results
+            .insert(path.to_string(), result);
+    }
+
+    /// Serves the responses of recorded fixtures, see `kraken::transport`.
+    /// Fixtures holding an error make their endpoint fail.
+    pub fn load_fixtures<P: AsRef<Path>>(&self, dir: P) -> Result<(), kraken::Error> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().map(|ext| ext == "json") != Some(true) {
+                continue;
+            }
+            let fixture: Fixture = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+            let errors = fixture.response["error"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default();
+            match errors.first().and_then(|e| e.as_str()) {
+                Some(error) => {
+                    self.fail(&fixture.path, Fault::Error(error.to_string()), usize::MAX)
+                }
+                None => self.set_result(&fixture.path, fixture.response["result"].clone()),
+            }
+        }
+        return Ok(());
+    }
+
+    /// Makes the next `times` requests to `path` fail, `*` matching any
+    /// path.
+    pub fn fail(&self, path: &str, fault: Fault, times: usize) {
+        self.state.lock().unwrap().faults.push(Injected {
+            path: path.to_string(),
+            fault,
+            remaining: times,
+        });
+    }
+
+    /// Requests received so far.
+    pub fn received(&self) -> Vec<Received> {
+        return self.state.lock().unwrap().received.clone();
+    }
+
+    /// Listens on `addr`, port 0 picking a free port, and returns the
+    /// address to pass to `kraken::set_base_url`.
+    pub async fn start(&self, addr: SocketAddr) -> Result<SocketAddr, hyper::Error> {
+        let mock = self.clone();
+        let make_service = make_service_fn(move |_| {
+            let mock = mock.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let mock = mock.clone();
+                    async move { Ok::<_, Infallible>(mock.handle(request).await) }
+                }))
+            }
+        });
+        let server = Server::try_bind(&addr)?.serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        return Ok(addr);
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let method = request.method().clone();
+        let path = request.uri().path().to_string();
+        let query = request.uri().query().unwrap_or("").to_string();
+        let key = request
+            .headers()
+            .get("API-Key")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let sign = request
+            .headers()
+            .get("API-Sign")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let body = hyper::body::to_bytes(request.into_body())
+            .await
+            .map(|b| String::from_utf8_lossy(&b).to_string())
+            .unwrap_or_default();
+        let private = path.starts_with("/0/private/");
+        let form = if private { &body } else { &query };
+        let params: BTreeMap<String, String> = serde_urlencoded::from_str(form).unwrap_or_default();
+
+        let mut state = self.state.lock().unwrap();
+        state.received.push(Received {
+            method: method.to_string(),
+            path: path.clone(),
+            params: params.clone(),
+        });
+        if let Some(fault) = state.take_fault(&path) {
+            return fault_response(fault);
+        }
+        let outcome = if private {
+            match state.authenticate(&method, &path, key, sign, &body, &params) {
+                Ok(()) => state.private(&path, &params),
+                Err(e) => Err(e),
+            }
+        } else {
+            state.public(&path)
+        };
+        let payload = match outcome {
+            Ok(result) => json!({"error": [], "result": result}),
+            Err(error) => json!({ "error": [error] }),
+        };
+        return Response::new(Body::from(payload.to_string()));
+    }
+}
+
+fn fault_response(fault: Fault) -> Response<Body> {
+    let (status, body) = match fault {
+        Fault::Error(error) => (200, json!({ "error": [error] }).to_string()),
+        Fault::RateLimit => (
+            200,
+            json!({"error": ["EAPI:Rate limit exceeded"]}).to_string(),
+        ),
+        Fault::Status(status) => (status, "upstream unavailable".to_string()),
+        Fault::Malformed => (200, "{\"error\":[],\"result\":{".to_string()),
+    };
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+    return response;
+}
+
+impl State {
+    fn take_fault(&mut self, path: &str) -> Option<Fault> {
+        let idx = self
+            .faults
+            .iter()
+            .position(|f| f.remaining > 0 && (f.path == path || f.path == "*"))?;
+        let injected = &mut self.faults[idx];
+        injected.remaining -= 1;
+        return Some(injected.fault.clone());
+    }
+
+    /// Checks the key, the signature and that the nonce increases, in the
+    /// order of the exchange.
+    fn authenticate(
+        &mut self,
+        method: &Method,
+        path: &str,
+        key: Option<String>,
+        sign: Option<String>,
+        body: &str,
+        params: &BTreeMap<String, String>,
+    ) -> Result<(), String> {
+        if method != Method::POST {
+            return Err("EGeneral:Invalid arguments".to_string());
+        }
+        let key = key.ok_or("EAPI:Invalid key")?;
+        let secret = self.credentials.get(&key).ok_or("EAPI:Invalid key")?;
+        let nonce = params.get("nonce").ok_or("EAPI:Invalid nonce")?;
+        let expected = sign_postdata(path, nonce, body, secret);
+        if sign.as_deref() != Some(expected.as_str()) {
+            return Err("EAPI:Invalid signature".to_string());
+        }
+        let nonce: u64 = nonce.parse().map_err(|_| "EAPI:Invalid nonce")?;
+        let last = self.nonces.entry(key).or_insert(0);
+        if nonce <= *last {
+            return Err("EAPI:Invalid nonce".to_string());
+        }
+        *last = nonce;
+        return Ok(());
+    }
+
+    fn canned(&self, path: &str) -> Outcome {
+        return self
+            .results
+            .get(path)
+            .cloned()
+            .ok_or_else(|| "EGeneral:Unknown method".to_string());
+    }
+
+    fn public(&self, path: &str) -> Outcome {
+        if path == "/0/public/Time" {
+            let now = Utc::now();
+            return Ok(json!({
+                "unixtime": now.timestamp(),
+                "rfc1123": now.format("%a, %d %b %y %H:%M:%S +0000").to_string(),
+            }));
+        }
+        return self.canned(path);
+    }
+
+    fn open_orders(&mut self) -> &mut Map<String, Value> {
+        let result = self
+            .results
+            .entry("/0/private/OpenOrders".to_string())
+            .or_insert_with(|| json!({ "open": {} }));
+        if !result["open"].is_object() {
+            result["open"] = json!({});
+        }
+        return result["open"].as_object_mut().unwrap();
+    }
+
+    /// Moves cancelled orders to the closed orders, where QueryOrders
+    /// still finds them.
+    fn cancel(&mut self, txid: &str, mut order: Value) {
+        order["status"] = json!("canceled");
+        order["closetm"] = json!(Utc::now().timestamp_millis() as f64 / 1000.0);
+        let result = self
+            .results
+            .entry("/0/private/ClosedOrders".to_string())
+            .or_insert_with(|| json!({ "closed": {} }));
+        if !result["closed"].is_object() {
+            result["closed"] = json!({});
+        }
+        result["closed"][txid] = order;
+    }
+
+    fn next_txid(&mut self) -> String {
+        self.next_id += 1;
+        return format!("OMOCK{:02}-{:05}-ORDER", self.next_id % 100, self.next_id);
+    }
+
+    /// Picks the entries of `ids` out of the maps found at `sources`.
+    fn lookup(&mut self, sources: &[(&str, &str)], ids: &[String]) -> Outcome {
+        let mut found = Map::new();
+        for (path, field) in sources {
+            let entries = match *path {
+                "/0/private/OpenOrders" => Some(self.open_orders().clone()),
+                _ => self
+                    .results
+                    .get(*path)
+                    .and_then(|r| r[*field].as_object().cloned()),
+            };
+            for (id, entry) in entries.unwrap_or_default() {
+                if ids.contains(&id) {
+                    found.insert(id, entry);
+                }
+            }
+        }
+        return Ok(Value::Object(found));
+    }
+
+    fn private(&mut self, path: &str, params: &BTreeMap<String, String>) -> Outcome {
+        return match path {
+            "/0/private/QueryOrders" => self.lookup(
+                &[
+                    ("/0/private/OpenOrders", "open"),
+                    ("/0/private/ClosedOrders", "closed"),
+                ],
+                &ids(params, "txid"),
+            ),
+            "/0/private/QueryTrades" => self.lookup(
+                &[("/0/private/TradesHistory", "trades")],
+                &ids(params, "txid"),
+            ),
+            "/0/private/QueryLedgers" => {
+                self.lookup(&[("/0/private/Ledgers", "ledger")], &ids(params, "id"))
+            }
+            "/0/private/AddOrder" => self.add_order(params),
+            "/0/private/EditOrder" => self.edit_order(params),
+            "/0/private/CancelOrder" => {
+                let txid = param(params, "txid")?;
+                match self.open_orders().remove(txid) {
+                    Some(order) => {
+                        self.cancel(txid, order);
+                        Ok(json!({ "count": 1 }))
+                    }
+                    None => Err("EOrder:Unknown order".to_string()),
+                }
+            }
+            "/0/private/CancelAll" => {
+                let orders = std::mem::take(self.open_orders());
+                let count = orders.len();
+                for (txid, order) in orders {
+                    self.cancel(&txid, order);
+                }
+                Ok(json!({ "count": count }))
+            }
+            _ => self.canned(path),
+        };
+    }
+
+    fn add_order(&mut self, params: &BTreeMap<String, String>) -> Outcome {
+        let pair = param(params, "pair")?;
+        let type_ = param(params, "type")?;
+        let ordertype = param(params, "ordertype")?;
+        let volume = param(params, "volume")?;
+        let price = params.get("price").map(|p| p.as_str()).unwrap_or("0");
+        let userref = match params.get("userref") {
+            Some(u) => u
+                .parse::<i32>()
+                .map_err(|_| "EGeneral:Invalid arguments:userref".to_string())?,
+            None => 0,
+        };
+        let description = match ordertype {
+            "market" => format!("{} {} {} @ market", type_, volume, pair),
+            _ => format!("{} {} {} @ {} {}", type_, volume, pair, ordertype, price),
+        };
+        if params.get("validate").map(|v| v == "true") == Some(true) {
+            return Ok(json!({ "descr": { "order": description } }));
+        }
+        let txid = self.next_txid();
+        let order = json!({
+            "refid": null,
+            "userref": userref,
+            "status": "open",
+            "opentm": Utc::now().timestamp_millis() as f64 / 1000.0,
+            "starttm": 0,
+            "expiretm": 0,
+            "descr": {
+                "pair": pair,
+                "type": type_,
+                "ordertype": ordertype,
+                "price": price,
+                "price2": params.get("price2").map(|p| p.as_str()).unwrap_or("0"),
+                "leverage": params.get("leverage").map(|p| p.as_str()).unwrap_or("none"),
+                "order": description,
+                "close": "",
+            },
+            "vol": volume,
+            "vol_exec": "0",
+            "cost": "0",
+            "fee": "0",
+            "price": "0",
+            "stopprice": "0",
+            "limitprice": "0",
+            "misc": "",
+            "oflags": params.get("oflags").map(|p| p.as_str()).unwrap_or(""),
+        });
+        self.open_orders().insert(txid.clone(), order);
+        return Ok(json!({ "descr": { "order": description }, "txid": [txid] }));
+    }
+
+    fn edit_order(&mut self, params: &BTreeMap<String, String>) -> Outcome {
+        let txid = param(params, "txid")?;
+        let mut order = self
+            .open_orders()
+            .get(txid)
+            .cloned()
+            .ok_or("EOrder:Unknown order")?;
+        let volume = params.get("volume").cloned();
+        let price = params.get("price").cloned();
+        if let Some(volume) = &volume {
+            order["vol"] = json!(volume);
+        }
+        if let Some(price) = &price {
+            order["descr"]["price"] = json!(price);
+        }
+        if params.get("validate").map(|v| v == "true") == Some(true) {
+            return Ok(json!({ "status": "ok", "originaltxid": txid }));
+        }
+        let new_txid = self.next_txid();
+        let orders = self.open_orders();
+        orders.remove(txid);
+        orders.insert(new_txid.clone(), order);
+        return Ok(json!({
+            "status": "ok",
+            "txid": new_txid,
+            "originaltxid": txid,
+            "volume": volume,
+            "price": price,
+            "orders_cancelled": 1,
+        }));
+    }
+}
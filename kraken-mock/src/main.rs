@@ -0,0 +1,88 @@
+#![allow(clippy::needless_return)]
+
+use anyhow::{anyhow, Context, Result};
+use clap::{App, Arg};
+use data_encoding::BASE64;
+use kraken_mock::{Fault, Mock};
+use std::net::SocketAddr;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let matches = App::new("kraken-mock")
+        .version("1.0.1")
+        .about("Local Kraken REST API serving canned data, for integration tests.")
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .takes_value(true)
+                .default_value("127.0.0.1:8080")
+                .help("address to listen on, port 0 picks a free port"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .env("KRAKEN_KEY")
+                .takes_value(true)
+                .help("key accepted for private endpoints"),
+        )
+        .arg(
+            Arg::with_name("secret")
+                .long("secret")
+                .env("KRAKEN_SECRET")
+                .takes_value(true)
+                .help("secret of the key, base64 encoded as given by Kraken"),
+        )
+        .arg(
+            Arg::with_name("fixtures")
+                .long("fixtures")
+                .takes_value(true)
+                .help("directory of fixtures recorded with kraken-cli --record, served instead of the canned data"),
+        )
+        .arg(
+            Arg::with_name("fail")
+                .long("fail")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("<path>=<fault> where fault is an error such as EAPI:Invalid nonce, rate-limit, malformed or a status code; path * matches any endpoint"),
+        )
+        .arg(
+            Arg::with_name("fail-count")
+                .long("fail-count")
+                .takes_value(true)
+                .help("number of requests failed by each --fail, all of them by default"),
+        )
+        .get_matches();
+
+    let mut mock = Mock::new();
+    if let (Some(key), Some(secret)) = (matches.value_of("key"), matches.value_of("secret")) {
+        let secret = BASE64
+            .decode(secret.as_bytes())
+            .context("cannot decode secret")?;
+        mock = mock.with_credential(key, &secret);
+    }
+    if let Some(dir) = matches.value_of("fixtures") {
+        mock.load_fixtures(dir)
+            .map_err(|e| anyhow!("cannot load fixtures of {} ({:?})", dir, e))?;
+    }
+    let count = match matches.value_of("fail-count") {
+        Some(count) => count.parse().context("invalid fail count")?,
+        None => usize::MAX,
+    };
+    for spec in matches.values_of("fail").unwrap_or_default() {
+        let (path, fault) = spec
+            .split_once('=')
+            .ok_or(anyhow!("invalid --fail {}, expected <path>=<fault>", spec))?;
+        let fault: Fault = fault.parse().map_err(|e: String| anyhow!(e))?;
+        mock.fail(path, fault, count);
+    }
+    let addr: SocketAddr = matches
+        .value_of("listen")
+        .unwrap()
+        .parse()
+        .context("invalid listen address")?;
+    let addr = mock.start(addr).await?;
+    eprintln!("listening on http://{}", addr);
+    tokio::signal::ctrl_c().await?;
+    return Ok(());
+}
@@ -0,0 +1,149 @@
+#![allow(clippy::needless_return)]
+
+use kraken::exchange::Live;
+use kraken::private::{self, NewOrder};
+use kraken::strategy::{OrderManager, OrderState, Update};
+use kraken::{public, Credential, Error};
+use kraken_mock::{Fault, Mock};
+use rust_decimal::Decimal;
+
+fn api_error<T: std::fmt::Debug>(result: Result<T, Error>) -> String {
+    return match result {
+        Err(Error::API(message)) => message,
+        other => panic!("expected an api error, got {:?}", other),
+    };
+}
+
+async fn orders(cred: &Credential) {
+    let order = NewOrder {
+        pair: "XBTUSD".to_string(),
+        type_: "buy".to_string(),
+        ordertype: "limit".to_string(),
+        volume: Decimal::new(5, 1),
+        price: Some(Decimal::from(29000)),
+        ..Default::default()
+    };
+    let validated = private::add_order(cred, &order, true).await.unwrap();
+    assert!(validated.txid.is_none());
+    let placed = private::add_order(cred, &order, false).await.unwrap();
+    let txid = placed.txid.unwrap().remove(0);
+    let open = private::open_orders(cred, None, None).await.unwrap();
+    assert_eq!(open.open.get(&txid).unwrap().vol, Decimal::new(5, 1));
+    let queried = private::query_orders(cred, None, None, &[&txid])
+        .await
+        .unwrap();
+    assert_eq!(queried.0.len(), 1);
+    assert_eq!(private::cancel_order(cred, &txid).await.unwrap().count, 1);
+    let error = api_error(private::cancel_order(cred, &txid).await);
+    assert_eq!(error, "EOrder:Unknown order");
+    // the canned open order is left.
+    assert_eq!(private::cancel_all(cred).await.unwrap().count, 1);
+}
+
+async fn order_manager(mock: &Mock, cred: &Credential) {
+    let live = Live::new(cred.clone());
+    let mut orders = OrderManager::starting_at(10);
+    let order = NewOrder {
+        pair: "XBTUSD".to_string(),
+        type_: "sell".to_string(),
+        ordertype: "limit".to_string(),
+        volume: Decimal::new(1, 1),
+        price: Some(Decimal::from(35000)),
+        ..Default::default()
+    };
+    let userref = orders.submit(&live, &order).await.unwrap();
+    assert!(matches!(
+        orders.drain()[..],
+        [Update::Placed { userref: 11, .. }]
+    ));
+    orders.reconcile(&live).await.unwrap();
+    assert!(orders.drain().is_empty());
+    orders.cancel(&live, userref).await.unwrap();
+    assert_eq!(orders.get(userref).unwrap().state, OrderState::Canceled);
+
+    // never reached the exchange.
+    mock.fail("/0/private/AddOrder", Fault::Status(502), 1);
+    assert!(orders.submit(&live, &order).await.is_err());
+    assert_eq!(orders.get(12).unwrap().state, OrderState::Pending);
+    orders.reconcile(&live).await.unwrap();
+    assert_eq!(orders.get(12).unwrap().state, OrderState::Rejected);
+
+    // placed but the answer was lost: found by its userref.
+    let order = NewOrder {
+        userref: Some(13),
+        ..order
+    };
+    mock.fail("/0/private/AddOrder", Fault::Status(502), 1);
+    assert!(orders.submit(&live, &order).await.is_err());
+    private::add_order(cred, &order, false).await.unwrap();
+    orders.reconcile(&live).await.unwrap();
+    assert_eq!(orders.get(13).unwrap().state, OrderState::Open);
+    assert!(orders.cancel(&live, 13).await.is_ok());
+}
+
+async fn authentication(url: &str) {
+    let cred = Credential::new("UNKNOWN", b"SECRET");
+    assert_eq!(api_error(private::balance(&cred).await), "EAPI:Invalid key");
+    let cred = Credential::new("KEY", b"WRONG");
+    assert_eq!(
+        api_error(private::balance(&cred).await),
+        "EAPI:Invalid signature"
+    );
+
+    // a nonce lower than the last one used by the key.
+    let path = "/0/private/Balance";
+    let args = [("nonce", "1")];
+    let body = serde_urlencoded::to_string(args).unwrap();
+    let response = reqwest::Client::new()
+        .post(format!("{}{}", url, path))
+        .header("API-Key", "KEY")
+        .header("API-Sign", kraken::signer::sign(path, &args, b"SECRET"))
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(response.contains("EAPI:Invalid nonce"));
+}
+
+async fn faults(mock: &Mock) {
+    mock.fail("/0/public/Ticker", Fault::RateLimit, 1);
+    let error = api_error(public::ticker("XBTUSD").await);
+    assert_eq!(error, "EAPI:Rate limit exceeded");
+    assert!(public::ticker("XBTUSD").await.is_ok());
+
+    mock.fail("*", Fault::Status(502), 1);
+    assert!(matches!(public::time().await, Err(Error::JSON(_))));
+    mock.fail("/0/public/Time", Fault::Malformed, 1);
+    assert!(matches!(public::time().await, Err(Error::JSON(_))));
+    mock.fail(
+        "/0/public/Time",
+        "EGeneral:Internal error".parse().unwrap(),
+        1,
+    );
+    assert_eq!(api_error(public::time().await), "EGeneral:Internal error");
+    assert!(public::time().await.is_ok());
+}
+
+#[tokio::test]
+async fn mock_test() {
+    let mock = Mock::new().with_credential("KEY", b"SECRET");
+    let addr = mock.start(([127, 0, 0, 1], 0).into()).await.unwrap();
+    let url = format!("http://{}", addr);
+    kraken::set_base_url(&url);
+    let cred = Credential::new("KEY", b"SECRET");
+
+    let ticker = public::ticker("XBTUSD").await.unwrap();
+    assert!(ticker.0.contains_key("XXBTZUSD"));
+    assert_eq!(private::balance(&cred).await.unwrap().0.len(), 3);
+    orders(&cred).await;
+    order_manager(&mock, &cred).await;
+    authentication(&url).await;
+    faults(&mock).await;
+
+    let received = mock.received();
+    assert_eq!(received[0].path, "/0/public/Ticker");
+    assert_eq!(received[0].params.get("pair").unwrap(), "XBTUSD");
+}
@@ -0,0 +1,447 @@
+use crate::registry::AssetRegistry;
+use crate::{private, public, Credential, Error};
+use chrono::Utc;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Alert rules read from a TOML file:
+///
+/// ```toml
+/// interval = 30
+///
+/// [[rule]]
+/// name = "btc above 70k"
+/// kind = "price"
+/// pair = "XBTUSD"
+/// above = 70000
+/// actions = [{ type = "log" }, { type = "webhook", url = "http://localhost:8080/alerts" }]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Seconds between two evaluations.
+    pub interval: Option<u64>,
+    #[serde(rename = "rule", default)]
+    pub rules: Vec<Rule>,
+}
+
+impl Config {
+    pub fn from_toml(content: &str) -> Result<Self, Error> {
+        return toml::from_str(content).map_err(Error::storage);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    #[serde(flatten)]
+    pub condition: Condition,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Condition {
+    /// Last traded price above and/or below a level.
+    Price {
+        pair: String,
+        above: Option<Decimal>,
+        below: Option<Decimal>,
+    },
+    /// Difference between the best ask and the best bid wider than a
+    /// value in the quote currency.
+    Spread { pair: String, wider_than: Decimal },
+    /// Balance of an asset below a threshold.
+    Balance { asset: String, below: Decimal },
+    /// Order closed after being filled.
+    OrderFilled { txid: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    /// Prints the alert on stdout.
+    Log,
+    /// Runs a shell command with `KRAKEN_ALERT_RULE` and
+    /// `KRAKEN_ALERT_MESSAGE` set.
+    Command { command: String },
+    /// Posts the alert as JSON.
+    Webhook { url: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub rule: String,
+    pub message: String,
+    pub time: i64,
+}
+
+/// Values the conditions are evaluated against, keyed by the names
+/// used in the rules.
+#[derive(Debug, Default)]
+pub struct Observations {
+    pub prices: HashMap<String, Decimal>,
+    /// (bid, ask) of the last spread entry.
+    pub spreads: HashMap<String, (Decimal, Decimal)>,
+    pub balances: HashMap<String, Decimal>,
+    pub order_status: HashMap<String, String>,
+    /// Fetches that failed, with the names of the rules left without a
+    /// value for this round.
+    pub errors: Vec<(String, Error)>,
+}
+
+/// Part of a condition that holds: a price condition has two, the
+/// others a single one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Above,
+    Below,
+    Holds,
+}
+
+impl Condition {
+    /// Returns `None` when the value is missing, otherwise the part of
+    /// the condition that holds, if any, with its description.
+    fn check(&self, observations: &Observations) -> Option<Option<(Side, String)>> {
+        return match self {
+            Condition::Price { pair, above, below } => {
+                let price = observations.prices.get(pair)?;
+                Some(match (above, below) {
+                    (Some(above), _) if price > above => Some((
+                        Side::Above,
+                        format!("{} at {} is above {}", pair, price, above),
+                    )),
+                    (_, Some(below)) if price < below => Some((
+                        Side::Below,
+                        format!("{} at {} is below {}", pair, price, below),
+                    )),
+                    _ => None,
+                })
+            }
+            Condition::Spread { pair, wider_than } => {
+                let (bid, ask) = observations.spreads.get(pair)?;
+                let spread = ask - bid;
+                Some((spread > *wider_than).then(|| {
+                    let message =
+                        format!("{} spread {} is wider than {}", pair, spread, wider_than);
+                    (Side::Holds, message)
+                }))
+            }
+            Condition::Balance { asset, below } => {
+                let balance = observations.balances.get(asset)?;
+                Some((balance < below).then(|| {
+                    let message = format!("{} balance {} is below {}", asset, balance, below);
+                    (Side::Holds, message)
+                }))
+            }
+            Condition::OrderFilled { txid } => {
+                let status = observations.order_status.get(txid)?;
+                Some(
+                    (status == "closed")
+                        .then(|| (Side::Holds, format!("order {} is filled", txid))),
+                )
+            }
+        };
+    }
+
+    /// Returns a description of the condition when it holds, `None` when
+    /// it does not or when the value is missing.
+    pub fn evaluate(&self, observations: &Observations) -> Option<String> {
+        return self
+            .check(observations)
+            .flatten()
+            .map(|(_, message)| message);
+    }
+}
+
+/// Evaluates rules and raises an alert when a condition starts to hold.
+/// A condition that keeps holding does not raise again until it stopped
+/// holding once, so a price crossing a level alerts once per crossing.
+/// A price moving from above its upper level to below its lower one is
+/// a new crossing. Rules whose value is missing keep their state.
+pub struct Monitor {
+    rules: Vec<Rule>,
+    active: Vec<Option<Side>>,
+}
+
+impl Monitor {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        let active = vec![None; rules.len()];
+        Self { rules, active }
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        return &self.rules;
+    }
+
+    /// Returns the alerts raised by `observations` with the index of
+    /// their rule.
+    pub fn evaluate(&mut self, observations: &Observations) -> Vec<(usize, Alert)> {
+        let time = Utc::now().timestamp();
+        let mut alerts = vec![];
+        for (idx, rule) in self.rules.iter().enumerate() {
+            let (side, message) = match rule.condition.check(observations) {
+                // a failed fetch is no evidence the condition stopped.
+                None => continue,
+                Some(None) => {
+                    self.active[idx] = None;
+                    continue;
+                }
+                Some(Some(held)) => held,
+            };
+            if self.active[idx].replace(side) != Some(side) {
+                alerts.push((
+                    idx,
+                    Alert {
+                        rule: rule.name.clone(),
+                        message,
+                        time,
+                    },
+                ));
+            }
+        }
+        return alerts;
+    }
+
+    /// Names of the rules matching `kind`, joined to report a failed fetch.
+    fn names(&self, kind: fn(&Condition) -> bool) -> String {
+        let names: Vec<&str> = self
+            .rules
+            .iter()
+            .filter(|rule| kind(&rule.condition))
+            .map(|rule| rule.name.as_str())
+            .collect();
+        return names.join(", ");
+    }
+
+    /// Fetches the values needed by the rules. Balance and order rules
+    /// are skipped without credentials. A failed fetch only leaves its
+    /// rules without a value, it is reported in `errors`.
+    pub async fn observe(&self, cred: Option<&Credential>) -> Result<Observations, Error> {
+        let registry = AssetRegistry::shared().await?;
+        let mut observations = Observations::default();
+        let mut price_pairs = vec![];
+        let mut txids = vec![];
+        let mut balances = false;
+        for rule in self.rules.iter() {
+            match &rule.condition {
+                Condition::Price { pair, .. } => price_pairs.push(pair.as_str()),
+                Condition::Spread { pair, .. } => {
+                    let spreads = match public::spread(pair, None).await {
+                        Ok(spreads) => spreads,
+                        Err(e) => {
+                            observations.errors.push((rule.name.clone(), e));
+                            continue;
+                        }
+                    };
+                    let last = registry.get_pair(&spreads.0, pair).and_then(|s| match s {
+                        public::Spread::Pair(data) => data.0.last(),
+                        public::Spread::Last(_) => None,
+                    });
+                    if let Some((_, bid, ask)) = last {
+                        observations.spreads.insert(pair.clone(), (*bid, *ask));
+                    }
+                }
+                Condition::Balance { .. } => balances = true,
+                Condition::OrderFilled { txid } => txids.push(txid.as_str()),
+            }
+        }
+        if !price_pairs.is_empty() {
+            match public::ticker(&price_pairs.join(",")).await {
+                Ok(tickers) => {
+                    for pair in price_pairs {
+                        if let Some(info) = tickers.get(registry, pair) {
+                            observations.prices.insert(pair.to_string(), info.c.0);
+                        }
+                    }
+                }
+                Err(e) => {
+                    let names = self.names(|c| matches!(c, Condition::Price { .. }));
+                    observations.errors.push((names, e));
+                }
+            }
+        }
+        let cred = match cred {
+            Some(cred) => cred,
+            None => return Ok(observations),
+        };
+        if balances {
+            match private::balance(cred).await {
+                Ok(response) => {
+                    for rule in self.rules.iter() {
+                        if let Condition::Balance { asset, .. } = &rule.condition {
+                            // assets without balance are not listed.
+                            let balance =
+                                response.get(registry, asset).copied().unwrap_or_default();
+                            observations.balances.insert(asset.clone(), balance);
+                        }
+                    }
+                }
+                Err(e) => {
+                    let names = self.names(|c| matches!(c, Condition::Balance { .. }));
+                    observations.errors.push((names, e));
+                }
+            }
+        }
+        if !txids.is_empty() {
+            match private::query_orders(cred, None, None, &txids).await {
+                Ok(orders) => {
+                    for (txid, order) in orders.0 {
+                        observations.order_status.insert(txid, order.status);
+                    }
+                }
+                Err(e) => {
+                    let names = self.names(|c| matches!(c, Condition::OrderFilled { .. }));
+                    observations.errors.push((names, e));
+                }
+            }
+        }
+        return Ok(observations);
+    }
+}
+
+/// Runs an action for an alert.
+pub async fn fire(action: &Action, alert: &Alert) -> Result<(), Error> {
+    match action {
+        Action::Log => println!("{} [{}] {}", alert.time, alert.rule, alert.message),
+        Action::Command { command } => {
+            let status = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("KRAKEN_ALERT_RULE", &alert.rule)
+                .env("KRAKEN_ALERT_MESSAGE", &alert.message)
+                .status()
+                .await?;
+            if !status.success() {
+                return Err(Error::Command(format!(
+                    "{} exited with {}",
+                    command, status
+                )));
+            }
+        }
+        Action::Webhook { url } => {
+            reqwest::Client::new()
+                .post(url)
+                .json(alert)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Condition, Monitor, Observations};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn config_test() {
+        let config = Config::from_toml(
+            r#"
+            interval = 10
+
+            [[rule]]
+            name = "btc"
+            kind = "price"
+            pair = "XBTUSD"
+            above = 70000
+            actions = [{ type = "log" }, { type = "command", command = "true" }]
+
+            [[rule]]
+            name = "filled"
+            kind = "order_filled"
+            txid = "OABC"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.interval, Some(10));
+        assert_eq!(config.rules.len(), 2);
+        assert_eq!(
+            config.rules[0].condition,
+            Condition::Price {
+                pair: "XBTUSD".to_string(),
+                above: Some(Decimal::from(70000)),
+                below: None,
+            }
+        );
+        assert_eq!(config.rules[0].actions.len(), 2);
+        assert!(config.rules[1].actions.is_empty());
+    }
+
+    #[test]
+    fn balance_test() {
+        let condition = Condition::Balance {
+            asset: "ZUSD".to_string(),
+            below: Decimal::from(100),
+        };
+        // no balance is observed without credentials.
+        let mut observations = Observations::default();
+        assert_eq!(condition.evaluate(&observations), None);
+        observations
+            .balances
+            .insert("ZUSD".to_string(), Decimal::ZERO);
+        assert!(condition.evaluate(&observations).is_some());
+    }
+
+    #[test]
+    fn crossing_test() {
+        let config = Config::from_toml(
+            r#"
+            [[rule]]
+            name = "btc"
+            kind = "price"
+            pair = "XBTUSD"
+            above = 100
+            "#,
+        )
+        .unwrap();
+        let mut monitor = Monitor::new(config.rules);
+        let mut observations = Observations::default();
+        let mut alerts_at = |price: i64| {
+            observations
+                .prices
+                .insert("XBTUSD".to_string(), Decimal::from(price));
+            monitor.evaluate(&observations).len()
+        };
+        assert_eq!(alerts_at(90), 0);
+        assert_eq!(alerts_at(110), 1);
+        assert_eq!(alerts_at(120), 0);
+        assert_eq!(alerts_at(95), 0);
+        assert_eq!(alerts_at(105), 1);
+    }
+
+    #[test]
+    fn missing_value_test() {
+        let config = Config::from_toml(
+            r#"
+            [[rule]]
+            name = "btc"
+            kind = "price"
+            pair = "XBTUSD"
+            above = 100
+            below = 50
+            "#,
+        )
+        .unwrap();
+        let mut monitor = Monitor::new(config.rules);
+        let mut alerts_at = |price: Option<i64>| {
+            let mut observations = Observations::default();
+            if let Some(price) = price {
+                observations
+                    .prices
+                    .insert("XBTUSD".to_string(), Decimal::from(price));
+            }
+            return monitor.evaluate(&observations).len();
+        };
+        assert_eq!(alerts_at(Some(110)), 1);
+        // a failed ticker fetch does not end the crossing.
+        assert_eq!(alerts_at(None), 0);
+        assert_eq!(alerts_at(Some(115)), 0);
+        // from one side to the other between two polls.
+        assert_eq!(alerts_at(Some(40)), 1);
+        assert_eq!(alerts_at(Some(110)), 1);
+    }
+}
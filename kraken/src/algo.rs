@@ -0,0 +1,504 @@
+use crate::backfill::Candle;
+use crate::exchange::{self, Exchange, MarketEvent};
+use crate::private::NewOrder;
+use crate::public::{AssetPair, OrderBook};
+use crate::strategy::{OrderManager, Update};
+use crate::Error;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const SECONDS_PER_DAY: u64 = 24 * 3600;
+
+/// Volume released once `at` seconds have elapsed since the start.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Slice {
+    pub at: u64,
+    pub volume: Decimal,
+}
+
+/// Gives what rounding left out to the last slice.
+fn balance(mut slices: Vec<Slice>, volume: Decimal) -> Vec<Slice> {
+    let total: Decimal = slices.iter().map(|s| s.volume).sum();
+    if let Some(last) = slices.last_mut() {
+        last.volume += volume - total;
+    }
+    return slices;
+}
+
+/// Splits `volume` into `count` equal slices over `duration` seconds.
+pub fn twap(volume: Decimal, duration: u64, count: u64) -> Vec<Slice> {
+    let count = std::cmp::max(count, 1);
+    let size = volume / Decimal::from(count);
+    let slices = (0..count)
+        .map(|i| Slice {
+            at: i * duration / count,
+            volume: size,
+        })
+        .collect();
+    return balance(slices, volume);
+}
+
+/// Splits `volume` over `duration` seconds from `start` (a unix time) into
+/// one slice per candle, weighted by the average volume of the candles
+/// at the same time of day. Falls back to equal slices when the candles
+/// do not cover the period.
+pub fn vwap(volume: Decimal, start: u64, duration: u64, candles: &[Candle]) -> Vec<Slice> {
+    let width = match candles.windows(2).map(|w| w[1].time - w[0].time).min() {
+        Some(width) if width > 0 => width,
+        _ => return twap(volume, duration, 1),
+    };
+    let bucket = |time: u64| (time % SECONDS_PER_DAY) / width;
+    let mut profile: HashMap<u64, (Decimal, u64)> = HashMap::new();
+    for candle in candles {
+        let entry = profile.entry(bucket(candle.time)).or_default();
+        entry.0 += candle.volume;
+        entry.1 += 1;
+    }
+    let count = std::cmp::max(duration / width, 1);
+    let weights: Vec<Decimal> = (0..count)
+        .map(|i| match profile.get(&bucket(start + i * width)) {
+            Some((volume, n)) => volume / Decimal::from(*n),
+            None => Decimal::ZERO,
+        })
+        .collect();
+    let total: Decimal = weights.iter().sum();
+    if total.is_zero() {
+        return twap(volume, duration, count);
+    }
+    let slices = weights
+        .iter()
+        .enumerate()
+        .map(|(i, weight)| Slice {
+            at: i as u64 * width,
+            volume: volume * weight / total,
+        })
+        .collect();
+    return balance(slices, volume);
+}
+
+/// Releases the whole volume at once, to be used with a visible size.
+pub fn iceberg(volume: Decimal) -> Vec<Slice> {
+    return vec![Slice { at: 0, volume }];
+}
+
+/// Executes a parent order as child limit orders following a schedule.
+/// A single child rests at a time at the best price of its side of the
+/// book and is replaced when the book moves or more volume is due. After
+/// the deadline the rest is priced to cross the spread. Children are
+/// capped by the visible size and rounded to the precision of the pair;
+/// volume below the order minimum waits for the next slice, and a
+/// remainder below it is left unexecuted.
+pub struct Execution<'a> {
+    pair: &'a AssetPair,
+    side: String,
+    schedule: Vec<Slice>,
+    volume: Decimal,
+    visible: Option<Decimal>,
+    limit: Option<Decimal>,
+    deadline: Option<u64>,
+    interval: Duration,
+    orders: OrderManager,
+    child: Option<(i32, Decimal)>,
+    children: usize,
+    executed: Decimal,
+    cost: Decimal,
+    fee: Decimal,
+}
+
+impl<'a> Execution<'a> {
+    pub fn new(pair: &'a AssetPair, side: &str, schedule: Vec<Slice>) -> Self {
+        Self {
+            pair,
+            side: side.to_string(),
+            volume: schedule.iter().map(|s| s.volume).sum(),
+            schedule,
+            visible: None,
+            limit: None,
+            deadline: None,
+            interval: Duration::from_secs(10),
+            orders: OrderManager::new(),
+            child: None,
+            children: 0,
+            executed: Decimal::ZERO,
+            cost: Decimal::ZERO,
+            fee: Decimal::ZERO,
+        }
+    }
+
+    /// Largest child order, for iceberg orders.
+    pub fn with_visible(mut self, size: Decimal) -> Self {
+        self.visible = Some(size);
+        return self;
+    }
+
+    /// Worst price children are placed at.
+    pub fn with_limit(mut self, price: Decimal) -> Self {
+        self.limit = Some(price);
+        return self;
+    }
+
+    /// Seconds after which children cross the spread. Without a deadline
+    /// they stay passive.
+    pub fn with_deadline(mut self, seconds: u64) -> Self {
+        self.deadline = Some(seconds);
+        return self;
+    }
+
+    /// Time between two checks of the book by `run`, 10 seconds by default.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        return self;
+    }
+
+    pub fn with_orders(mut self, orders: OrderManager) -> Self {
+        self.orders = orders;
+        return self;
+    }
+
+    pub fn volume(&self) -> Decimal {
+        return self.volume;
+    }
+
+    pub fn executed(&self) -> Decimal {
+        return self.executed;
+    }
+
+    pub fn cost(&self) -> Decimal {
+        return self.cost;
+    }
+
+    pub fn fee(&self) -> Decimal {
+        return self.fee;
+    }
+
+    pub fn average_price(&self) -> Option<Decimal> {
+        if self.executed.is_zero() {
+            return None;
+        }
+        return Some(self.cost / self.executed);
+    }
+
+    /// Number of child orders placed.
+    pub fn children(&self) -> usize {
+        return self.children;
+    }
+
+    /// Volume released once `elapsed` seconds have passed.
+    pub fn target(&self, elapsed: u64) -> Decimal {
+        return self
+            .schedule
+            .iter()
+            .filter(|s| s.at <= elapsed)
+            .map(|s| s.volume)
+            .sum();
+    }
+
+    pub fn is_done(&self) -> bool {
+        return self.lot(self.volume - self.executed) < self.pair.ordermin;
+    }
+
+    fn lot(&self, volume: Decimal) -> Decimal {
+        return volume
+            .round_dp_with_strategy(self.pair.lot_decimals as u32, RoundingStrategy::ToZero);
+    }
+
+    /// Counts the fills of the children and forgets the closed one.
+    fn absorb(&mut self) {
+        for update in self.orders.drain() {
+            match update {
+                Update::Filled {
+                    volume, cost, fee, ..
+                } => {
+                    self.executed += volume;
+                    self.cost += cost;
+                    self.fee += fee;
+                }
+                Update::Done { userref, .. } | Update::Rejected { userref, .. }
+                    if self.child.map(|c| c.0) == Some(userref) =>
+                {
+                    self.child = None;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Cancels the resting child, if any.
+    pub async fn cancel(&mut self, exchange: &dyn Exchange) -> Result<(), Error> {
+        let userref = match self.child {
+            Some((userref, _)) => userref,
+            None => return Ok(()),
+        };
+        self.cancel_child(exchange, userref).await?;
+        self.absorb();
+        self.child = None;
+        return Ok(());
+    }
+
+    /// Cancels every child still open, once reconciled. Stops an
+    /// interrupted execution, which may have placed a child without
+    /// knowing it.
+    pub async fn abort(&mut self, exchange: &dyn Exchange) -> Result<(), Error> {
+        self.orders.reconcile(exchange).await?;
+        let open: Vec<i32> = self
+            .orders
+            .active()
+            .iter()
+            .filter(|t| t.txid.is_some())
+            .map(|t| t.userref)
+            .collect();
+        for userref in open {
+            self.cancel_child(exchange, userref).await?;
+        }
+        self.absorb();
+        self.child = None;
+        return Ok(());
+    }
+
+    async fn cancel_child(&mut self, exchange: &dyn Exchange, userref: i32) -> Result<(), Error> {
+        match self.orders.cancel(exchange, userref).await {
+            Ok(()) => {}
+            // closed meanwhile, the reconciliation tells how; otherwise
+            // the child may still rest on the book.
+            Err(Error::API(e)) => {
+                self.orders.reconcile(exchange).await?;
+                if !matches!(self.orders.get(userref), Some(t) if t.state.is_final()) {
+                    return Err(Error::API(e));
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        return Ok(());
+    }
+
+    /// Updates the children for the book seen `elapsed` seconds after
+    /// the start.
+    pub async fn step(
+        &mut self,
+        exchange: &dyn Exchange,
+        elapsed: u64,
+        book: &OrderBook,
+    ) -> Result<(), Error> {
+        self.orders.reconcile(exchange).await?;
+        self.absorb();
+        if self.is_done() {
+            return self.cancel(exchange).await;
+        }
+        let buy = self.side == "buy";
+        let late = matches!(self.deadline, Some(deadline) if elapsed >= deadline);
+        let (bid, ask) = (book.bids.first(), book.asks.first());
+        let price = match (buy, late) {
+            (true, false) | (false, true) => bid,
+            (true, true) | (false, false) => ask,
+        };
+        let mut price = match price {
+            Some(level) => level.0,
+            None => return Ok(()),
+        };
+        let rounding = if buy {
+            RoundingStrategy::ToZero
+        } else {
+            RoundingStrategy::AwayFromZero
+        };
+        if let Some(limit) = self.limit {
+            price = if buy {
+                std::cmp::min(price, limit)
+            } else {
+                std::cmp::max(price, limit)
+            };
+        }
+        price = price.round_dp_with_strategy(self.pair.pair_decimals as u32, rounding);
+
+        let due = match late {
+            true => self.volume,
+            false => self.target(elapsed),
+        } - self.executed;
+        let size = match self.visible {
+            Some(visible) => std::cmp::min(due, visible),
+            None => due,
+        };
+        let size = self.lot(size);
+        if let Some((userref, current)) = self.child {
+            let resting = self
+                .orders
+                .get(userref)
+                .map(|t| t.order.volume - t.vol_exec)
+                .unwrap_or_default();
+            if current == price && resting >= size {
+                return Ok(());
+            }
+            self.cancel(exchange).await?;
+        }
+        // fills seen while cancelling lower what is due.
+        let size = std::cmp::min(size, self.lot(self.volume - self.executed));
+        if size < self.pair.ordermin {
+            return Ok(());
+        }
+        let order = NewOrder {
+            pair: self.pair.altname.clone(),
+            type_: self.side.clone(),
+            ordertype: "limit".to_string(),
+            volume: size,
+            price: Some(price),
+            ..Default::default()
+        };
+        let userref = self.orders.submit(exchange, &order).await?;
+        self.child = Some((userref, price));
+        self.children += 1;
+        self.absorb();
+        return Ok(());
+    }
+
+    /// Fetches the book, shows it to the exchange and steps.
+    async fn round(
+        &mut self,
+        exchange: &dyn Exchange,
+        since: &mut Option<i64>,
+        start: Instant,
+    ) -> Result<(), Error> {
+        let (events, last) = exchange::fetch(&self.pair.altname, *since).await?;
+        *since = last.or(*since);
+        let mut book = None;
+        for event in events {
+            exchange.observe(&event)?;
+            if let MarketEvent::Book { book: b, .. } = event {
+                book = Some(b);
+            }
+        }
+        if let Some(book) = book {
+            self.step(exchange, start.elapsed().as_secs(), &book)
+                .await?;
+        }
+        return Ok(());
+    }
+
+    /// Steps every interval until done, calling `progress` after each
+    /// step. Network errors are retried at the next step.
+    pub async fn run<F>(&mut self, exchange: &dyn Exchange, mut progress: F) -> Result<(), Error>
+    where
+        F: FnMut(&Execution) + Send,
+    {
+        let start = Instant::now();
+        let mut since = None;
+        loop {
+            match self.round(exchange, &mut since, start).await {
+                Ok(()) | Err(Error::Request(_)) => {}
+                Err(e) => return Err(e),
+            }
+            progress(self);
+            if self.is_done() {
+                return Ok(());
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{iceberg, twap, vwap, Execution};
+    use crate::exchange::{Exchange, MarketEvent};
+    use crate::strategy::OrderManager;
+    use crate::testing::{book, candle, d, order, pair, paper};
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn schedule_test() {
+        let slices = twap(d("1"), 90, 3);
+        assert_eq!(
+            slices.iter().map(|s| s.at).collect::<Vec<_>>(),
+            vec![0, 30, 60]
+        );
+        assert_eq!(slices.iter().map(|s| s.volume).sum::<Decimal>(), d("1"));
+
+        // two days of 1 hour candles, busier at 01:00.
+        let mut candles = vec![];
+        for day in 0..2 {
+            candles.push(candle(day * 86400, "100", "100", "100", "100", "1"));
+            candles.push(candle(day * 86400 + 3600, "100", "100", "100", "100", "3"));
+        }
+        let slices = vwap(d("2"), 86400 * 7, 7200, &candles);
+        assert_eq!(slices.len(), 2);
+        assert_eq!((slices[0].at, slices[0].volume), (0, d("0.5")));
+        assert_eq!((slices[1].at, slices[1].volume), (3600, d("1.5")));
+        assert_eq!(vwap(d("2"), 86400 * 7 + 7200, 7200, &candles).len(), 2);
+        assert_eq!(iceberg(d("2"))[0].volume, d("2"));
+    }
+
+    #[tokio::test]
+    async fn execution_test() {
+        let pair = pair();
+        let paper = paper();
+        let mut execution = Execution::new(&pair, "buy", twap(d("1"), 100, 2)).with_deadline(100);
+
+        paper.update_book("XBTUSD", &book("99", "100")).unwrap();
+        execution.step(&paper, 0, &book("99", "100")).await.unwrap();
+        assert_eq!(paper.open_orders().await.unwrap().open.len(), 1);
+        let trades = MarketEvent::Trades {
+            pair: "XXBTZUSD".to_string(),
+            trades: vec![(d("99"), d("0.3"), 0.0)],
+        };
+        paper.observe(&trades).unwrap();
+        execution
+            .step(&paper, 10, &book("99", "100"))
+            .await
+            .unwrap();
+        assert_eq!(execution.executed(), d("0.3"));
+        assert_eq!(execution.children(), 1);
+
+        // the book moved: re-priced with what is left of the slice.
+        execution
+            .step(&paper, 20, &book("99.5", "100.5"))
+            .await
+            .unwrap();
+        let open = paper.open_orders().await.unwrap().open;
+        let child = open.values().next().unwrap();
+        assert_eq!((child.vol, child.descr.price), (d("0.2"), d("99.5")));
+
+        // the second slice grows the child.
+        execution
+            .step(&paper, 50, &book("99.5", "100.5"))
+            .await
+            .unwrap();
+        let open = paper.open_orders().await.unwrap().open;
+        assert_eq!(open.values().next().unwrap().vol, d("0.7"));
+        assert_eq!(execution.children(), 3);
+
+        // past the deadline, crosses the spread.
+        paper.update_book("XBTUSD", &book("99.5", "100.5")).unwrap();
+        execution
+            .step(&paper, 100, &book("99.5", "100.5"))
+            .await
+            .unwrap();
+        execution
+            .step(&paper, 110, &book("99.5", "100.5"))
+            .await
+            .unwrap();
+        assert!(execution.is_done());
+        assert_eq!(execution.executed(), d("1"));
+        assert_eq!(execution.average_price(), Some(d("100.05")));
+        assert_eq!(paper.account().balances["XXBT"], d("1"));
+    }
+
+    #[tokio::test]
+    async fn abort_test() {
+        let pair = pair();
+        let paper = paper();
+        paper.update_book("XBTUSD", &book("99", "100")).unwrap();
+        // a child placed by an interrupted step, unknown to the execution.
+        let mut orders = OrderManager::new();
+        orders
+            .submit(&paper, &order("buy", "limit", "0.5", Some("99")))
+            .await
+            .unwrap();
+        let mut execution = Execution::new(&pair, "buy", iceberg(d("1"))).with_orders(orders);
+        execution.step(&paper, 0, &book("99", "100")).await.unwrap();
+        assert_eq!(paper.open_orders().await.unwrap().open.len(), 2);
+
+        execution.abort(&paper).await.unwrap();
+        assert!(paper.open_orders().await.unwrap().open.is_empty());
+        execution.cancel(&paper).await.unwrap();
+    }
+}
@@ -1,6 +1,11 @@
 mod request;
+pub use request::{set_base_url, set_observer};
+pub use transport::set_transport;
 pub use request::Credential;
 pub use request::Error;
+pub(crate) use request::retry_throttled;
 
 pub mod private;
 pub mod public;
+pub mod signer;
+pub mod transport;
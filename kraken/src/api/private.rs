@@ -1,28 +1,72 @@
 use super::request::*;
+use crate::registry::AssetRegistry;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use serde_with::{serde_as, DefaultOnError};
+use serde_with::{serde_as, DefaultOnError, DisplayFromStr, PickFirst};
 use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+/// Delay applied before the first retry of a call rejected by the call
+/// rate limiter.
+const RATE_LIMIT_DELAY: Duration = Duration::from_secs(5);
+
+/// Fetches pages from `fetch`, given the offset of the first row, until
+/// `count` rows were received or a page adds no new id. The offset
+/// advances by the rows received since pages may overlap when new rows
+/// are inserted during the download.
+async fn paginate<T, F, Fut>(mut fetch: F) -> Result<HashMap<String, T>, Error>
+where
+    F: FnMut(i64) -> Fut,
+    Fut: Future<Output = Result<(HashMap<String, T>, u64), Error>>,
+{
+    let mut entries = HashMap::new();
+    let mut ofs = 0;
+    loop {
+        let (page, count) = retry_throttled(RATE_LIMIT_DELAY, || fetch(ofs)).await?;
+        let known = entries.len();
+        ofs += page.len() as i64;
+        entries.extend(page);
+        if entries.len() == known || ofs as u64 >= count {
+            break;
+        }
+    }
+    return Ok(entries);
+}
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct BalanceResponse(HashMap<String, Decimal>);
+pub struct BalanceResponse(pub HashMap<String, Decimal>);
+
+impl BalanceResponse {
+    /// Returns the balance of an asset under any of its spellings.
+    pub fn get(&self, registry: &AssetRegistry, asset: &str) -> Option<&Decimal> {
+        return registry.get_asset(&self.0, asset);
+    }
+}
 
 pub async fn balance(cred: &Credential) -> Result<BalanceResponse, Error> {
-    let response = private_request(&cred, "/0/private/Balance", &[]).await?;
+    let response = private_request(cred, "/0/private/Balance", &[]).await?;
     return load_response(&response);
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BalanceEx {
-    balance: Decimal,
-    hold_trade: Decimal,
+    pub balance: Decimal,
+    pub hold_trade: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct BalanceExResponse(HashMap<String, BalanceEx>);
+pub struct BalanceExResponse(pub HashMap<String, BalanceEx>);
+
+impl BalanceExResponse {
+    /// Returns the balance of an asset under any of its spellings.
+    pub fn get(&self, registry: &AssetRegistry, asset: &str) -> Option<&BalanceEx> {
+        return registry.get_asset(&self.0, asset);
+    }
+}
 
 pub async fn balance_ex(cred: &Credential) -> Result<BalanceExResponse, Error> {
-    let response = private_request(&cred, "/0/private/BalanceEx", &[]).await?;
+    let response = private_request(cred, "/0/private/BalanceEx", &[]).await?;
     return load_response(&response);
 }
 
@@ -45,52 +89,68 @@ pub async fn trade_balance(
 ) -> Result<TradeBalanceResponse, Error> {
     let mut params: Vec<(&str, &str)> = vec![];
     if let Some(val) = asset {
-        params.push(("asset", &val));
+        params.push(("asset", val));
     }
-    let response = private_request(&cred, "/0/private/TradeBalance", &params).await?;
+    let response = private_request(cred, "/0/private/TradeBalance", &params).await?;
     return load_response(&response);
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderDescr {
-    pair: String,
+    pub pair: String,
     #[serde(rename = "type")]
-    type_: String, // needs to be renamed
-    ordertype: String,
-    price: Decimal,
-    price2: Decimal,
-    leverage: String,
-    order: String,
-    close: String,
+    pub type_: String, // needs to be renamed
+    pub ordertype: String,
+    pub price: Decimal,
+    pub price2: Decimal,
+    pub leverage: String,
+    pub order: String,
+    pub close: String,
+}
+
+/// Parameters of an order to be submitted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NewOrder {
+    pub pair: String,
+    #[serde(rename = "type")]
+    pub type_: String, // buy or sell
+    pub ordertype: String,
+    pub volume: Decimal,
+    pub price: Option<Decimal>,
+    pub price2: Option<Decimal>,
+    pub leverage: Option<u64>,
+    pub oflags: Option<String>,
+    pub userref: Option<i32>,
 }
 
 #[serde_as]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
-    refid: Option<String>,
-    #[serde_as(deserialize_as = "DefaultOnError")]
+    pub refid: Option<String>,
+    /// Sent as a number by the exchange, accepted as a string too.
+    #[serde_as(deserialize_as = "DefaultOnError<Option<PickFirst<(_, DisplayFromStr)>>>")]
     #[serde(default)]
-    userref: Option<String>,
-    status: String,
-    opentm: f64,
-    starttm: i64,
-    expiretm: i64,
-    descr: OrderDescr,
-    vol: Decimal,
-    vol_exec: Decimal,
-    cost: Decimal,
-    fee: Decimal,
-    price: Decimal,
-    stopprice: Decimal,
-    limitprice: Decimal,
-    misc: String,
-    oflags: String,
-    trades: Option<Vec<String>>,
+    pub userref: Option<i32>,
+    pub status: String,
+    pub opentm: f64,
+    pub starttm: i64,
+    pub expiretm: i64,
+    pub descr: OrderDescr,
+    pub vol: Decimal,
+    pub vol_exec: Decimal,
+    pub cost: Decimal,
+    pub fee: Decimal,
+    pub price: Decimal,
+    pub stopprice: Decimal,
+    pub limitprice: Decimal,
+    pub misc: String,
+    pub oflags: String,
+    pub trades: Option<Vec<String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OpenOrdersResponse {
-    open: HashMap<String, Order>,
+    pub open: HashMap<String, Order>,
 }
 
 pub async fn open_orders(
@@ -109,13 +169,13 @@ pub async fn open_orders(
         userref_string = val.to_string();
         params.push(("userref", &userref_string));
     }
-    let response = private_request(&cred, "/0/private/OpenOrders", &params).await?;
+    let response = private_request(cred, "/0/private/OpenOrders", &params).await?;
     return load_response(&response);
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ClosedOrdersResponse {
-    closed: HashMap<String, Order>,
+    pub closed: HashMap<String, Order>,
 }
 
 pub async fn closed_orders(
@@ -156,14 +216,14 @@ pub async fn closed_orders(
     let closetime_string;
     if let Some(val) = closetime {
         closetime_string = val;
-        params.push(("closetime", &closetime_string));
+        params.push(("closetime", closetime_string));
     }
-    let response = private_request(&cred, "/0/private/ClosedOrders", &params).await?;
+    let response = private_request(cred, "/0/private/ClosedOrders", &params).await?;
     return load_response(&response);
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct QueryOrdersResponse(HashMap<String, Order>);
+pub struct QueryOrdersResponse(pub HashMap<String, Order>);
 
 pub async fn query_orders(
     cred: &Credential,
@@ -184,37 +244,179 @@ pub async fn query_orders(
     }
     let txid = txid.join(",");
     params.push(("txid", &txid));
-    let response = private_request(&cred, "/0/private/QueryOrders", &params).await?;
+    let response = private_request(cred, "/0/private/QueryOrders", &params).await?;
+    return load_response(&response);
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddOrderDescr {
+    pub order: String,
+    pub close: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddOrderResponse {
+    pub descr: AddOrderDescr,
+    /// Missing when the order was only validated.
+    pub txid: Option<Vec<String>>,
+}
+
+/// Submits an order. When `validate` is set the order is only checked
+/// by the exchange and not placed.
+pub async fn add_order(
+    cred: &Credential,
+    order: &NewOrder,
+    validate: bool,
+) -> Result<AddOrderResponse, Error> {
+    let volume = order.volume.to_string();
+    let mut params: Vec<(&str, &str)> = vec![
+        ("pair", &order.pair),
+        ("type", &order.type_),
+        ("ordertype", &order.ordertype),
+        ("volume", &volume),
+    ];
+    let price_string;
+    if let Some(val) = order.price {
+        price_string = val.to_string();
+        params.push(("price", &price_string));
+    }
+    let price2_string;
+    if let Some(val) = order.price2 {
+        price2_string = val.to_string();
+        params.push(("price2", &price2_string));
+    }
+    let leverage_string;
+    if let Some(val) = order.leverage {
+        leverage_string = val.to_string();
+        params.push(("leverage", &leverage_string));
+    }
+    if let Some(val) = &order.oflags {
+        params.push(("oflags", val));
+    }
+    let userref_string;
+    if let Some(val) = order.userref {
+        userref_string = val.to_string();
+        params.push(("userref", &userref_string));
+    }
+    if validate {
+        params.push(("validate", "true"));
+    }
+    let response = private_request(cred, "/0/private/AddOrder", &params).await?;
     return load_response(&response);
 }
 
+/// Changes of an open order requested with `edit_order`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OrderEdit {
+    pub volume: Option<Decimal>,
+    pub price: Option<Decimal>,
+    pub price2: Option<Decimal>,
+    pub oflags: Option<String>,
+    pub userref: Option<i32>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+pub struct EditOrderResponse {
+    pub descr: Option<AddOrderDescr>,
+    pub txid: Option<String>,
+    pub originaltxid: Option<String>,
+    pub volume: Option<Decimal>,
+    pub price: Option<Decimal>,
+    pub price2: Option<Decimal>,
+    pub orders_cancelled: Option<u64>,
+    pub status: Option<String>,
+}
+
+/// Replaces an open order of `pair` with a new one carrying the changes
+/// of `edit`. The new order gets a new txid.
+pub async fn edit_order(
+    cred: &Credential,
+    txid: &str,
+    pair: &str,
+    edit: &OrderEdit,
+    validate: bool,
+) -> Result<EditOrderResponse, Error> {
+    let mut params: Vec<(&str, &str)> = vec![("txid", txid), ("pair", pair)];
+    let volume_string;
+    if let Some(val) = edit.volume {
+        volume_string = val.to_string();
+        params.push(("volume", &volume_string));
+    }
+    let price_string;
+    if let Some(val) = edit.price {
+        price_string = val.to_string();
+        params.push(("price", &price_string));
+    }
+    let price2_string;
+    if let Some(val) = edit.price2 {
+        price2_string = val.to_string();
+        params.push(("price2", &price2_string));
+    }
+    if let Some(val) = &edit.oflags {
+        params.push(("oflags", val));
+    }
+    let userref_string;
+    if let Some(val) = edit.userref {
+        userref_string = val.to_string();
+        params.push(("userref", &userref_string));
+    }
+    if validate {
+        params.push(("validate", "true"));
+    }
+    let response = private_request(cred, "/0/private/EditOrder", &params).await?;
+    return load_response(&response);
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CancelOrderResponse {
+    pub count: u64,
+    pub pending: Option<bool>,
+}
+
+/// Cancels an order by txid or by userref.
+pub async fn cancel_order(cred: &Credential, txid: &str) -> Result<CancelOrderResponse, Error> {
+    let params: Vec<(&str, &str)> = vec![("txid", txid)];
+    let response = private_request(cred, "/0/private/CancelOrder", &params).await?;
+    return load_response(&response);
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CancelAllResponse {
+    pub count: u64,
+}
+
+pub async fn cancel_all(cred: &Credential) -> Result<CancelAllResponse, Error> {
+    let response = private_request(cred, "/0/private/CancelAll", &[]).await?;
+    return load_response(&response);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
-    ordertxid: String,
-    postxid: Option<String>,
-    pair: String,
-    time: f64,
+    pub ordertxid: String,
+    pub postxid: Option<String>,
+    pub pair: String,
+    pub time: f64,
     #[serde(rename = "type")]
-    type_: String, // needs to be renamed
-    ordertype: String,
-    price: Decimal,
-    cost: Decimal,
-    fee: Decimal,
-    vol: Decimal,
-    margin: Decimal,
-    misc: String,
-    ccost: Option<Decimal>,
-    cfee: Option<Decimal>,
-    cvol: Option<Decimal>,
-    cmargin: Option<Decimal>,
-    net: Option<Decimal>,
-    trades: Option<Vec<String>>,
+    pub type_: String, // needs to be renamed
+    pub ordertype: String,
+    pub price: Decimal,
+    pub cost: Decimal,
+    pub fee: Decimal,
+    pub vol: Decimal,
+    pub margin: Decimal,
+    pub misc: String,
+    pub ccost: Option<Decimal>,
+    pub cfee: Option<Decimal>,
+    pub cvol: Option<Decimal>,
+    pub cmargin: Option<Decimal>,
+    pub net: Option<Decimal>,
+    pub trades: Option<Vec<String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TradesHistoryResponse {
-    trades: HashMap<String, Trade>,
-    count: u64,
+    pub trades: HashMap<String, Trade>,
+    pub count: u64,
 }
 
 pub async fn trades_history(
@@ -249,10 +451,25 @@ pub async fn trades_history(
         ofs_string = val.to_string();
         params.push(("ofs", &ofs_string));
     }
-    let response = private_request(&cred, "/0/private/TradesHistory", &params).await?;
+    let response = private_request(cred, "/0/private/TradesHistory", &params).await?;
     return load_response(&response);
 }
 
+/// Fetches every page of `trades_history` between `start` and `end`.
+/// Returns the trades keyed by txid.
+pub async fn trades_history_all(
+    cred: &Credential,
+    type_: Option<&str>,
+    start: Option<i64>,
+    end: Option<i64>,
+) -> Result<HashMap<String, Trade>, Error> {
+    return paginate(|ofs| async move {
+        let page = trades_history(cred, type_, None, start, end, Some(ofs)).await?;
+        return Ok((page.trades, page.count));
+    })
+    .await;
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct QueryTradesResponse(HashMap<String, Trade>);
 
@@ -268,10 +485,10 @@ pub async fn query_trades(
         params.push(("trades", &trades_string));
     }
     let txids = txids.join(",");
-    if txids != "" {
+    if !txids.is_empty() {
         params.push(("txid", &txids))
     }
-    let response = private_request(&cred, "/0/private/QueryTrades", &params).await?;
+    let response = private_request(cred, "/0/private/QueryTrades", &params).await?;
     return load_response(&response);
 }
 
@@ -306,7 +523,7 @@ pub async fn open_positions(
     docalcs: Option<bool>,
     consolidation: &str,
 ) -> Result<OpenPositionsResponse, Error> {
-    let mut params: Vec<(&str, &str)> = vec![("consolidation", &consolidation)];
+    let mut params: Vec<(&str, &str)> = vec![("consolidation", consolidation)];
     let txids = txids.join(",");
     params.push(("txid", &txids));
     let docalcs_string;
@@ -314,28 +531,28 @@ pub async fn open_positions(
         docalcs_string = val.to_string();
         params.push(("docalcs", &docalcs_string));
     }
-    let response = private_request(&cred, "/0/private/OpenPositions", &params).await?;
+    let response = private_request(cred, "/0/private/OpenPositions", &params).await?;
     return load_response(&response);
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Ledger {
-    refid: String,
-    time: f64,
+    pub refid: String,
+    pub time: f64,
     #[serde(rename = "type")]
-    type_: String,
-    subtype: String,
-    aclass: String,
-    asset: String,
-    amount: Decimal,
-    fee: Decimal,
-    balance: Decimal,
+    pub type_: String,
+    pub subtype: String,
+    pub aclass: String,
+    pub asset: String,
+    pub amount: Decimal,
+    pub fee: Decimal,
+    pub balance: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LedgersResponse {
-    ledger: HashMap<String, Ledger>,
-    count: u64,
+    pub ledger: HashMap<String, Ledger>,
+    pub count: u64,
 }
 
 pub async fn ledgers(
@@ -354,10 +571,10 @@ pub async fn ledgers(
         params.push(("asset", &asset_string));
     }
     if let Some(val) = aclass {
-        params.push(("aclass", &val));
+        params.push(("aclass", val));
     }
     if let Some(val) = type_ {
-        params.push(("type", &val));
+        params.push(("type", val));
     }
     let start_string;
     if let Some(val) = start {
@@ -374,10 +591,26 @@ pub async fn ledgers(
         ofs_string = val.to_string();
         params.push(("ofs", &ofs_string));
     }
-    let response = private_request(&cred, "/0/private/Ledgers", &params).await?;
+    let response = private_request(cred, "/0/private/Ledgers", &params).await?;
     return load_response(&response);
 }
 
+/// Fetches every page of `ledgers` between `start` and `end`.
+/// Returns the entries keyed by ledger id.
+pub async fn ledgers_all(
+    cred: &Credential,
+    asset: Option<&[&str]>,
+    type_: Option<&str>,
+    start: Option<i64>,
+    end: Option<i64>,
+) -> Result<HashMap<String, Ledger>, Error> {
+    return paginate(|ofs| async move {
+        let page = ledgers(cred, asset, None, type_, start, end, Some(ofs)).await?;
+        return Ok((page.ledger, page.count));
+    })
+    .await;
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct QueryLedgersResponse(HashMap<String, Ledger>);
 
@@ -394,26 +627,26 @@ pub async fn query_ledgers(
     }
     let ids = id.join(",");
     params.push(("id", &ids));
-    let response = private_request(&cred, "/0/private/QueryLedgers", &params).await?;
+    let response = private_request(cred, "/0/private/QueryLedgers", &params).await?;
     return load_response(&response);
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Fee {
-    fee: Decimal,
-    minfee: Decimal,
-    maxfee: Decimal,
-    nextfee: Option<Decimal>,
-    nextvolume: Option<Decimal>,
-    tiervolume: Decimal,
+    pub fee: Decimal,
+    pub minfee: Decimal,
+    pub maxfee: Decimal,
+    pub nextfee: Option<Decimal>,
+    pub nextvolume: Option<Decimal>,
+    pub tiervolume: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TradeVolumeResponse {
-    currency: String,
-    volume: Decimal,
-    fees: Option<HashMap<String, Fee>>,
-    fees_maker: Option<HashMap<String, Fee>>,
+    pub currency: String,
+    pub volume: Decimal,
+    pub fees: Option<HashMap<String, Fee>>,
+    pub fees_maker: Option<HashMap<String, Fee>>,
 }
 
 pub async fn trade_volume(
@@ -432,6 +665,43 @@ pub async fn trade_volume(
         fee_info_string = val.to_string();
         params.push(("fee-info", &fee_info_string));
     }
-    let response = private_request(&cred, "/0/private/TradeVolume", &params).await?;
+    let response = private_request(cred, "/0/private/TradeVolume", &params).await?;
     return load_response(&response);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::paginate;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn paginate_test() {
+        // a row inserted during the download shifts the second page by one
+        let pages = [vec!["d", "c"], vec!["c", "b"], vec!["b", "a"]];
+        let mut requested = vec![];
+        let rows = paginate(|ofs| {
+            requested.push(ofs);
+            let page: HashMap<String, ()> = pages[requested.len() - 1]
+                .iter()
+                .map(|id| (id.to_string(), ()))
+                .collect();
+            async move { Ok((page, 5)) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(requested, vec![0, 2, 4]);
+        assert_eq!(rows.len(), 4);
+
+        // a page without any new row ends the download
+        let mut calls = 0;
+        let rows = paginate(|_| {
+            calls += 1;
+            let page: HashMap<String, ()> = [("a".to_string(), ())].into_iter().collect();
+            async move { Ok((page, 10)) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(calls, 2);
+        assert_eq!(rows.len(), 1);
+    }
+}
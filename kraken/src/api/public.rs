@@ -1,4 +1,5 @@
 use super::request::*;
+use crate::registry::AssetRegistry;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -27,14 +28,14 @@ pub async fn system_status() -> Result<SystemStatusResponse, Error> {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Asset {
-    aclass: String,
-    altname: String,
-    decimals: u64,
-    display_decimals: u64,
+    pub aclass: String,
+    pub altname: String,
+    pub decimals: u64,
+    pub display_decimals: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct AssetsResponse(HashMap<String, Asset>);
+pub struct AssetsResponse(pub HashMap<String, Asset>);
 
 pub async fn assets(asset: Option<&[&str]>, aclass: Option<&str>) -> Result<AssetsResponse, Error> {
     let mut params: Vec<(&str, &str)> = vec![];
@@ -52,35 +53,37 @@ pub async fn assets(asset: Option<&[&str]>, aclass: Option<&str>) -> Result<Asse
     return load_response(&response);
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AssetPair {
-    altname: String,
-    wsname: String,
-    aclass_base: String,
-    base: String,
-    aclass_quote: String,
-    quote: String,
-    lot: String,
-    pair_decimals: u64,
-    lot_decimals: u64,
-    lot_multiplier: u64,
-    leverage_buy: Vec<u64>,
-    leverage_sell: Vec<u64>,
-    fees: Vec<Vec<Decimal>>,
-    fees_maker: Vec<Vec<Decimal>>,
-    fee_volume_currency: String,
-    margin_call: u64,
-    margin_stop: u64,
-    ordermin: Decimal,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AssetPairResponse(HashMap<String, AssetPair>);
+    pub altname: String,
+    pub wsname: String,
+    pub aclass_base: String,
+    pub base: String,
+    pub aclass_quote: String,
+    pub quote: String,
+    pub lot: String,
+    pub pair_decimals: u64,
+    pub lot_decimals: u64,
+    pub lot_multiplier: u64,
+    pub leverage_buy: Vec<u64>,
+    pub leverage_sell: Vec<u64>,
+    pub fees: Vec<Vec<Decimal>>,
+    pub fees_maker: Vec<Vec<Decimal>>,
+    pub fee_volume_currency: String,
+    pub margin_call: u64,
+    pub margin_stop: u64,
+    pub ordermin: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AssetPairResponse(pub HashMap<String, AssetPair>);
 
 pub async fn asset_pair(pair: &[&str], info: Option<&str>) -> Result<AssetPairResponse, Error> {
     let mut params: Vec<(&str, &str)> = vec![];
     let pair = pair.join(",");
-    params.push(("pair", &pair));
+    if !pair.is_empty() {
+        params.push(("pair", &pair));
+    }
     if let Some(val) = info {
         params.push(("info", val));
     }
@@ -90,19 +93,26 @@ pub async fn asset_pair(pair: &[&str], info: Option<&str>) -> Result<AssetPairRe
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AssetTickerInfo {
-    a: (Decimal, Decimal, Decimal),
-    b: (Decimal, Decimal, Decimal),
-    c: (Decimal, Decimal),
-    v: (Decimal, Decimal),
-    p: (Decimal, Decimal),
-    t: (Decimal, Decimal),
-    l: (Decimal, Decimal),
-    h: (Decimal, Decimal),
-    o: Decimal,
+    pub a: (Decimal, Decimal, Decimal),
+    pub b: (Decimal, Decimal, Decimal),
+    pub c: (Decimal, Decimal),
+    pub v: (Decimal, Decimal),
+    pub p: (Decimal, Decimal),
+    pub t: (Decimal, Decimal),
+    pub l: (Decimal, Decimal),
+    pub h: (Decimal, Decimal),
+    pub o: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct TickerResponse(HashMap<String, AssetTickerInfo>);
+pub struct TickerResponse(pub HashMap<String, AssetTickerInfo>);
+
+impl TickerResponse {
+    /// Returns the ticker of a pair under any of its spellings.
+    pub fn get(&self, registry: &AssetRegistry, pair: &str) -> Option<&AssetTickerInfo> {
+        return registry.get_pair(&self.0, pair);
+    }
+}
 
 pub async fn ticker(pair: &str) -> Result<TickerResponse, Error> {
     let response = public_request("/0/public/Ticker", &[("pair", pair)]).await?;
@@ -128,7 +138,7 @@ pub type OHLCTickData = Vec<(
 )>;
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct OHLCResponse(HashMap<String, OHLC>);
+pub struct OHLCResponse(pub HashMap<String, OHLC>);
 
 pub async fn ohcl(
     pair: &str,
@@ -152,12 +162,12 @@ pub async fn ohcl(
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OrderBook {
-    asks: Vec<(
+    pub asks: Vec<(
         Decimal, // price
         Decimal, // volume
         u64,     // timestamp
     )>,
-    bids: Vec<(
+    pub bids: Vec<(
         Decimal, // price
         Decimal, // volume
         u64,     // timestamp
@@ -165,7 +175,7 @@ pub struct OrderBook {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct DepthResponse(HashMap<String, OrderBook>);
+pub struct DepthResponse(pub HashMap<String, OrderBook>);
 
 pub async fn depth(pair: &str, count: Option<i64>) -> Result<DepthResponse, Error> {
     let mut params = vec![("pair", pair)];
@@ -186,10 +196,10 @@ pub enum Trade {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct TradeTickData(Vec<(Decimal, Decimal, f64, String, String, String)>);
+pub struct TradeTickData(pub Vec<(Decimal, Decimal, f64, String, String, String)>);
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct TradesResponse(HashMap<String, Trade>);
+pub struct TradesResponse(pub HashMap<String, Trade>);
 
 pub async fn trades(pair: &str, since: Option<i64>) -> Result<TradesResponse, Error> {
     let mut params = vec![("pair", pair)];
@@ -210,10 +220,10 @@ pub enum Spread {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct SpreadData(Vec<(u64, Decimal, Decimal)>);
+pub struct SpreadData(pub Vec<(u64, Decimal, Decimal)>);
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct SpreadResponse(HashMap<String, Spread>);
+pub struct SpreadResponse(pub HashMap<String, Spread>);
 
 pub async fn spread(pair: &str, since: Option<i64>) -> Result<SpreadResponse, Error> {
     let mut params = vec![("pair", pair)];
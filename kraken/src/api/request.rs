@@ -1,41 +1,126 @@
 use chrono::Utc;
 use data_encoding::BASE64;
 use hmac::{Hmac, Mac};
-use reqwest;
 use serde::Deserialize;
 use sha2::{Digest, Sha256, Sha512};
+use std::future::Future;
 use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use thiserror::Error;
+use zeroize::Zeroize;
+
+use super::signer::{HmacSigner, Signer};
+use super::transport::{transport, HttpRequest, Method};
+use crate::permissions::Permission;
 
 /// This valud is set to 10 sec since the nonce as a
 /// limit of few seconds only. If this valud is too short,
 /// please another network.
-const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+pub(super) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_BASE_URL: &str = "https://api.kraken.com";
+
+/// Retries of a call rejected by the rate limiter before its error is
+/// returned.
+const MAX_RETRIES: u32 = 5;
+
+static BASE_URL: RwLock<Option<String>> = RwLock::new(None);
+
+/// Overrides the address requests are sent to, `https://api.kraken.com`
+/// by default.
+pub fn set_base_url(url: &str) {
+    *BASE_URL.write().unwrap() = Some(url.trim_end_matches('/').to_string());
+}
+
+type Observer = Box<dyn Fn(&str, &str) + Send + Sync>;
+
+static OBSERVER: RwLock<Option<Observer>> = RwLock::new(None);
+
+/// Registers a function called with the path and the untouched body
+/// of every response, before it gets decoded.
+pub fn set_observer<F>(observer: F)
+where
+    F: Fn(&str, &str) + Send + Sync + 'static,
+{
+    *OBSERVER.write().unwrap() = Some(Box::new(observer));
+}
+
+fn observe(path: &str, body: &str) {
+    if let Some(observer) = OBSERVER.read().unwrap().as_ref() {
+        observer(path, body);
+    }
+}
 
 /// Stores the credentials used for private enpoints.
-/// The key must be decoded before being passed or payload
-/// signing will fail.
-#[derive(Debug, Clone)]
+/// The secret must be decoded before being passed or payload
+/// signing will fail. The key is wiped from memory on drop, the
+/// secret is held by the signer (`HmacSigner` wipes it on drop).
+#[derive(Clone)]
 pub struct Credential {
     key: String,
-    secret: Vec<u8>,
+    signer: Arc<dyn Signer>,
+    permissions: Option<Vec<Permission>>,
 }
 
 impl Credential {
     pub fn new(key: &str, secret: &[u8]) -> Self {
+        return Self::with_signer(key, HmacSigner::new(secret));
+    }
+
+    /// Credentials whose requests are signed by `signer`, which may hold
+    /// the secret outside of this process.
+    pub fn with_signer<S>(key: &str, signer: S) -> Self
+    where
+        S: Signer + 'static,
+    {
         Self {
             key: key.to_string(),
-            secret: secret.to_vec(),
+            signer: Arc::new(signer),
+            permissions: None,
         }
     }
+
+    /// Declares what the key is allowed to do. Requests to endpoints
+    /// needing another permission are refused without being sent.
+    pub fn with_permissions(mut self, permissions: Option<Vec<Permission>>) -> Self {
+        self.permissions = permissions;
+        return self;
+    }
+
+    pub fn permissions(&self) -> Option<&[Permission]> {
+        return self.permissions.as_deref();
+    }
+
+    pub fn key(&self) -> &str {
+        return &self.key;
+    }
 }
 
-fn build_url(path: &str, query: &[(&str, &str)]) -> String {
-    let mut url = String::from("https://api.kraken.com");
+impl std::fmt::Debug for Credential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f
+            .debug_struct("Credential")
+            .field("key", &"<redacted>")
+            .finish();
+    }
+}
+
+impl Drop for Credential {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+pub(super) fn build_url(path: &str, query: &[(&str, &str)]) -> String {
+    let mut url = match BASE_URL.read().unwrap().as_ref() {
+        Some(base_url) => base_url.clone(),
+        None => DEFAULT_BASE_URL.to_string(),
+    };
     url.push_str(path);
-    if query.len() > 0 {
-        url.push_str("?");
+    if !query.is_empty() {
+        url.push('?');
         url.push_str(&serde_urlencoded::to_string(query).unwrap());
     }
     return url;
@@ -46,35 +131,88 @@ fn build_url(path: &str, query: &[(&str, &str)]) -> String {
 /// See https://docs.kraken.com/rest/#section/Authentication/Headers-and-Signature
 pub fn sign(path: &str, args: &[(&str, &str)], secret: &[u8]) -> String {
     // extract nonce value
-    let nonce = args.into_iter().find(|&item| item.0.eq("nonce")).unwrap().1;
+    let nonce = args.iter().find(|&item| item.0.eq("nonce")).unwrap().1;
     // url encode payload
     let postdata = serde_urlencoded::to_string(args).unwrap();
-    let encoded: String = nonce.to_string().to_owned() + &postdata;
+    return sign_postdata(path, nonce, &postdata, secret);
+}
+
+/// Same as `sign` for an already encoded payload.
+pub fn sign_postdata(path: &str, nonce: &str, postdata: &str, secret: &[u8]) -> String {
+    let encoded: String = nonce.to_string() + postdata;
     let mut hasher = Sha256::new();
     hasher.update(encoded.as_bytes());
     let mut message: Vec<u8> = vec![];
-    message.write(&path.as_bytes()).unwrap();
-    message.write(&hasher.finalize().as_slice()).unwrap();
+    message.write_all(path.as_bytes()).unwrap();
+    message.write_all(hasher.finalize().as_slice()).unwrap();
     let mut mac = Hmac::<Sha512>::new_from_slice(secret).unwrap();
     mac.update(&message);
     return BASE64.encode(&mac.finalize().into_bytes());
 }
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("request error")]
     Request(#[from] reqwest::Error),
     #[error("json error")]
     JSON(#[from] serde_json::Error),
+    /// Error returned by the exchange, or by the paper trading simulator
+    /// in the same form.
     #[error("api error {0}")]
     API(String),
+    #[error("io error")]
+    IO(#[from] std::io::Error),
+    /// A file written or read by the library (csv, toml) is malformed.
+    #[error("storage error {0}")]
+    Storage(String),
+    /// Invalid input or local state, detected before any request.
+    #[error("invalid {0}")]
+    Invalid(String),
+    /// The request needs a permission that is not declared for the key,
+    /// see `Credential::with_permissions`. Nothing was sent.
+    #[error("{path} needs the {needed} permission, not declared for this key")]
+    Permission { path: String, needed: Permission },
+    /// A command run by the library (alert actions) failed.
+    #[error("command error {0}")]
+    Command(String),
+}
+
+impl Error {
+    pub(crate) fn storage<E: std::fmt::Display>(e: E) -> Self {
+        return Error::Storage(e.to_string());
+    }
+}
+
+static LAST_NONCE: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the current time in milliseconds, bumped when needed so two
+/// requests sent within the same millisecond get increasing nonces.
+fn next_nonce() -> u64 {
+    let now = Utc::now().timestamp_millis() as u64;
+    let previous = LAST_NONCE
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+            Some(std::cmp::max(now, last + 1))
+        })
+        .unwrap();
+    return std::cmp::max(now, previous + 1);
 }
 
 /// Performs a request against a public endpoint.
 pub async fn public_request(path: &str, query: &[(&str, &str)]) -> Result<String, Error> {
-    let client = reqwest::Client::new();
-    let builder = client.get(build_url(path, query)).timeout(DEFAULT_TIMEOUT);
-    return Ok(builder.send().await?.text().await?);
+    let request = HttpRequest {
+        method: Method::Get,
+        path: path.to_string(),
+        query: query
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        headers: vec![],
+        body: None,
+    };
+    let body = transport().send(&request).await?;
+    observe(path, &body);
+    return Ok(body);
 }
 
 /// Performs a request against a private endpoint where
@@ -84,14 +222,23 @@ pub async fn private_request(
     path: &str,
     params: &[(&str, &str)],
 ) -> Result<String, Error> {
+    if let (Some(declared), Some(needed)) = (&cred.permissions, Permission::required_by(path)) {
+        if !declared.contains(&needed) {
+            return Err(Error::Permission {
+                path: path.to_string(),
+                needed,
+            });
+        }
+    }
     let mut params_secure: Vec<(&str, &str)> = Vec::new();
-    let nonce = Utc::now().timestamp_millis().to_string();
+    let nonce = next_nonce().to_string();
     params_secure.push(("nonce", &nonce));
     params
-        .into_iter()
+        .iter()
         .for_each(|item| params_secure.push(*item));
 
-    let signature = sign(path, &params_secure, &cred.secret);
+    let postdata = serde_urlencoded::to_string(&params_secure).unwrap();
+    let signature = cred.signer.sign(path, &nonce, &postdata).await?;
     let headers: Vec<(&str, &str)> = vec![
         ("API-Key", &cred.key),
         ("API-Sign", &signature),
@@ -100,14 +247,44 @@ pub async fn private_request(
             "application/x-www-form-urlencoded; charset=utf-8",
         ),
     ];
-    let client = reqwest::Client::new();
-    let mut builder = client.post(build_url(path, &[])).timeout(DEFAULT_TIMEOUT);
-    for item in headers {
-        builder = builder.header(item.0, item.1);
+    let request = HttpRequest {
+        method: Method::Post,
+        path: path.to_string(),
+        query: vec![],
+        headers: headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        body: Some(postdata),
+    };
+    let body = transport().send(&request).await?;
+    observe(path, &body);
+    return Ok(body);
+}
+
+/// Calls `call` again while the exchange rejects it for its call rate,
+/// waiting `delay` before the first retry and twice as long before each
+/// next one. The error is returned after `MAX_RETRIES` retries.
+pub(crate) async fn retry_throttled<T, F, Fut>(delay: Duration, mut call: F) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut delay = delay;
+    let mut retries = 0;
+    loop {
+        match call().await {
+            Err(Error::API(e))
+                if retries < MAX_RETRIES
+                    && (e.contains("Rate limit exceeded") || e.contains("Too many requests")) =>
+            {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                retries += 1;
+            }
+            result => return result,
+        }
     }
-    let body = serde_urlencoded::to_string(params_secure).unwrap();
-    builder = builder.body(body);
-    return Ok(builder.send().await?.text().await?);
 }
 
 #[derive(Debug, Deserialize)]
@@ -122,7 +299,7 @@ where
     for<'a> T: Deserialize<'a>,
 {
     let response: Response<T> = serde_json::from_str(payload)?;
-    if response.error.len() > 0 {
+    if !response.error.is_empty() {
         return Err(Error::API(response.error.join(" ")));
     }
     return Ok(response.result.unwrap());
@@ -130,8 +307,9 @@ where
 
 #[cfg(test)]
 mod tests {
-    use super::sign;
+    use super::{retry_throttled, sign, Error, MAX_RETRIES};
     use data_encoding::BASE64;
+    use std::time::Duration;
 
     #[test]
     fn sign_test() {
@@ -150,4 +328,29 @@ mod tests {
         let expected_signature = "4/dpxb3iT4tp/ZCVEwSnEsLxx0bqyhLpdfOpc6fn7OR8+UClSV5n9E6aSS8MPtnRfp32bAb0nmbRn6H8ndwLUQ==";
         assert_eq!(&signature, expected_signature);
     }
+
+    #[tokio::test]
+    async fn retry_test() {
+        let mut calls = 0;
+        let result = retry_throttled(Duration::ZERO, || {
+            calls += 1;
+            let result = match calls {
+                1 => Err(Error::API("EGeneral:Too many requests".to_string())),
+                _ => Ok(calls),
+            };
+            async move { result }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 2);
+
+        // a key that stays throttled gets the error back.
+        let mut calls = 0;
+        let result: Result<(), Error> = retry_throttled(Duration::ZERO, || {
+            calls += 1;
+            async { Err(Error::API("EAPI:Rate limit exceeded".to_string())) }
+        })
+        .await;
+        assert!(matches!(result, Err(Error::API(e)) if e == "EAPI:Rate limit exceeded"));
+        assert_eq!(calls, MAX_RETRIES + 1);
+    }
 }
@@ -0,0 +1,222 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use super::request::{Credential, Error};
+
+/// Signature helpers for `Signer` implementations holding the secret.
+pub use super::request::{sign, sign_postdata};
+
+/// Computes the `API-Sign` header of private requests. Implementations
+/// may keep the secret out of this process, in a signing agent or an OS
+/// keyring for instance.
+#[async_trait]
+pub trait Signer: Send + Sync {
+    /// `postdata` is the url encoded body of the request, nonce included.
+    async fn sign(&self, path: &str, nonce: &str, postdata: &str) -> Result<String, Error>;
+}
+
+/// Signs with a decoded secret held in memory, wiped on drop.
+pub struct HmacSigner {
+    secret: Vec<u8>,
+}
+
+impl HmacSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+        }
+    }
+}
+
+impl Drop for HmacSigner {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+#[async_trait]
+impl Signer for HmacSigner {
+    async fn sign(&self, path: &str, nonce: &str, postdata: &str) -> Result<String, Error> {
+        Ok(sign_postdata(path, nonce, postdata, &self.secret))
+    }
+}
+
+/// Requests understood by a signing agent, one JSON document per line.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AgentRequest {
+    /// Returns the API key matching the secret of the agent.
+    Key,
+    Sign {
+        path: String,
+        nonce: String,
+        postdata: String,
+    },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AgentResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Signs through an agent listening on a Unix socket, see `serve_agent`.
+/// A connection is opened for each request so the agent can be restarted
+/// at any time.
+#[cfg(unix)]
+pub struct AgentSigner {
+    socket: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl AgentSigner {
+    pub fn new<P: AsRef<std::path::Path>>(socket: P) -> Self {
+        Self {
+            socket: socket.as_ref().to_path_buf(),
+        }
+    }
+
+    async fn call(&self, request: &AgentRequest) -> Result<AgentResponse, Error> {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let stream = tokio::net::UnixStream::connect(&self.socket).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+        let mut reply = String::new();
+        BufReader::new(reader).read_line(&mut reply).await?;
+        let response: AgentResponse = serde_json::from_str(&reply)?;
+        if let Some(error) = response.error {
+            return Err(Error::API(format!("signing agent: {}", error)));
+        }
+        return Ok(response);
+    }
+
+    pub async fn key(&self) -> Result<String, Error> {
+        return self
+            .call(&AgentRequest::Key)
+            .await?
+            .key
+            .ok_or_else(|| Error::API("signing agent: no key".to_string()));
+    }
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl Signer for AgentSigner {
+    async fn sign(&self, path: &str, nonce: &str, postdata: &str) -> Result<String, Error> {
+        let request = AgentRequest::Sign {
+            path: path.to_string(),
+            nonce: nonce.to_string(),
+            postdata: postdata.to_string(),
+        };
+        self.call(&request)
+            .await?
+            .signature
+            .ok_or_else(|| Error::API("signing agent: no signature".to_string()))
+    }
+}
+
+#[cfg(unix)]
+impl Credential {
+    /// Credentials whose key is read from the agent listening on `socket`
+    /// and whose requests are signed by it.
+    pub async fn from_agent<P: AsRef<std::path::Path>>(socket: P) -> Result<Self, Error> {
+        let signer = AgentSigner::new(socket);
+        let key = signer.key().await?;
+        return Ok(Credential::with_signer(&key, signer));
+    }
+}
+
+/// Answers `AgentSigner` requests on `socket` with `signer`. A stale
+/// socket file is replaced and the new one is only accessible by its
+/// owner. Runs until accepting a connection fails.
+#[cfg(unix)]
+pub async fn serve_agent<S>(socket: &std::path::Path, key: &str, signer: S) -> Result<(), Error>
+where
+    S: Signer + 'static,
+{
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    if socket.exists() {
+        std::fs::remove_file(socket)?;
+    }
+    let listener = tokio::net::UnixListener::bind(socket)?;
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600))?;
+    let key: Arc<str> = Arc::from(key);
+    let signer = Arc::new(signer);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let key = key.clone();
+        let signer = signer.clone();
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let mut response = AgentResponse::default();
+                match serde_json::from_str(&line) {
+                    Ok(AgentRequest::Key) => response.key = Some(key.to_string()),
+                    Ok(AgentRequest::Sign {
+                        path,
+                        nonce,
+                        postdata,
+                    }) => match signer.sign(&path, &nonce, &postdata).await {
+                        Ok(signature) => response.signature = Some(signature),
+                        Err(e) => response.error = Some(e.to_string()),
+                    },
+                    Err(e) => response.error = Some(format!("invalid request ({})", e)),
+                }
+                let mut reply = serde_json::to_string(&response).unwrap();
+                reply.push('\n');
+                if writer.write_all(reply.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{serve_agent, AgentSigner, HmacSigner, Signer};
+    use crate::Credential;
+    use data_encoding::BASE64;
+
+    #[tokio::test]
+    async fn agent_test() {
+        let secret = BASE64.decode(b"kQH5HW/8p1uGOVjbgWA7FunAmGO8lsSUXNsu3eow76sz84Q18fWxnyRzBHCd3pd5nE9qa99HAZtuZuj6F1huXg==").unwrap();
+        let socket = std::env::temp_dir().join(format!("kraken-agent-{}.sock", std::process::id()));
+        let server = tokio::spawn({
+            let socket = socket.clone();
+            let signer = HmacSigner::new(&secret);
+            async move { serve_agent(&socket, "KEY", signer).await }
+        });
+        while !socket.exists() {
+            tokio::task::yield_now().await;
+        }
+
+        let path = "/0/private/AddOrder";
+        let nonce = "1616492376594";
+        let postdata =
+            "nonce=1616492376594&ordertype=limit&pair=XBTUSD&price=37500&type=buy&volume=1.25";
+        let expected = "4/dpxb3iT4tp/ZCVEwSnEsLxx0bqyhLpdfOpc6fn7OR8+UClSV5n9E6aSS8MPtnRfp32bAb0nmbRn6H8ndwLUQ==";
+        let signature = AgentSigner::new(&socket)
+            .sign(path, nonce, postdata)
+            .await
+            .unwrap();
+        assert_eq!(signature, expected);
+        let cred = Credential::from_agent(&socket).await.unwrap();
+        assert_eq!(cred.key(), "KEY");
+
+        server.abort();
+        std::fs::remove_file(&socket).unwrap();
+    }
+}
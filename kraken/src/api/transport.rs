@@ -0,0 +1,292 @@
+use async_trait::async_trait;
+use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use super::request::{build_url, Error, DEFAULT_TIMEOUT};
+
+/// Headers replaced in fixtures.
+const REDACTED_HEADERS: &[&str] = &["API-Key", "API-Sign"];
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Method {
+    Get,
+    Post,
+}
+
+impl std::fmt::Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Method::Get => f.write_str("GET"),
+            Method::Post => f.write_str("POST"),
+        };
+    }
+}
+
+/// A request as built by `public_request` and `private_request`, signed
+/// and ready to be sent.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    /// Url encoded form, for private endpoints.
+    pub body: Option<String>,
+}
+
+/// Sends requests and returns the untouched body of the responses.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn send(&self, request: &HttpRequest) -> Result<String, Error>;
+}
+
+/// Sends requests over the network to the base url, see `set_base_url`.
+#[derive(Default)]
+pub struct HttpTransport {
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+    async fn send(&self, request: &HttpRequest) -> Result<String, Error> {
+        let query: Vec<(&str, &str)> = request
+            .query
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let url = build_url(&request.path, &query);
+        let mut builder = match request.method {
+            Method::Get => self.client.get(url),
+            Method::Post => self.client.post(url),
+        };
+        builder = builder.timeout(DEFAULT_TIMEOUT);
+        for (name, value) in request.headers.iter() {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = &request.body {
+            builder = builder.body(body.clone());
+        }
+        Ok(builder.send().await?.text().await?)
+    }
+}
+
+static TRANSPORT: RwLock<Option<Arc<dyn Transport>>> = RwLock::new(None);
+
+/// Replaces the transport of every request, `HttpTransport` by default.
+pub fn set_transport<T>(transport: T)
+where
+    T: Transport + 'static,
+{
+    *TRANSPORT.write().unwrap() = Some(Arc::new(transport));
+}
+
+pub(super) fn transport() -> Arc<dyn Transport> {
+    return match TRANSPORT.read().unwrap().as_ref() {
+        Some(transport) => transport.clone(),
+        None => Arc::new(HttpTransport::default()),
+    };
+}
+
+/// A request and its response, without credentials nor nonce so the
+/// same call always matches the same fixture.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fixture {
+    pub method: Method,
+    pub path: String,
+    #[serde(default)]
+    pub query: BTreeMap<String, String>,
+    /// Form parameters of private requests.
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Kept as JSON when the body is JSON, as a string otherwise.
+    pub response: serde_json::Value,
+}
+
+fn form_params(body: Option<&str>) -> BTreeMap<String, String> {
+    let params: Vec<(String, String)> = body
+        .and_then(|body| serde_urlencoded::from_str(body).ok())
+        .unwrap_or_default();
+    return params.into_iter().filter(|(k, _)| k != "nonce").collect();
+}
+
+impl Fixture {
+    pub fn new(request: &HttpRequest, response: &str) -> Self {
+        let headers = request
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                if REDACTED_HEADERS.contains(&name.as_str()) {
+                    (name.clone(), REDACTED.to_string())
+                } else {
+                    (name.clone(), value.clone())
+                }
+            })
+            .collect();
+        Self {
+            method: request.method,
+            path: request.path.clone(),
+            query: request.query.iter().cloned().collect(),
+            params: form_params(request.body.as_deref()),
+            headers,
+            response: serde_json::from_str(response)
+                .unwrap_or_else(|_| serde_json::Value::String(response.to_string())),
+        }
+    }
+
+    pub fn matches(&self, request: &HttpRequest) -> bool {
+        return self.method == request.method
+            && self.path == request.path
+            && self.query == request.query.iter().cloned().collect()
+            && self.params == form_params(request.body.as_deref());
+    }
+
+    /// Name of the fixture file, e.g. `public-Ticker-1a2b3c4d.json`.
+    pub fn file_name(&self) -> String {
+        let key = serde_json::to_string(&(self.method, &self.path, &self.query, &self.params));
+        let hash = Sha256::digest(key.unwrap().as_bytes());
+        let name = self.path.trim_start_matches("/0/").replace('/', "-");
+        return format!("{}-{}.json", name, HEXLOWER.encode(&hash[..4]));
+    }
+
+    pub fn body(&self) -> String {
+        return match &self.response {
+            serde_json::Value::String(body) => body.clone(),
+            value => value.to_string(),
+        };
+    }
+}
+
+/// Sends requests with another transport and writes every exchange to
+/// a fixture file of `dir`. A request sent twice keeps the last response.
+pub struct Recorder {
+    inner: Arc<dyn Transport>,
+    dir: PathBuf,
+}
+
+impl Recorder {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        return Self::wrapping(dir, HttpTransport::default());
+    }
+
+    pub fn wrapping<P, T>(dir: P, inner: T) -> Self
+    where
+        P: AsRef<Path>,
+        T: Transport + 'static,
+    {
+        Self {
+            inner: Arc::new(inner),
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for Recorder {
+    async fn send(&self, request: &HttpRequest) -> Result<String, Error> {
+        let response = self.inner.send(request).await?;
+        let fixture = Fixture::new(request, &response);
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let content = serde_json::to_string_pretty(&fixture)?;
+        tokio::fs::write(self.dir.join(fixture.file_name()), content).await?;
+        Ok(response)
+    }
+}
+
+/// Serves the fixtures of a directory without network.
+pub struct Replay {
+    fixtures: Vec<Fixture>,
+}
+
+impl Replay {
+    pub fn new(fixtures: Vec<Fixture>) -> Self {
+        Self { fixtures }
+    }
+
+    /// Loads every `.json` file of `dir`.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        let mut fixtures = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().map(|ext| ext == "json") != Some(true) {
+                continue;
+            }
+            let content = std::fs::read_to_string(&path)?;
+            fixtures.push(serde_json::from_str(&content)?);
+        }
+        return Ok(Self::new(fixtures));
+    }
+}
+
+#[async_trait]
+impl Transport for Replay {
+    async fn send(&self, request: &HttpRequest) -> Result<String, Error> {
+        self.fixtures
+            .iter()
+            .find(|fixture| fixture.matches(request))
+            .map(|fixture| fixture.body())
+            .ok_or_else(|| {
+                Error::API(format!(
+                    "no fixture for {} {}",
+                    request.method, request.path
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpRequest, Method, Recorder, Replay, Transport};
+    use crate::Error;
+    use async_trait::async_trait;
+
+    struct Echo;
+
+    #[async_trait]
+    impl Transport for Echo {
+        async fn send(&self, request: &HttpRequest) -> Result<String, Error> {
+            Ok(format!("{{\"error\":[],\"result\":{:?}}}", request.path))
+        }
+    }
+
+    fn request(nonce: &str) -> HttpRequest {
+        return HttpRequest {
+            method: Method::Post,
+            path: "/0/private/CancelOrder".to_string(),
+            query: vec![],
+            headers: vec![
+                ("API-Key".to_string(), "KEY".to_string()),
+                ("API-Sign".to_string(), "SIGN".to_string()),
+            ],
+            body: Some(format!("nonce={}&txid=OABC", nonce)),
+        };
+    }
+
+    #[tokio::test]
+    async fn record_replay_test() {
+        let dir = std::env::temp_dir().join(format!("kraken-fixtures-{}", std::process::id()));
+        let recorder = Recorder::wrapping(&dir, Echo);
+        let body = recorder.send(&request("1")).await.unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let content = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(!content.contains("KEY") && !content.contains("SIGN"));
+        assert!(!content.contains("nonce"));
+
+        // the nonce of a later run does not matter.
+        let replay = Replay::load(&dir).unwrap();
+        assert_eq!(replay.send(&request("2")).await.unwrap(), body);
+        let mut other = request("2");
+        other.body = Some("nonce=2&txid=ODEF".to_string());
+        assert!(replay.send(&other).await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,358 @@
+use crate::public::{self, OHLCResponse, Trade, OHLC};
+use crate::{retry_throttled, Error};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Public endpoints are rate limited to roughly one call per second.
+pub const DEFAULT_DELAY: Duration = Duration::from_millis(1500);
+
+/// A single public trade as stored on disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeRecord {
+    pub price: Decimal,
+    pub volume: Decimal,
+    pub time: f64,
+    pub side: String,
+    pub ordertype: String,
+    pub misc: String,
+}
+
+/// An OHLC candle built from stored trades. Fields follow the
+/// layout of `public::OHLCTickData`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candle {
+    pub time: u64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub vwap: Decimal,
+    pub volume: Decimal,
+    pub count: u64,
+}
+
+fn trades_path(dir: &Path, pair: &str) -> PathBuf {
+    return dir.join(format!("{}.trades.csv", pair));
+}
+
+fn cursor_path(dir: &Path, pair: &str) -> PathBuf {
+    return dir.join(format!("{}.cursor", pair));
+}
+
+fn candles_path(dir: &Path, pair: &str, interval: u64) -> PathBuf {
+    return dir.join(format!("{}.ohlc{}.csv", pair, interval));
+}
+
+/// Returns the cursor saved by a previous backfill of `pair`, if any.
+pub fn load_cursor(dir: &Path, pair: &str) -> Result<Option<i64>, Error> {
+    return Ok(load_state(dir, pair)?.map(|(cursor, _)| cursor));
+}
+
+/// Returns the saved cursor and the length of the trades file when it
+/// was saved.
+fn load_state(dir: &Path, pair: &str) -> Result<Option<(i64, u64)>, Error> {
+    let path = cursor_path(dir, pair);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)?;
+    let invalid = || Error::Invalid(format!("cursor file for {}", pair));
+    let mut fields = content.split_whitespace();
+    let cursor = fields
+        .next()
+        .and_then(|c| c.parse::<i64>().ok())
+        .ok_or_else(invalid)?;
+    let length = fields
+        .next()
+        .and_then(|l| l.parse::<u64>().ok())
+        .ok_or_else(invalid)?;
+    return Ok(Some((cursor, length)));
+}
+
+/// Saves the cursor with the length of the trades file it matches. The
+/// file is written to a temporary file first and renamed so an
+/// interruption never leaves a truncated value behind.
+fn save_cursor(dir: &Path, pair: &str, cursor: i64, length: u64) -> Result<(), Error> {
+    let path = cursor_path(dir, pair);
+    let tmp = path.with_extension("cursor.tmp");
+    fs::write(&tmp, format!("{} {}", cursor, length))?;
+    fs::rename(tmp, path)?;
+    return Ok(());
+}
+
+/// Drops the trades appended after the cursor was last saved.
+fn truncate_trades(dir: &Path, pair: &str, length: u64) -> Result<(), Error> {
+    let path = trades_path(dir, pair);
+    if path.exists() && fs::metadata(&path)?.len() > length {
+        OpenOptions::new().write(true).open(path)?.set_len(length)?;
+    }
+    return Ok(());
+}
+
+/// Appends trades and returns the new length of the file.
+fn append_trades(dir: &Path, pair: &str, trades: &[TradeRecord]) -> Result<u64, Error> {
+    let path = trades_path(dir, pair);
+    let has_header = path.exists() && fs::metadata(&path)?.len() > 0;
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(!has_header)
+        .from_writer(&file);
+    for trade in trades {
+        writer.serialize(trade).map_err(Error::storage)?;
+    }
+    writer.flush()?;
+    file.sync_data()?;
+    return Ok(fs::metadata(path)?.len());
+}
+
+/// Fetches a single page of trades starting at `since`. Returns the
+/// trades and the cursor of the next page.
+async fn fetch_page(pair: &str, since: i64) -> Result<(Vec<TradeRecord>, i64), Error> {
+    let response = public::trades(pair, Some(since)).await?;
+    let mut records = vec![];
+    let mut last = since;
+    for (_, item) in response.0 {
+        match item {
+            Trade::Last(val) => {
+                last = val
+                    .parse::<i64>()
+                    .map_err(|_| Error::Invalid(format!("cursor {}", val)))?;
+            }
+            Trade::Pair(data) => {
+                for (price, volume, time, side, ordertype, misc) in data.0 {
+                    records.push(TradeRecord {
+                        price,
+                        volume,
+                        time,
+                        side,
+                        ordertype,
+                        misc,
+                    });
+                }
+            }
+        }
+    }
+    return Ok((records, last));
+}
+
+/// Walks `public::trades` from `since` (or from the cursor left by a
+/// previous run) up to the present, appending every page to
+/// `<dir>/<pair>.trades.csv`. The cursor is stored in `<dir>/<pair>.cursor`
+/// after each page has been flushed, along with the length of the trades
+/// file, so an interrupted backfill is resumed by calling this function
+/// again: trades appended after the last saved cursor are dropped and
+/// fetched again.
+///
+/// `since` is expressed in nanoseconds, like the `last` value returned by
+/// the endpoint, and defaults to the first trade. It is refused when a
+/// cursor exists, as the stored trades would no longer be contiguous.
+/// `progress` is called after each page with the number of trades stored
+/// so far and the current cursor.
+pub async fn backfill_trades<F>(
+    pair: &str,
+    dir: &Path,
+    since: Option<i64>,
+    delay: Duration,
+    mut progress: F,
+) -> Result<u64, Error>
+where
+    F: FnMut(u64, i64),
+{
+    fs::create_dir_all(dir)?;
+    let (mut cursor, length) = match (load_state(dir, pair)?, since) {
+        (Some(_), Some(_)) => {
+            return Err(Error::Invalid(format!(
+                "since, {} is already backfilled in {}",
+                pair,
+                dir.display()
+            )))
+        }
+        (Some(state), None) => state,
+        (None, since) => (since.unwrap_or(0), 0),
+    };
+    truncate_trades(dir, pair, length)?;
+    let mut stored: u64 = 0;
+    loop {
+        let (records, last) = retry_throttled(delay * 4, || fetch_page(pair, cursor)).await?;
+        if records.is_empty() || last <= cursor {
+            break;
+        }
+        let length = append_trades(dir, pair, &records)?;
+        save_cursor(dir, pair, last, length)?;
+        stored += records.len() as u64;
+        cursor = last;
+        progress(stored, cursor);
+        tokio::time::sleep(delay).await;
+    }
+    return Ok(stored);
+}
+
+/// Loads every trade stored for `pair`.
+pub fn load_trades(dir: &Path, pair: &str) -> Result<Vec<TradeRecord>, Error> {
+    let path = trades_path(dir, pair);
+    let mut reader = csv::Reader::from_reader(File::open(path)?);
+    let mut trades = vec![];
+    for record in reader.deserialize() {
+        trades.push(record.map_err(Error::storage)?);
+    }
+    return Ok(trades);
+}
+
+/// Groups trades into candles of `interval` minutes, the unit used by
+/// `public::ohcl`. Trades must be sorted by time. Intervals without any
+/// trade produce no candle.
+pub fn build_candles(trades: &[TradeRecord], interval: u64) -> Vec<Candle> {
+    let width = interval * 60;
+    let mut candles: Vec<Candle> = vec![];
+    for trade in trades {
+        let time = (trade.time as u64) / width * width;
+        match candles.last_mut() {
+            Some(candle) if candle.time == time => {
+                if trade.price > candle.high {
+                    candle.high = trade.price;
+                }
+                if trade.price < candle.low {
+                    candle.low = trade.price;
+                }
+                candle.close = trade.price;
+                // vwap temporarily holds the traded notional.
+                candle.vwap += trade.price * trade.volume;
+                candle.volume += trade.volume;
+                candle.count += 1;
+            }
+            _ => candles.push(Candle {
+                time,
+                open: trade.price,
+                high: trade.price,
+                low: trade.price,
+                close: trade.price,
+                vwap: trade.price * trade.volume,
+                volume: trade.volume,
+                count: 1,
+            }),
+        }
+    }
+    for candle in candles.iter_mut() {
+        candle.vwap = if candle.volume.is_zero() {
+            candle.close
+        } else {
+            candle.vwap / candle.volume
+        };
+    }
+    return candles;
+}
+
+/// Builds candles from the stored trades of `pair` and writes them to
+/// `<dir>/<pair>.ohlc<interval>.csv`. Returns the number of candles.
+pub fn write_candles(dir: &Path, pair: &str, interval: u64) -> Result<usize, Error> {
+    let trades = load_trades(dir, pair)?;
+    let candles = build_candles(&trades, interval);
+    let mut writer =
+        csv::Writer::from_path(candles_path(dir, pair, interval)).map_err(Error::storage)?;
+    for candle in &candles {
+        writer.serialize(candle).map_err(Error::storage)?;
+    }
+    writer.flush()?;
+    return Ok(candles.len());
+}
+
+/// Returns the candles of an OHLC response, oldest first.
+pub fn from_ohlc(response: &OHLCResponse) -> Vec<Candle> {
+    let mut candles = vec![];
+    for value in response.0.values() {
+        if let OHLC::Pair(data) = value {
+            candles.extend(data.iter().map(|c| Candle {
+                time: c.0,
+                open: c.1,
+                high: c.2,
+                low: c.3,
+                close: c.4,
+                vwap: c.5,
+                volume: c.6,
+                count: c.7,
+            }));
+        }
+    }
+    return candles;
+}
+
+/// Loads the candles written by `write_candles`.
+pub fn load_candles(dir: &Path, pair: &str, interval: u64) -> Result<Vec<Candle>, Error> {
+    let mut reader =
+        csv::Reader::from_path(candles_path(dir, pair, interval)).map_err(Error::storage)?;
+    let mut candles = vec![];
+    for record in reader.deserialize() {
+        candles.push(record.map_err(Error::storage)?);
+    }
+    return Ok(candles);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        append_trades, build_candles, load_cursor, load_state, load_trades, save_cursor,
+        truncate_trades, TradeRecord,
+    };
+    use crate::Error;
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    fn trade(price: &str, volume: &str, time: f64) -> TradeRecord {
+        TradeRecord {
+            price: Decimal::from_str(price).unwrap(),
+            volume: Decimal::from_str(volume).unwrap(),
+            time,
+            side: "b".to_string(),
+            ordertype: "l".to_string(),
+            misc: "".to_string(),
+        }
+    }
+
+    #[test]
+    fn build_candles_test() {
+        let trades = vec![
+            trade("100", "1", 60.5),
+            trade("110", "1", 90.0),
+            trade("90", "2", 119.9),
+            trade("95", "1", 300.0),
+        ];
+        let candles = build_candles(&trades, 1);
+        assert_eq!(candles.len(), 2);
+        let first = &candles[0];
+        assert_eq!(first.time, 60);
+        assert_eq!(first.open, Decimal::from(100));
+        assert_eq!(first.high, Decimal::from(110));
+        assert_eq!(first.low, Decimal::from(90));
+        assert_eq!(first.close, Decimal::from(90));
+        assert_eq!(first.volume, Decimal::from(4));
+        assert_eq!(first.vwap, Decimal::from_str("97.5").unwrap());
+        assert_eq!(first.count, 3);
+        assert_eq!(candles[1].time, 300);
+    }
+
+    #[test]
+    fn resume_test() {
+        let dir = std::env::temp_dir().join(format!("kraken-backfill-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let length = append_trades(&dir, "XBTUSD", &[trade("100", "1", 60.0)]).unwrap();
+        save_cursor(&dir, "XBTUSD", 61_000_000_000, length).unwrap();
+        // a page appended before the process died, without its cursor.
+        append_trades(&dir, "XBTUSD", &[trade("101", "1", 62.0)]).unwrap();
+
+        let state = load_state(&dir, "XBTUSD").unwrap();
+        assert_eq!(state, Some((61_000_000_000, length)));
+        truncate_trades(&dir, "XBTUSD", length).unwrap();
+        assert_eq!(load_trades(&dir, "XBTUSD").unwrap().len(), 1);
+
+        // without the length the stored trades cannot be trusted.
+        std::fs::write(dir.join("XBTUSD.cursor"), "42").unwrap();
+        assert!(matches!(
+            load_cursor(&dir, "XBTUSD"),
+            Err(Error::Invalid(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,536 @@
+use crate::backfill::{self, TradeRecord};
+use crate::exchange::{Exchange, MarketEvent};
+use crate::fees::Liquidity;
+use crate::paper::{self, Account};
+use crate::private::{
+    AddOrderDescr, AddOrderResponse, BalanceResponse, CancelAllResponse, CancelOrderResponse,
+    ClosedOrdersResponse, NewOrder, OpenOrdersResponse, QueryOrdersResponse, TradesHistoryResponse,
+};
+use crate::public::{AssetPair, OHLCResponse, OHLC};
+use crate::strategy::{Runtime, Strategy};
+use crate::validation::Validator;
+use crate::Error;
+use async_trait::async_trait;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 3600.0;
+
+/// Returns the time, open, high, low and close of an event. Books fill
+/// nothing and return `None`.
+fn bar(event: &MarketEvent) -> Option<(f64, Decimal, Decimal, Decimal, Decimal)> {
+    return match event {
+        MarketEvent::Candle { candle: c, .. } => {
+            Some((c.time as f64, c.open, c.high, c.low, c.close))
+        }
+        MarketEvent::Trades { trades, .. } => {
+            let (first, last) = (trades.first()?, trades.last()?);
+            let high = trades.iter().map(|t| t.0).max()?;
+            let low = trades.iter().map(|t| t.0).min()?;
+            Some((last.2, first.0, high, low, last.0))
+        }
+        MarketEvent::Book { .. } => None,
+    };
+}
+
+/// Returns the candles of an OHLC response as events.
+pub fn candles(response: &OHLCResponse) -> Vec<MarketEvent> {
+    let pair = response
+        .0
+        .iter()
+        .find(|(_, value)| matches!(value, OHLC::Pair(_)))
+        .map(|(pair, _)| pair.clone())
+        .unwrap_or_default();
+    return backfill::from_ohlc(response)
+        .into_iter()
+        .map(|candle| MarketEvent::Candle {
+            pair: pair.clone(),
+            candle,
+        })
+        .collect();
+}
+
+/// Returns stored trades of a pair as events, one per trade.
+pub fn trades(pair: &str, records: &[TradeRecord]) -> Vec<MarketEvent> {
+    return records
+        .iter()
+        .map(|t| MarketEvent::Trades {
+            pair: pair.to_string(),
+            trades: vec![(t.price, t.volume, t.time)],
+        })
+        .collect();
+}
+
+/// An execution of a simulated order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fill {
+    pub order: String,
+    pub time: f64,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub ordertype: String,
+    pub price: Decimal,
+    pub volume: Decimal,
+    pub cost: Decimal,
+    pub fee: Decimal,
+    pub liquidity: Liquidity,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub initial_equity: Decimal,
+    pub final_equity: Decimal,
+    /// Equity in the quote currency at the close of each event.
+    pub equity: Vec<(f64, Decimal)>,
+    /// Largest fall from a peak of the equity, as a fraction of the peak.
+    pub max_drawdown: Decimal,
+    /// Annualized from the returns between events, `None` with fewer
+    /// than three events or a flat equity.
+    pub sharpe: Option<f64>,
+    pub fills: Vec<Fill>,
+    pub fees: Decimal,
+}
+
+struct State {
+    account: Account,
+    /// Open orders as placed, oldest first. Triggered stop-limit orders
+    /// turn into limit orders.
+    orders: Vec<(String, NewOrder)>,
+    fills: Vec<Fill>,
+    time: f64,
+}
+
+/// Account of a backtest, seen by the strategy as an exchange. Orders
+/// are checked against the precision and minimum size of the pair,
+/// rounding when needed, and fill from the next event on.
+struct Simulator {
+    pair: AssetPair,
+    slippage: Decimal,
+    state: Mutex<State>,
+}
+
+impl Simulator {
+    fn equity(&self, price: Decimal) -> Decimal {
+        let state = self.state.lock().unwrap();
+        let balance = |asset: &str| {
+            return state
+                .account
+                .balances
+                .get(asset)
+                .copied()
+                .unwrap_or_default();
+        };
+        return balance(&self.pair.quote) + balance(&self.pair.base) * price;
+    }
+
+    /// Returns the price and liquidity of an order filled by a bar.
+    /// Triggered stop-limit orders turn into limit orders.
+    fn execute(
+        &self,
+        order: &mut NewOrder,
+        open: Decimal,
+        high: Decimal,
+        low: Decimal,
+    ) -> Option<(Decimal, Liquidity)> {
+        let buy = order.type_ == "buy";
+        let price = order.price.unwrap_or_default();
+        let slipped = |price: Decimal| {
+            let slip = if buy { self.slippage } else { -self.slippage };
+            return (price * (Decimal::ONE + slip)).round_dp(self.pair.pair_decimals as u32);
+        };
+        // a buy stop-loss and a sell take-profit trigger on the way up.
+        let rising = buy == (order.ordertype.starts_with("stop-loss"));
+        let triggered = if rising { high >= price } else { low <= price };
+        let trigger = if rising {
+            std::cmp::max(open, price)
+        } else {
+            std::cmp::min(open, price)
+        };
+        return match order.ordertype.as_str() {
+            "market" => Some((slipped(open), Liquidity::Taker)),
+            "limit" if buy && low <= price => match open <= price {
+                true => Some((open, Liquidity::Taker)),
+                false => Some((price, Liquidity::Maker)),
+            },
+            "limit" if !buy && high >= price => match open >= price {
+                true => Some((open, Liquidity::Taker)),
+                false => Some((price, Liquidity::Maker)),
+            },
+            "stop-loss" | "take-profit" if triggered => Some((slipped(trigger), Liquidity::Taker)),
+            "stop-loss-limit" | "take-profit-limit" if triggered => {
+                order.ordertype = "limit".to_string();
+                order.price = order.price2.take();
+                None
+            }
+            _ => None,
+        };
+    }
+}
+
+#[async_trait]
+impl Exchange for Simulator {
+    async fn balance(&self) -> Result<BalanceResponse, Error> {
+        let balances = self.state.lock().unwrap().account.balances.clone();
+        Ok(BalanceResponse(balances))
+    }
+
+    async fn open_orders(&self) -> Result<OpenOrdersResponse, Error> {
+        let open = self.state.lock().unwrap().account.open.clone();
+        Ok(OpenOrdersResponse { open })
+    }
+
+    async fn closed_orders(&self) -> Result<ClosedOrdersResponse, Error> {
+        let closed = self.state.lock().unwrap().account.closed.clone();
+        Ok(ClosedOrdersResponse { closed })
+    }
+
+    async fn query_orders(&self, txids: &[&str]) -> Result<QueryOrdersResponse, Error> {
+        let state = self.state.lock().unwrap();
+        let account = &state.account;
+        let orders = txids
+            .iter()
+            .filter_map(|txid| {
+                let order = account.open.get(*txid).or(account.closed.get(*txid))?;
+                Some((txid.to_string(), order.clone()))
+            })
+            .collect();
+        Ok(QueryOrdersResponse(orders))
+    }
+
+    async fn trades_history(&self) -> Result<TradesHistoryResponse, Error> {
+        let trades = self.state.lock().unwrap().account.trades.clone();
+        Ok(TradesHistoryResponse {
+            count: trades.len() as u64,
+            trades,
+        })
+    }
+
+    async fn add_order(&self, new: &NewOrder, validate: bool) -> Result<AddOrderResponse, Error> {
+        if paper::UNSIMULATED.contains(&new.ordertype.as_str()) {
+            return Err(Error::API(
+                "EGeneral:Invalid arguments:ordertype".to_string(),
+            ));
+        }
+        let order = Validator::new(&self.pair)
+            .round(true)
+            .validate(new)
+            .map_err(|e| Error::API(format!("EGeneral:Invalid arguments:{}", e)))?;
+        let price = match order.ordertype.as_str() {
+            "market" => Decimal::ZERO,
+            _ => order.price.unwrap_or_default(),
+        };
+        let price2 = order.price2.unwrap_or_default();
+        let mut state = self.state.lock().unwrap();
+        let placed = paper::open_order(&order, &self.pair, price, price2, state.time);
+        let descr = AddOrderDescr {
+            order: placed.descr.order.clone(),
+            close: None,
+        };
+        if validate {
+            return Ok(AddOrderResponse { descr, txid: None });
+        }
+        state.account.next_id += 1;
+        let txid = format!(
+            "O{:05}-BTEST-{:06}",
+            state.account.next_id, state.account.next_id
+        );
+        state.account.open.insert(txid.clone(), placed);
+        state.orders.push((txid.clone(), order));
+        Ok(AddOrderResponse {
+            descr,
+            txid: Some(vec![txid]),
+        })
+    }
+
+    async fn cancel_order(&self, txid: &str) -> Result<CancelOrderResponse, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.orders.retain(|(t, _)| t != txid);
+        match state.account.close(txid, "canceled") {
+            Some(()) => Ok(CancelOrderResponse {
+                count: 1,
+                pending: None,
+            }),
+            None => Err(Error::API("EOrder:Unknown order".to_string())),
+        }
+    }
+
+    async fn cancel_all(&self) -> Result<CancelAllResponse, Error> {
+        let mut state = self.state.lock().unwrap();
+        let txids: Vec<String> = state.orders.drain(..).map(|(txid, _)| txid).collect();
+        for txid in txids.iter() {
+            state.account.close(txid, "canceled");
+        }
+        Ok(CancelAllResponse {
+            count: txids.len() as u64,
+        })
+    }
+
+    /// Fills the open orders the event reaches.
+    fn observe(&self, event: &MarketEvent) -> Result<(), Error> {
+        let (time, open, high, low, _) = match bar(event) {
+            Some(bar) => bar,
+            None => return Ok(()),
+        };
+        let mut state = self.state.lock().unwrap();
+        state.time = time;
+        for (txid, mut order) in std::mem::take(&mut state.orders) {
+            let (price, liquidity) = match self.execute(&mut order, open, high, low) {
+                Some(fill) => fill,
+                None => {
+                    state.orders.push((txid, order));
+                    continue;
+                }
+            };
+            let pair = (self.pair.altname.as_str(), &self.pair);
+            let fee = state
+                .account
+                .fill(&txid, pair, price, order.volume, liquidity, time);
+            state.fills.push(Fill {
+                order: txid,
+                time,
+                type_: order.type_,
+                ordertype: order.ordertype,
+                price,
+                volume: order.volume,
+                cost: price * order.volume,
+                fee,
+                liquidity,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Replays events of a single pair through a `strategy::Strategy`, whose
+/// orders go to a simulated account. Market and triggered stop orders
+/// fill at the open of the next event moved by the slippage and pay the
+/// taker fee; limit orders fill at their price once the range of an event
+/// reaches it and pay the maker fee, or the taker fee when the event
+/// opens through them. Balances are not checked.
+pub struct Backtest<'a> {
+    pair: &'a AssetPair,
+    cash: Decimal,
+    position: Decimal,
+    slippage: Decimal,
+    fee_volume: Decimal,
+}
+
+impl<'a> Backtest<'a> {
+    pub fn new(pair: &'a AssetPair) -> Self {
+        Self {
+            pair,
+            cash: Decimal::ZERO,
+            position: Decimal::ZERO,
+            slippage: Decimal::ZERO,
+            fee_volume: Decimal::ZERO,
+        }
+    }
+
+    /// Initial balances in the quote and base currencies.
+    pub fn with_balances(mut self, cash: Decimal, position: Decimal) -> Self {
+        self.cash = cash;
+        self.position = position;
+        return self;
+    }
+
+    /// Slippage of market and stop orders, in basis points.
+    pub fn with_slippage(mut self, bps: Decimal) -> Self {
+        self.slippage = bps / Decimal::from(10_000);
+        return self;
+    }
+
+    /// 30-day volume of the account when the backtest starts, which
+    /// picks the fee tier along with the simulated volume.
+    pub fn with_fee_volume(mut self, volume: Decimal) -> Self {
+        self.fee_volume = volume;
+        return self;
+    }
+
+    /// The strategy sees each event after the orders were matched against
+    /// it, and the updates of its orders like on a live exchange.
+    pub async fn run<S, I>(self, strategy: &mut S, events: I) -> Result<Report, Error>
+    where
+        S: Strategy,
+        I: IntoIterator<Item = MarketEvent>,
+    {
+        let mut account = Account {
+            volume: self.fee_volume,
+            ..Default::default()
+        };
+        account.balances.insert(self.pair.quote.clone(), self.cash);
+        account
+            .balances
+            .insert(self.pair.base.clone(), self.position);
+        let state = State {
+            account,
+            orders: vec![],
+            fills: vec![],
+            time: 0.0,
+        };
+        let simulator = Arc::new(Simulator {
+            pair: self.pair.clone(),
+            slippage: self.slippage,
+            state: Mutex::new(state),
+        });
+        let mut runtime = Runtime::new(Box::new(simulator.clone()), &[]);
+        let mut equity = vec![];
+        let mut initial_equity = None;
+        for event in events {
+            let bar = bar(&event);
+            if let (None, Some((_, open, ..))) = (initial_equity, bar) {
+                initial_equity = Some(simulator.equity(open));
+            }
+            runtime
+                .process(strategy, std::slice::from_ref(&event))
+                .await?;
+            if let Some((time, .., close)) = bar {
+                equity.push((time, simulator.equity(close)));
+            }
+        }
+        let fills = simulator.state.lock().unwrap().fills.clone();
+        let initial_equity = initial_equity.unwrap_or(self.cash);
+        return Ok(Report {
+            initial_equity,
+            final_equity: equity.last().map(|e| e.1).unwrap_or(initial_equity),
+            max_drawdown: max_drawdown(&equity),
+            sharpe: sharpe(&equity),
+            fees: fills.iter().map(|f| f.fee).sum(),
+            fills,
+            equity,
+        });
+    }
+}
+
+fn max_drawdown(equity: &[(f64, Decimal)]) -> Decimal {
+    let mut peak = Decimal::ZERO;
+    let mut drawdown = Decimal::ZERO;
+    for (_, value) in equity {
+        peak = std::cmp::max(peak, *value);
+        if peak > Decimal::ZERO {
+            drawdown = std::cmp::max(drawdown, (peak - value) / peak);
+        }
+    }
+    return drawdown;
+}
+
+fn sharpe(equity: &[(f64, Decimal)]) -> Option<f64> {
+    // there is no return on an equity wiped out.
+    let returns: Vec<f64> = equity
+        .windows(2)
+        .filter(|w| w[0].1 > Decimal::ZERO)
+        .filter_map(|w| (w[1].1 / w[0].1 - Decimal::ONE).to_f64())
+        .collect();
+    if returns.len() < 2 {
+        return None;
+    }
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    let period = (equity[equity.len() - 1].0 - equity[0].0) / n;
+    if variance <= 0.0 || period <= 0.0 {
+        return None;
+    }
+    return Some(mean / variance.sqrt() * (SECONDS_PER_YEAR / period).sqrt());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sharpe, Backtest};
+    use crate::exchange::MarketEvent;
+    use crate::fees::Liquidity;
+    use crate::strategy::{Context, Strategy, Update};
+    use crate::testing::{candle, candle_event, d, order, pair};
+    use crate::Error;
+    use async_trait::async_trait;
+    use rust_decimal::Decimal;
+
+    /// Buys at market on the first event, then places a take profit
+    /// limit order and a protective stop.
+    struct Breakout {
+        step: usize,
+        fills: usize,
+    }
+
+    #[async_trait]
+    impl Strategy for Breakout {
+        async fn on_market(
+            &mut self,
+            _event: &MarketEvent,
+            ctx: &mut Context<'_>,
+        ) -> Result<(), Error> {
+            self.step += 1;
+            match self.step {
+                1 => {
+                    ctx.submit(&order("buy", "market", "1.000000001", None))
+                        .await?;
+                }
+                2 => {
+                    ctx.submit(&order("sell", "limit", "0.5", Some("110")))
+                        .await?;
+                    ctx.submit(&order("sell", "stop-loss", "0.5", Some("95")))
+                        .await?;
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+
+        async fn on_order(&mut self, update: &Update, _ctx: &mut Context<'_>) -> Result<(), Error> {
+            if matches!(update, Update::Filled { .. }) {
+                self.fills += 1;
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn backtest_test() {
+        let pair = pair();
+        let events = vec![
+            candle_event(candle(0, "100", "101", "99", "100", "1")),
+            candle_event(candle(60, "100", "105", "99", "104", "1")),
+            candle_event(candle(120, "104", "108", "103", "107", "1")),
+            candle_event(candle(180, "107", "112", "106", "111", "1")),
+            candle_event(candle(240, "97", "98", "90", "92", "1")),
+        ];
+        let mut breakout = Breakout { step: 0, fills: 0 };
+        let report = Backtest::new(&pair)
+            .with_balances(d("1000"), Decimal::ZERO)
+            .with_slippage(d("10"))
+            .run(&mut breakout, events)
+            .await
+            .unwrap();
+        assert_eq!(report.fills.len(), 3);
+        assert_eq!(breakout.fills, 3);
+        // rounded down to the lot decimals, 10 bps above the open.
+        let buy = &report.fills[0];
+        assert_eq!(buy.volume, d("1"));
+        assert_eq!(buy.price, d("100.1"));
+        assert_eq!(buy.fee, d("0.26026"));
+        let limit = &report.fills[1];
+        assert_eq!((limit.price, limit.liquidity), (d("110"), Liquidity::Maker));
+        assert_eq!(limit.fee, d("0.088"));
+        // 10 bps below the trigger.
+        let stop = &report.fills[2];
+        assert_eq!((stop.price, stop.liquidity), (d("94.9"), Liquidity::Taker));
+        assert_eq!(report.final_equity, d("1001.87837"));
+        assert_eq!(report.fees, report.fills.iter().map(|f| f.fee).sum());
+        assert_eq!(report.equity.len(), 5);
+        assert!(report.max_drawdown > d("0.005"));
+        assert!(report.sharpe.is_some());
+    }
+
+    #[test]
+    fn sharpe_test() {
+        let equity = [
+            (0.0, d("100")),
+            (60.0, d("0")),
+            (120.0, d("50")),
+            (180.0, d("60")),
+        ];
+        assert!(sharpe(&equity).is_some());
+        assert_eq!(sharpe(&equity[..3]), None);
+    }
+}
@@ -0,0 +1,780 @@
+use crate::exchange::{self, Exchange, MarketEvent};
+use crate::private::NewOrder;
+use crate::strategy::{OrderManager, OrderState};
+use crate::Error;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Distance of a trailing stop from the best price seen.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Offset {
+    /// Percentage of the best price.
+    Percent(Decimal),
+    Absolute(Decimal),
+}
+
+impl Offset {
+    fn from(&self, price: Decimal) -> Decimal {
+        match self {
+            Offset::Percent(percent) => price * percent / Decimal::ONE_HUNDRED,
+            Offset::Absolute(offset) => *offset,
+        }
+    }
+}
+
+/// When a leg is sent to the exchange.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Activation {
+    /// Right away, typically a limit order resting on the book.
+    Now,
+    /// Once the price reaches `trigger`: at or above it for a buy, at or
+    /// below it for a sell.
+    Stop { trigger: Decimal },
+    /// Once the price comes back by `offset` from the best price seen
+    /// since the leg is armed: the highest for a sell, the lowest for a
+    /// buy.
+    Trailing {
+        offset: Offset,
+        extreme: Option<Decimal>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LegState {
+    Waiting,
+    /// Handed to the order manager under `userref`.
+    Sent,
+    /// Canceled before being sent.
+    Dropped,
+}
+
+/// An order of a group and the condition to send it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Leg {
+    pub order: NewOrder,
+    pub activation: Activation,
+    pub state: LegState,
+    pub userref: Option<i32>,
+}
+
+impl Leg {
+    pub fn new(order: NewOrder) -> Self {
+        return Self::with_activation(order, Activation::Now);
+    }
+
+    pub fn stop(order: NewOrder, trigger: Decimal) -> Self {
+        return Self::with_activation(order, Activation::Stop { trigger });
+    }
+
+    pub fn trailing(order: NewOrder, offset: Offset) -> Self {
+        return Self::with_activation(
+            order,
+            Activation::Trailing {
+                offset,
+                extreme: None,
+            },
+        );
+    }
+
+    fn with_activation(order: NewOrder, activation: Activation) -> Self {
+        Self {
+            order,
+            activation,
+            state: LegState::Waiting,
+            userref: None,
+        }
+    }
+
+    /// Follows the price and tells whether the leg is to be sent.
+    fn triggered(&mut self, price: Option<Decimal>) -> bool {
+        let buy = self.order.type_ == "buy";
+        let price = match (&self.activation, price) {
+            (Activation::Now, _) => return true,
+            (_, None) => return false,
+            (_, Some(price)) => price,
+        };
+        match &mut self.activation {
+            Activation::Stop { trigger } if buy => price >= *trigger,
+            Activation::Stop { trigger } => price <= *trigger,
+            Activation::Trailing { offset, extreme } => {
+                let best = match (*extreme, buy) {
+                    (Some(extreme), true) => std::cmp::min(extreme, price),
+                    (Some(extreme), false) => std::cmp::max(extreme, price),
+                    (None, _) => price,
+                };
+                *extreme = Some(best);
+                match buy {
+                    true => price >= best + offset.from(best),
+                    false => price <= best - offset.from(best),
+                }
+            }
+            Activation::Now => true,
+        }
+    }
+}
+
+/// Linked orders on a pair: an optional entry, then exit legs armed with
+/// the volume executed by the entry, of which the first to fill or to
+/// trigger cancels the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    pub id: u64,
+    /// Pair as named by the market data, e.g. XXBTZUSD.
+    pub pair: String,
+    pub entry: Option<Leg>,
+    pub legs: Vec<Leg>,
+    pub armed: bool,
+    pub done: bool,
+}
+
+/// Emulates OCO pairs, bracket orders and trailing stops by watching the
+/// price of their pairs, the last trade or the middle of the book, and
+/// managing their orders on any `Exchange`. Stop and trailing legs stay
+/// on the client until triggered, so they do not hold funds. The state
+/// is saved to the path given with `with_path` after every change and
+/// before each order is sent, so a restarted supervisor finds the orders
+/// placed, filled or lost meanwhile.
+#[derive(Serialize, Deserialize)]
+pub struct Supervisor {
+    groups: Vec<Group>,
+    orders: OrderManager,
+    prices: HashMap<String, Decimal>,
+    next_id: u64,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self {
+            groups: vec![],
+            orders: OrderManager::new(),
+            prices: HashMap::new(),
+            next_id: 0,
+            path: None,
+        }
+    }
+
+    /// Loads a state saved by `save` and keeps saving it to `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let content = std::fs::read_to_string(&path)?;
+        let supervisor: Self = serde_json::from_str(&content)?;
+        return Ok(supervisor.with_path(path));
+    }
+
+    pub fn with_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.path = Some(path.as_ref().to_path_buf());
+        return self;
+    }
+
+    /// Writes the state to a temporary file first, so an interrupted save
+    /// leaves the previous state.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(tmp, path)?;
+        return Ok(());
+    }
+
+    fn persist(&self) -> Result<(), Error> {
+        return match &self.path {
+            Some(path) => self.save(path),
+            None => Ok(()),
+        };
+    }
+
+    pub fn groups(&self) -> &[Group] {
+        return &self.groups;
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Group> {
+        return self.groups.iter().find(|g| g.id == id);
+    }
+
+    pub fn orders(&self) -> &OrderManager {
+        return &self.orders;
+    }
+
+    /// Pairs of the groups that are not done.
+    pub fn pairs(&self) -> Vec<String> {
+        let mut pairs: Vec<String> = self
+            .groups
+            .iter()
+            .filter(|g| !g.done)
+            .map(|g| g.pair.clone())
+            .collect();
+        pairs.sort();
+        pairs.dedup();
+        return pairs;
+    }
+
+    /// Adds a group and returns its id. Without entry the legs are armed
+    /// right away.
+    pub fn add(&mut self, pair: &str, entry: Option<Leg>, legs: Vec<Leg>) -> u64 {
+        self.next_id += 1;
+        self.groups.push(Group {
+            id: self.next_id,
+            pair: pair.to_string(),
+            armed: entry.is_none(),
+            entry,
+            legs,
+            done: false,
+        });
+        return self.next_id;
+    }
+
+    /// Two legs, the first to fill or trigger cancels the other.
+    pub fn add_oco(&mut self, pair: &str, first: Leg, second: Leg) -> u64 {
+        return self.add(pair, None, vec![first, second]);
+    }
+
+    /// An entry order, closed once executed by a limit order at
+    /// `take_profit` or a market order when the price reaches `stop`.
+    /// The stop also covers a partially filled entry, whose rest is
+    /// canceled when it triggers.
+    pub fn add_bracket(
+        &mut self,
+        pair: &str,
+        entry: NewOrder,
+        take_profit: Decimal,
+        stop: Decimal,
+    ) -> u64 {
+        let side = match entry.type_.as_str() {
+            "buy" => "sell",
+            _ => "buy",
+        };
+        let exit = |ordertype: &str, price: Option<Decimal>| NewOrder {
+            pair: entry.pair.clone(),
+            type_: side.to_string(),
+            ordertype: ordertype.to_string(),
+            volume: entry.volume,
+            price,
+            ..Default::default()
+        };
+        let legs = vec![
+            Leg::new(exit("limit", Some(take_profit))),
+            Leg::stop(exit("market", None), stop),
+        ];
+        return self.add(pair, Some(Leg::new(entry)), legs);
+    }
+
+    /// `order`, usually a market order, sent once the price comes back
+    /// by `offset` from its best.
+    pub fn add_trailing_stop(&mut self, pair: &str, order: NewOrder, offset: Offset) -> u64 {
+        return self.add(pair, None, vec![Leg::trailing(order, offset)]);
+    }
+
+    /// Cancels the orders of a group and marks it done.
+    pub async fn cancel(&mut self, exchange: &dyn Exchange, id: u64) -> Result<(), Error> {
+        let g = self
+            .groups
+            .iter()
+            .position(|g| g.id == id)
+            .ok_or_else(|| Error::Invalid(format!("id, unknown group {}", id)))?;
+        let group = &self.groups[g];
+        let userrefs: Vec<i32> = group
+            .entry
+            .iter()
+            .chain(group.legs.iter())
+            .filter(|leg| self.is_live(leg))
+            .filter_map(|leg| leg.userref)
+            .collect();
+        for userref in userrefs {
+            self.cancel_order(exchange, userref).await?;
+        }
+        let group = &mut self.groups[g];
+        for leg in group.entry.iter_mut().chain(group.legs.iter_mut()) {
+            if leg.state == LegState::Waiting {
+                leg.state = LegState::Dropped;
+            }
+        }
+        group.done = true;
+        return self.persist();
+    }
+
+    fn leg(&mut self, g: usize, l: Option<usize>) -> &mut Leg {
+        let group = &mut self.groups[g];
+        return match l {
+            Some(l) => &mut group.legs[l],
+            None => group.entry.as_mut().unwrap(),
+        };
+    }
+
+    fn is_live(&self, leg: &Leg) -> bool {
+        return match leg.state {
+            LegState::Waiting => true,
+            LegState::Dropped => false,
+            LegState::Sent => match leg.userref.and_then(|u| self.orders.get(u)) {
+                Some(tracked) => !tracked.state.is_final(),
+                None => false,
+            },
+        };
+    }
+
+    fn executed(&self, leg: &Leg) -> Decimal {
+        return match leg.userref.and_then(|u| self.orders.get(u)) {
+            Some(tracked) => tracked.vol_exec,
+            None => Decimal::ZERO,
+        };
+    }
+
+    /// Sends a leg, saving its userref first.
+    async fn send(
+        &mut self,
+        exchange: &dyn Exchange,
+        g: usize,
+        l: Option<usize>,
+    ) -> Result<(), Error> {
+        let order = self.leg(g, l).order.clone();
+        let userref = self.orders.prepare(&order)?;
+        let leg = self.leg(g, l);
+        leg.userref = Some(userref);
+        leg.state = LegState::Sent;
+        self.persist()?;
+        match self.orders.send(exchange, userref).await {
+            // refused orders are tracked as rejected.
+            Ok(()) | Err(Error::API(_)) => {}
+            Err(e) => return Err(e),
+        }
+        return self.persist();
+    }
+
+    async fn cancel_order(&mut self, exchange: &dyn Exchange, userref: i32) -> Result<(), Error> {
+        match self.orders.cancel(exchange, userref).await {
+            Ok(()) => {}
+            // closed or lost meanwhile, the reconciliation tells how;
+            // otherwise the order may still rest on the book.
+            Err(Error::API(e)) => {
+                self.orders.reconcile(exchange).await?;
+                if !matches!(self.orders.get(userref), Some(t) if t.state.is_final()) {
+                    return Err(Error::API(e));
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        return Ok(());
+    }
+
+    /// Cancels or drops the legs of a group other than `keep`. Returns
+    /// whether none of them is live anymore, which the exchange may not
+    /// have confirmed yet for a canceled order.
+    async fn drop_others(
+        &mut self,
+        exchange: &dyn Exchange,
+        g: usize,
+        keep: usize,
+    ) -> Result<bool, Error> {
+        for l in 0..self.groups[g].legs.len() {
+            let leg = &self.groups[g].legs[l];
+            if l == keep || !self.is_live(leg) {
+                continue;
+            }
+            match (leg.state, leg.userref) {
+                (LegState::Sent, Some(userref)) => self.cancel_order(exchange, userref).await?,
+                _ => self.leg(g, Some(l)).state = LegState::Dropped,
+            }
+        }
+        self.persist()?;
+        let group = &self.groups[g];
+        return Ok((0..group.legs.len()).all(|l| l == keep || !self.is_live(&group.legs[l])));
+    }
+
+    async fn advance(&mut self, exchange: &dyn Exchange, g: usize) -> Result<(), Error> {
+        let price = self.prices.get(&self.groups[g].pair).copied();
+        if !self.groups[g].armed {
+            let entry = self.groups[g].entry.as_ref().unwrap();
+            if entry.state == LegState::Waiting {
+                if self.leg(g, None).triggered(price) {
+                    self.send(exchange, g, None).await?;
+                }
+                return Ok(());
+            }
+            if self.is_live(entry) {
+                let executed = self.executed(entry);
+                if executed.is_zero() {
+                    return Ok(());
+                }
+                // partially filled: the legs follow the executed volume
+                // and a triggered stop cancels the rest of the entry, so
+                // the filled part is closed; the others wait for the
+                // entry to be final.
+                let mut triggered = false;
+                for l in 0..self.groups[g].legs.len() {
+                    let leg = self.leg(g, Some(l));
+                    leg.order.volume = executed;
+                    triggered |= leg.activation != Activation::Now && leg.triggered(price);
+                }
+                if !triggered {
+                    return Ok(());
+                }
+                let userref = self.groups[g].entry.as_ref().unwrap().userref.unwrap();
+                self.cancel_order(exchange, userref).await?;
+                if self.is_live(self.groups[g].entry.as_ref().unwrap()) {
+                    return Ok(());
+                }
+            }
+            let entry = self.groups[g].entry.as_ref().unwrap();
+            let executed = self.executed(entry);
+            let group = &mut self.groups[g];
+            if executed.is_zero() {
+                group.done = true;
+                return Ok(());
+            }
+            for leg in group.legs.iter_mut() {
+                leg.order.volume = executed;
+            }
+            group.armed = true;
+        }
+        let count = self.groups[g].legs.len();
+        let filled = (0..count).find(|&l| !self.executed(&self.groups[g].legs[l]).is_zero());
+        match filled {
+            Some(l) => {
+                self.drop_others(exchange, g, l).await?;
+            }
+            None => {
+                for l in 0..count {
+                    let leg = self.leg(g, Some(l));
+                    if leg.state != LegState::Waiting || !leg.triggered(price) {
+                        continue;
+                    }
+                    if leg.activation != Activation::Now {
+                        // sent only once the others are final, or both
+                        // could fill; the next round tries again.
+                        if !self.drop_others(exchange, g, l).await? {
+                            continue;
+                        }
+                        // what the others filled while canceled is closed.
+                        let others: Decimal = (0..count)
+                            .filter(|&o| o != l)
+                            .map(|o| self.executed(&self.groups[g].legs[o]))
+                            .sum();
+                        let leg = self.leg(g, Some(l));
+                        leg.order.volume -= others;
+                        if leg.order.volume <= Decimal::ZERO {
+                            leg.state = LegState::Dropped;
+                            continue;
+                        }
+                    }
+                    self.send(exchange, g, Some(l)).await?;
+                }
+            }
+        }
+        let group = &self.groups[g];
+        if !group.legs.iter().any(|leg| self.is_live(leg)) {
+            self.groups[g].done = true;
+        }
+        return Ok(());
+    }
+
+    /// Shows the events to the exchange, reconciles the orders and moves
+    /// the groups on.
+    pub async fn process(
+        &mut self,
+        exchange: &dyn Exchange,
+        events: &[MarketEvent],
+    ) -> Result<(), Error> {
+        for event in events {
+            exchange.observe(event)?;
+            match event {
+                MarketEvent::Book { pair, book } => {
+                    if let (Some(bid), Some(ask)) = (book.bids.first(), book.asks.first()) {
+                        self.prices
+                            .insert(pair.clone(), (bid.0 + ask.0) / Decimal::TWO);
+                    }
+                }
+                MarketEvent::Trades { pair, trades } => {
+                    if let Some(last) = trades.last() {
+                        self.prices.insert(pair.clone(), last.0);
+                    }
+                }
+                MarketEvent::Candle { pair, candle } => {
+                    self.prices.insert(pair.clone(), candle.close);
+                }
+            }
+        }
+        // sent but unanswered, after a crash or a network error.
+        let pending: Vec<i32> = self
+            .orders
+            .active()
+            .iter()
+            .filter(|t| t.state == OrderState::Pending)
+            .map(|t| t.userref)
+            .collect();
+        self.orders.reconcile(exchange).await?;
+        for group in self.groups.iter_mut() {
+            for leg in group.entry.iter_mut().chain(group.legs.iter_mut()) {
+                let lost = leg
+                    .userref
+                    .filter(|u| pending.contains(u))
+                    .and_then(|u| self.orders.get(u));
+                if matches!(lost, Some(t) if t.state == OrderState::Rejected) {
+                    // never reached the exchange, sent again once triggered.
+                    leg.state = LegState::Waiting;
+                    leg.userref = None;
+                }
+            }
+        }
+        for g in 0..self.groups.len() {
+            if !self.groups[g].done {
+                self.advance(exchange, g).await?;
+            }
+        }
+        // groups only look at the tracked state.
+        self.orders.drain();
+        return self.persist();
+    }
+
+    /// Processes the market data of the pairs of the groups not done
+    /// every `interval` until all are. Network errors are retried at the
+    /// next round.
+    pub async fn run(&mut self, exchange: &dyn Exchange, interval: Duration) -> Result<(), Error> {
+        let mut since: HashMap<String, i64> = HashMap::new();
+        loop {
+            let pairs = self.pairs();
+            if pairs.is_empty() {
+                return Ok(());
+            }
+            match self.round(exchange, &pairs, &mut since).await {
+                Ok(()) | Err(Error::Request(_)) => {}
+                Err(e) => return Err(e),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    async fn round(
+        &mut self,
+        exchange: &dyn Exchange,
+        pairs: &[String],
+        since: &mut HashMap<String, i64>,
+    ) -> Result<(), Error> {
+        let mut events = vec![];
+        for pair in pairs {
+            let (fetched, last) = exchange::fetch(pair, since.get(pair).copied()).await?;
+            events.extend(fetched);
+            if let Some(last) = last {
+                since.insert(pair.clone(), last);
+            }
+        }
+        return self.process(exchange, &events).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Activation, LegState, Offset, Supervisor};
+    use crate::exchange::Exchange;
+    use crate::strategy::OrderState;
+    use crate::testing::{book_event, candle, candle_event, d, order, paper, Unreliable};
+    use crate::Error;
+
+    #[tokio::test]
+    async fn bracket_test() {
+        let paper = paper();
+        let mut supervisor = Supervisor::new();
+        let id = supervisor.add_bracket(
+            "XXBTZUSD",
+            order("buy", "limit", "1", Some("100")),
+            d("110"),
+            d("90"),
+        );
+
+        // the entry crosses the book, then the take profit rests.
+        supervisor
+            .process(&paper, &[book_event("99", "100")])
+            .await
+            .unwrap();
+        supervisor
+            .process(&paper, &[book_event("99", "100")])
+            .await
+            .unwrap();
+        let group = supervisor.get(id).unwrap();
+        assert!(group.armed);
+        assert_eq!(group.legs[0].state, LegState::Sent);
+        assert_eq!(group.legs[1].state, LegState::Waiting);
+        assert_eq!(paper.open_orders().await.unwrap().open.len(), 1);
+
+        // the stop cancels the take profit and sells at the bid, which the
+        // next round sees.
+        supervisor
+            .process(&paper, &[book_event("88", "90")])
+            .await
+            .unwrap();
+        assert!(!supervisor.get(id).unwrap().done);
+        supervisor
+            .process(&paper, &[book_event("88", "90")])
+            .await
+            .unwrap();
+        let group = supervisor.get(id).unwrap().clone();
+        assert!(group.done);
+        let take_profit = supervisor
+            .orders()
+            .get(group.legs[0].userref.unwrap())
+            .unwrap();
+        assert_eq!(take_profit.state, OrderState::Canceled);
+        let stop = supervisor
+            .orders()
+            .get(group.legs[1].userref.unwrap())
+            .unwrap();
+        assert_eq!((stop.state, stop.vol_exec), (OrderState::Closed, d("1")));
+        assert!(paper.open_orders().await.unwrap().open.is_empty());
+        assert_eq!(paper.account().balances["XXBT"], d("0"));
+    }
+
+    #[tokio::test]
+    async fn partial_entry_test() {
+        let paper = paper();
+        let mut supervisor = Supervisor::new();
+        let id = supervisor.add_bracket(
+            "XXBTZUSD",
+            order("buy", "limit", "8", Some("100")),
+            d("110"),
+            d("90"),
+        );
+
+        // the entry takes the 5 offered, the rest stays on the book.
+        supervisor
+            .process(&paper, &[book_event("99", "100")])
+            .await
+            .unwrap();
+        supervisor
+            .process(&paper, &[book_event("99", "101")])
+            .await
+            .unwrap();
+        let group = supervisor.get(id).unwrap();
+        assert!(!group.armed);
+        assert!(group.legs.iter().all(|leg| leg.order.volume == d("5")));
+        assert!(group.legs.iter().all(|leg| leg.state == LegState::Waiting));
+
+        // the stop cancels the rest of the entry and sells what it bought.
+        let low = candle_event(candle(0, "89", "89", "89", "89", "1"));
+        for _ in 0..2 {
+            supervisor
+                .process(&paper, std::slice::from_ref(&low))
+                .await
+                .unwrap();
+        }
+        let group = supervisor.get(id).unwrap().clone();
+        assert!(group.done);
+        let entry = supervisor
+            .orders()
+            .get(group.entry.unwrap().userref.unwrap())
+            .unwrap();
+        assert_eq!(entry.state, OrderState::Canceled);
+        assert_eq!(entry.vol_exec, d("5"));
+        let take_profit = supervisor
+            .orders()
+            .get(group.legs[0].userref.unwrap())
+            .unwrap();
+        assert_eq!(take_profit.state, OrderState::Canceled);
+        let stop = supervisor
+            .orders()
+            .get(group.legs[1].userref.unwrap())
+            .unwrap();
+        assert_eq!(stop.vol_exec, d("5"));
+        assert_eq!(paper.balance().await.unwrap().0["XXBT"], d("0"));
+    }
+
+    #[tokio::test]
+    async fn trailing_stop_test() {
+        let paper = paper().with_balance("XBT", d("1"));
+        let path =
+            std::env::temp_dir().join(format!("kraken-conditional-{}.json", std::process::id()));
+        let mut supervisor = Supervisor::new().with_path(&path);
+        let stop = supervisor.add_trailing_stop(
+            "XXBTZUSD",
+            order("sell", "market", "1", None),
+            Offset::Percent(d("5")),
+        );
+        let oco = supervisor.add_oco(
+            "XXBTZUSD",
+            super::Leg::new(order("buy", "limit", "0.1", Some("50"))),
+            super::Leg::stop(order("buy", "market", "0.1", None), d("200")),
+        );
+        supervisor
+            .process(&paper, &[book_event("99", "101")])
+            .await
+            .unwrap();
+        supervisor
+            .process(&paper, &[book_event("119", "121")])
+            .await
+            .unwrap();
+
+        // resumes from the saved state.
+        let mut supervisor = Supervisor::load(&path).unwrap();
+        let leg = &supervisor.get(stop).unwrap().legs[0];
+        assert_eq!(
+            leg.activation,
+            Activation::Trailing {
+                offset: Offset::Percent(d("5")),
+                extreme: Some(d("120"))
+            }
+        );
+        supervisor
+            .process(&paper, &[book_event("114", "116")])
+            .await
+            .unwrap();
+        assert_eq!(
+            supervisor.get(stop).unwrap().legs[0].state,
+            LegState::Waiting
+        );
+        supervisor
+            .process(&paper, &[book_event("112", "114")])
+            .await
+            .unwrap();
+        assert_eq!(supervisor.get(stop).unwrap().legs[0].state, LegState::Sent);
+        supervisor
+            .process(&paper, &[book_event("112", "114")])
+            .await
+            .unwrap();
+        assert!(supervisor.get(stop).unwrap().done);
+        assert_eq!(paper.account().balances["XXBT"], d("0"));
+
+        supervisor.cancel(&paper, oco).await.unwrap();
+        assert!(supervisor.pairs().is_empty());
+        assert!(paper.open_orders().await.unwrap().open.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_cancel_test() {
+        let exchange = Unreliable::new(paper()).with_cancel_order("EService:Busy");
+        let mut supervisor = Supervisor::new();
+        let id = supervisor.add_oco(
+            "XXBTZUSD",
+            super::Leg::new(order("buy", "limit", "0.1", Some("90"))),
+            super::Leg::stop(order("buy", "market", "0.1", None), d("110")),
+        );
+        supervisor
+            .process(&exchange, &[book_event("99", "101")])
+            .await
+            .unwrap();
+
+        // the limit order may still fill, the stop waits for its cancel.
+        assert!(supervisor
+            .process(&exchange, &[book_event("109", "111")])
+            .await
+            .is_err());
+        let group = supervisor.get(id).unwrap();
+        assert_eq!(group.legs[1].state, LegState::Waiting);
+        assert_eq!(exchange.open_orders().await.unwrap().open.len(), 1);
+
+        assert!(matches!(
+            supervisor.cancel(&exchange, id + 1).await,
+            Err(Error::Invalid(_))
+        ));
+    }
+}
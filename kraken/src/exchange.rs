@@ -0,0 +1,167 @@
+use crate::backfill::Candle;
+use crate::private::{
+    self, AddOrderResponse, BalanceResponse, CancelAllResponse, CancelOrderResponse,
+    ClosedOrdersResponse, NewOrder, OpenOrdersResponse, QueryOrdersResponse, TradesHistoryResponse,
+};
+use crate::public::{self, OrderBook};
+use crate::{Credential, Error};
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use std::sync::Arc;
+
+/// Public market data of a pair, keyed by pair id.
+#[derive(Debug)]
+pub enum MarketEvent {
+    Book {
+        pair: String,
+        book: OrderBook,
+    },
+    /// Trades as (price, volume, time), oldest first.
+    Trades {
+        pair: String,
+        trades: Vec<(Decimal, Decimal, f64)>,
+    },
+    /// A candle of the history replayed by a backtest.
+    Candle {
+        pair: String,
+        candle: Candle,
+    },
+}
+
+/// Fetches the book of a pair and its trades since the `since` cursor.
+/// Returns the events and the cursor of the next call; without a cursor
+/// the recent trades are history and only the book is returned.
+pub async fn fetch(
+    pair: &str,
+    since: Option<i64>,
+) -> Result<(Vec<MarketEvent>, Option<i64>), Error> {
+    let mut events = vec![];
+    for (pair, book) in public::depth(pair, Some(100)).await?.0 {
+        events.push(MarketEvent::Book { pair, book });
+    }
+    let mut last = None;
+    for (key, value) in public::trades(pair, since).await?.0 {
+        match value {
+            public::Trade::Pair(data) if since.is_some() => {
+                let trades = data.0.iter().map(|t| (t.0, t.1, t.2)).collect();
+                events.push(MarketEvent::Trades { pair: key, trades });
+            }
+            public::Trade::Last(cursor) => last = cursor.parse::<i64>().ok(),
+            _ => {}
+        }
+    }
+    return Ok((events, last));
+}
+
+/// Account operations shared by the exchange and the paper trading
+/// simulator, so strategies can run against either of them.
+#[async_trait]
+pub trait Exchange: Send + Sync {
+    async fn balance(&self) -> Result<BalanceResponse, Error>;
+
+    async fn open_orders(&self) -> Result<OpenOrdersResponse, Error>;
+
+    async fn closed_orders(&self) -> Result<ClosedOrdersResponse, Error>;
+
+    async fn query_orders(&self, txids: &[&str]) -> Result<QueryOrdersResponse, Error>;
+
+    async fn trades_history(&self) -> Result<TradesHistoryResponse, Error>;
+
+    async fn add_order(&self, order: &NewOrder, validate: bool) -> Result<AddOrderResponse, Error>;
+
+    async fn cancel_order(&self, txid: &str) -> Result<CancelOrderResponse, Error>;
+
+    async fn cancel_all(&self) -> Result<CancelAllResponse, Error>;
+
+    /// Market data seen by a strategy runtime, which simulated exchanges
+    /// fill their orders against.
+    fn observe(&self, _event: &MarketEvent) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Lets the owner of an exchange handed to a runtime keep using it.
+#[async_trait]
+impl<T: Exchange + ?Sized> Exchange for Arc<T> {
+    async fn balance(&self) -> Result<BalanceResponse, Error> {
+        self.as_ref().balance().await
+    }
+
+    async fn open_orders(&self) -> Result<OpenOrdersResponse, Error> {
+        self.as_ref().open_orders().await
+    }
+
+    async fn closed_orders(&self) -> Result<ClosedOrdersResponse, Error> {
+        self.as_ref().closed_orders().await
+    }
+
+    async fn query_orders(&self, txids: &[&str]) -> Result<QueryOrdersResponse, Error> {
+        self.as_ref().query_orders(txids).await
+    }
+
+    async fn trades_history(&self) -> Result<TradesHistoryResponse, Error> {
+        self.as_ref().trades_history().await
+    }
+
+    async fn add_order(&self, order: &NewOrder, validate: bool) -> Result<AddOrderResponse, Error> {
+        self.as_ref().add_order(order, validate).await
+    }
+
+    async fn cancel_order(&self, txid: &str) -> Result<CancelOrderResponse, Error> {
+        self.as_ref().cancel_order(txid).await
+    }
+
+    async fn cancel_all(&self) -> Result<CancelAllResponse, Error> {
+        self.as_ref().cancel_all().await
+    }
+
+    fn observe(&self, event: &MarketEvent) -> Result<(), Error> {
+        self.as_ref().observe(event)
+    }
+}
+
+/// Kraken itself, through the private endpoints.
+pub struct Live {
+    cred: Credential,
+}
+
+impl Live {
+    pub fn new(cred: Credential) -> Self {
+        Self { cred }
+    }
+}
+
+#[async_trait]
+impl Exchange for Live {
+    async fn balance(&self) -> Result<BalanceResponse, Error> {
+        private::balance(&self.cred).await
+    }
+
+    async fn open_orders(&self) -> Result<OpenOrdersResponse, Error> {
+        private::open_orders(&self.cred, None, None).await
+    }
+
+    async fn closed_orders(&self) -> Result<ClosedOrdersResponse, Error> {
+        private::closed_orders(&self.cred, None, None, None, None, None, None).await
+    }
+
+    async fn query_orders(&self, txids: &[&str]) -> Result<QueryOrdersResponse, Error> {
+        private::query_orders(&self.cred, None, None, txids).await
+    }
+
+    async fn trades_history(&self) -> Result<TradesHistoryResponse, Error> {
+        private::trades_history(&self.cred, None, None, None, None, None).await
+    }
+
+    async fn add_order(&self, order: &NewOrder, validate: bool) -> Result<AddOrderResponse, Error> {
+        private::add_order(&self.cred, order, validate).await
+    }
+
+    async fn cancel_order(&self, txid: &str) -> Result<CancelOrderResponse, Error> {
+        private::cancel_order(&self.cred, txid).await
+    }
+
+    async fn cancel_all(&self) -> Result<CancelAllResponse, Error> {
+        private::cancel_all(&self.cred).await
+    }
+}
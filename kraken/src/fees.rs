@@ -0,0 +1,130 @@
+use crate::private::{Fee, TradeVolumeResponse};
+use crate::public::AssetPair;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+/// Expected fee of an order. Rates are percentages, as returned by
+/// the API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeEstimate {
+    pub liquidity: Liquidity,
+    pub notional: Decimal,
+    pub rate: Decimal,
+    pub fee: Decimal,
+    pub volume: Decimal,
+    pub next_rate: Option<Decimal>,
+    pub next_volume: Option<Decimal>,
+    pub volume_to_next_tier: Option<Decimal>,
+}
+
+/// Returns the volume tiered schedule of a pair as (volume, rate) rows.
+/// Pairs without a maker schedule charge the taker rate.
+pub fn schedule(pair: &AssetPair, liquidity: Liquidity) -> Vec<(Decimal, Decimal)> {
+    let rows = match liquidity {
+        Liquidity::Maker if !pair.fees_maker.is_empty() => &pair.fees_maker,
+        _ => &pair.fees,
+    };
+    return rows
+        .iter()
+        .filter(|row| row.len() >= 2)
+        .map(|row| (row[0], row[1]))
+        .collect();
+}
+
+/// Returns the rate applicable to a 30-day volume and the next tier, if any.
+pub fn tier(
+    schedule: &[(Decimal, Decimal)],
+    volume: Decimal,
+) -> (Decimal, Option<(Decimal, Decimal)>) {
+    let mut rate = schedule.first().map(|row| row.1).unwrap_or_default();
+    let mut next = None;
+    for (tier_volume, tier_rate) in schedule {
+        if volume >= *tier_volume {
+            rate = *tier_rate;
+        } else {
+            next = Some((*tier_volume, *tier_rate));
+            break;
+        }
+    }
+    return (rate, next);
+}
+
+/// Estimates the fee of an order of `notional` (in the quote currency)
+/// for an account that traded `volume` over the last 30 days.
+pub fn estimate(
+    pair: &AssetPair,
+    liquidity: Liquidity,
+    volume: Decimal,
+    notional: Decimal,
+) -> FeeEstimate {
+    let (rate, next) = tier(&schedule(pair, liquidity), volume);
+    return FeeEstimate {
+        liquidity,
+        notional,
+        rate,
+        fee: notional * rate / Decimal::ONE_HUNDRED,
+        volume,
+        next_rate: next.map(|n| n.1),
+        next_volume: next.map(|n| n.0),
+        volume_to_next_tier: next.map(|n| n.0 - volume),
+    };
+}
+
+/// Same as `estimate` but prefers the rates reported for the account by
+/// `private::trade_volume` (requested with `fee_info`), which take
+/// account specific schedules into account. `pair_id` is the key used
+/// in the response (e.g. `XXBTZUSD`).
+pub fn estimate_for_account(
+    pair_id: &str,
+    pair: &AssetPair,
+    liquidity: Liquidity,
+    trade_volume: &TradeVolumeResponse,
+    notional: Decimal,
+) -> FeeEstimate {
+    let fees = match liquidity {
+        Liquidity::Maker => trade_volume.fees_maker.as_ref(),
+        Liquidity::Taker => trade_volume.fees.as_ref(),
+    };
+    let fee: Option<&Fee> = fees.and_then(|f| f.get(pair_id));
+    let volume = trade_volume.volume;
+    return match fee {
+        Some(fee) => FeeEstimate {
+            liquidity,
+            notional,
+            rate: fee.fee,
+            fee: notional * fee.fee / Decimal::ONE_HUNDRED,
+            volume,
+            next_rate: fee.nextfee,
+            next_volume: fee.nextvolume,
+            volume_to_next_tier: fee.nextvolume.map(|v| v - volume),
+        },
+        None => estimate(pair, liquidity, volume, notional),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate, Liquidity};
+    use crate::testing::{d, pair};
+
+    #[test]
+    fn estimate_test() {
+        let pair = pair();
+        let taker = estimate(&pair, Liquidity::Taker, d("10000"), d("1000"));
+        assert_eq!(taker.rate, d("0.26"));
+        assert_eq!(taker.fee, d("2.6"));
+        assert_eq!(taker.next_rate, Some(d("0.24")));
+        assert_eq!(taker.volume_to_next_tier, Some(d("40000")));
+        let maker = estimate(&pair, Liquidity::Maker, d("150000"), d("1000"));
+        assert_eq!(maker.rate, d("0.12"));
+        assert_eq!(maker.fee, d("1.2"));
+        assert_eq!(maker.volume_to_next_tier, None);
+    }
+}
@@ -1,3 +1,25 @@
+#![allow(clippy::needless_return)]
+
 mod api;
 
 pub use api::*;
+
+pub mod alerts;
+pub mod algo;
+pub mod backfill;
+pub mod backtest;
+pub mod conditional;
+pub mod exchange;
+pub mod fees;
+pub mod paper;
+pub mod permissions;
+pub mod pnl;
+pub mod portfolio;
+pub mod reconcile;
+pub mod registry;
+pub mod strategy;
+pub mod tax;
+pub mod validation;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,787 @@
+use crate::exchange::{self, Exchange, MarketEvent};
+use crate::fees::{self, Liquidity};
+use crate::private::{
+    AddOrderDescr, AddOrderResponse, BalanceResponse, CancelAllResponse, CancelOrderResponse,
+    ClosedOrdersResponse, NewOrder, OpenOrdersResponse, Order, OrderDescr, QueryOrdersResponse,
+    Trade, TradesHistoryResponse,
+};
+use crate::public::{AssetPair, OrderBook};
+use crate::registry::AssetRegistry;
+use crate::validation::ORDER_TYPES;
+use crate::Error;
+use async_trait::async_trait;
+use chrono::Utc;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// State of a simulated account, saved between paper trading sessions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Account {
+    /// Keyed by asset id (`XXBT`, `ZUSD`...).
+    pub balances: HashMap<String, Decimal>,
+    pub open: HashMap<String, Order>,
+    pub closed: HashMap<String, Order>,
+    pub trades: HashMap<String, Trade>,
+    /// Traded volume in the quote currency, used to pick the fee tier.
+    pub volume: Decimal,
+    /// Stop orders whose trigger price was reached.
+    #[serde(default)]
+    pub triggered: Vec<String>,
+    /// Cursor of the public trades of each pair, see `Paper::poll`.
+    #[serde(default)]
+    pub since: HashMap<String, i64>,
+    #[serde(default)]
+    pub next_id: u64,
+}
+
+impl Account {
+    /// Executes `volume` of an open order at `price` and closes it once
+    /// filled. `pair` is the id and the pair of the order. Returns the fee.
+    pub(crate) fn fill(
+        &mut self,
+        txid: &str,
+        pair: (&str, &AssetPair),
+        price: Decimal,
+        volume: Decimal,
+        liquidity: Liquidity,
+        time: f64,
+    ) -> Decimal {
+        let (pair_id, pair) = pair;
+        let order = self.open.get_mut(txid).unwrap();
+        let cost = price * volume;
+        let rate = fees::tier(&fees::schedule(pair, liquidity), self.volume).0;
+        let fee = cost * rate / Decimal::ONE_HUNDRED;
+        let (base, quote) = (pair.base.clone(), pair.quote.clone());
+        if is_buy(order) {
+            *self.balances.entry(base).or_default() += volume;
+            *self.balances.entry(quote).or_default() -= cost + fee;
+        } else {
+            *self.balances.entry(base).or_default() -= volume;
+            *self.balances.entry(quote).or_default() += cost - fee;
+        }
+        self.volume += cost;
+        self.next_id += 1;
+        let trade_id = format!("T{:05}-PAPER-{:06}", self.next_id, self.next_id);
+        order.vol_exec += volume;
+        order.cost += cost;
+        order.fee += fee;
+        order.price = order.cost / order.vol_exec;
+        order
+            .trades
+            .get_or_insert_with(Vec::new)
+            .push(trade_id.clone());
+        let trade = Trade {
+            ordertxid: txid.to_string(),
+            postxid: None,
+            pair: pair_id.to_string(),
+            time,
+            type_: order.descr.type_.clone(),
+            ordertype: order.descr.ordertype.clone(),
+            price,
+            cost,
+            fee,
+            vol: volume,
+            margin: Decimal::ZERO,
+            misc: String::new(),
+            ccost: None,
+            cfee: None,
+            cvol: None,
+            cmargin: None,
+            net: None,
+            trades: None,
+        };
+        self.trades.insert(trade_id, trade);
+        if remaining(order).is_zero() {
+            self.close(txid, "closed");
+        }
+        return fee;
+    }
+
+    /// Moves an open order to the closed ones with `status`.
+    pub(crate) fn close(&mut self, txid: &str, status: &str) -> Option<()> {
+        let mut order = self.open.remove(txid)?;
+        order.status = status.to_string();
+        self.triggered.retain(|t| t != txid);
+        self.closed.insert(txid.to_string(), order);
+        return Some(());
+    }
+}
+
+/// Levels of a pair as (price, volume), best first.
+#[derive(Debug, Default)]
+struct Book {
+    asks: Vec<(Decimal, Decimal)>,
+    bids: Vec<(Decimal, Decimal)>,
+    last: Option<Decimal>,
+}
+
+impl Book {
+    /// Price stop orders are triggered against: the last trade, or the
+    /// middle of the book before any trade was seen.
+    fn reference(&self) -> Option<Decimal> {
+        if self.last.is_some() {
+            return self.last;
+        }
+        let (ask, bid) = (self.asks.first()?, self.bids.first()?);
+        return Some((ask.0 + bid.0) / Decimal::TWO);
+    }
+}
+
+/// How an open order executes at the moment.
+enum Kind {
+    Market,
+    Limit(Decimal),
+}
+
+fn is_buy(order: &Order) -> bool {
+    return order.descr.type_ == "buy";
+}
+
+fn remaining(order: &Order) -> Decimal {
+    return order.vol - order.vol_exec;
+}
+
+/// Returns `None` for stop orders that were not triggered yet.
+fn kind(order: &Order, triggered: bool) -> Option<Kind> {
+    return match order.descr.ordertype.as_str() {
+        "market" => Some(Kind::Market),
+        "limit" => Some(Kind::Limit(order.descr.price)),
+        "stop-loss" | "take-profit" if triggered => Some(Kind::Market),
+        "stop-loss-limit" | "take-profit-limit" if triggered => {
+            Some(Kind::Limit(order.descr.price2))
+        }
+        _ => None,
+    };
+}
+
+fn triggers(order: &Order, price: Decimal) -> bool {
+    let trigger = order.descr.price;
+    return match order.descr.ordertype.as_str() {
+        "stop-loss" | "stop-loss-limit" if is_buy(order) => price >= trigger,
+        "stop-loss" | "stop-loss-limit" => price <= trigger,
+        "take-profit" | "take-profit-limit" if is_buy(order) => price <= trigger,
+        "take-profit" | "take-profit-limit" => price >= trigger,
+        _ => false,
+    };
+}
+
+/// Whether a buy (sell) order limited at `limit` accepts `price`.
+fn within(buy: bool, price: Decimal, limit: Option<Decimal>) -> bool {
+    return match limit {
+        None => true,
+        Some(limit) if buy => price <= limit,
+        Some(limit) => price >= limit,
+    };
+}
+
+/// Returns an order as the exchange reports it once placed. `price` and
+/// `price2` are zero when the order type does not use them.
+pub(crate) fn open_order(
+    new: &NewOrder,
+    pair: &AssetPair,
+    price: Decimal,
+    price2: Decimal,
+    opentm: f64,
+) -> Order {
+    let mut description = format!(
+        "{} {} {} @ {}",
+        new.type_, new.volume, pair.altname, new.ordertype
+    );
+    if !price.is_zero() {
+        description = format!("{} {}", description, price);
+    }
+    return Order {
+        refid: None,
+        userref: new.userref,
+        status: "open".to_string(),
+        opentm,
+        starttm: 0,
+        expiretm: 0,
+        descr: OrderDescr {
+            pair: pair.altname.clone(),
+            type_: new.type_.clone(),
+            ordertype: new.ordertype.clone(),
+            price,
+            price2,
+            leverage: "none".to_string(),
+            order: description,
+            close: String::new(),
+        },
+        vol: new.volume,
+        vol_exec: Decimal::ZERO,
+        cost: Decimal::ZERO,
+        fee: Decimal::ZERO,
+        price: Decimal::ZERO,
+        stopprice: Decimal::ZERO,
+        limitprice: Decimal::ZERO,
+        misc: String::new(),
+        oflags: new.oflags.clone().unwrap_or_default(),
+        trades: None,
+    };
+}
+
+/// Order types of the exchange the simulator does not fill.
+pub(crate) const UNSIMULATED: &[&str] =
+    &["trailing-stop", "trailing-stop-limit", "settle-position"];
+
+struct Inner {
+    account: Account,
+    books: HashMap<String, Book>,
+}
+
+/// Simulated exchange filling orders against a depth and trades feed,
+/// either fetched with `poll` or pushed with `update_book` and
+/// `update_trades`. Orders taking liquidity pay the taker fee of the
+/// pair, resting limit orders the maker fee.
+pub struct Paper {
+    registry: &'static AssetRegistry,
+    inner: Mutex<Inner>,
+}
+
+impl Paper {
+    pub fn new(registry: &'static AssetRegistry) -> Self {
+        return Self::with_account(registry, Account::default());
+    }
+
+    pub fn with_account(registry: &'static AssetRegistry, account: Account) -> Self {
+        let inner = Inner {
+            account,
+            books: HashMap::new(),
+        };
+        Self {
+            registry,
+            inner: Mutex::new(inner),
+        }
+    }
+
+    /// Credits `amount` of `asset` under any of its spellings.
+    pub fn with_balance(self, asset: &str, amount: Decimal) -> Self {
+        let asset = self.registry.asset_id(asset).unwrap_or(asset).to_string();
+        *self
+            .inner
+            .lock()
+            .unwrap()
+            .account
+            .balances
+            .entry(asset)
+            .or_default() += amount;
+        return self;
+    }
+
+    pub fn load<P: AsRef<Path>>(registry: &'static AssetRegistry, path: P) -> Result<Self, Error> {
+        let content = std::fs::read_to_string(path)?;
+        return Ok(Self::with_account(
+            registry,
+            serde_json::from_str(&content)?,
+        ));
+    }
+
+    /// Writes the account to a temporary file renamed over `path`, so
+    /// an interruption never leaves a truncated state behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(&self.account())?)?;
+        std::fs::rename(tmp, path)?;
+        return Ok(());
+    }
+
+    pub fn account(&self) -> Account {
+        return self.inner.lock().unwrap().account.clone();
+    }
+
+    fn pair(&self, name: &str) -> Result<(&'static str, &'static AssetPair), Error> {
+        let id = self
+            .registry
+            .pair_id(name)
+            .ok_or_else(|| Error::API("EQuery:Unknown asset pair".to_string()))?;
+        return Ok((id, self.registry.pair(id).unwrap()));
+    }
+
+    /// Pairs with open orders, to be polled by a paper trading loop.
+    pub fn active_pairs(&self) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        let mut pairs: Vec<String> = inner
+            .account
+            .open
+            .values()
+            .filter_map(|order| self.registry.pair_id(&order.descr.pair))
+            .map(|id| id.to_string())
+            .collect();
+        pairs.sort();
+        pairs.dedup();
+        return pairs;
+    }
+
+    /// Replaces the book of a pair and fills the orders it crosses.
+    pub fn update_book(&self, pair: &str, book: &OrderBook) -> Result<(), Error> {
+        let (id, _) = self.pair(pair)?;
+        let mut inner = self.inner.lock().unwrap();
+        let entry = inner.books.entry(id.to_string()).or_default();
+        entry.asks = book.asks.iter().map(|(p, v, _)| (*p, *v)).collect();
+        entry.bids = book.bids.iter().map(|(p, v, _)| (*p, *v)).collect();
+        entry.asks.sort_by_key(|level| level.0);
+        entry.bids.sort_by_key(|level| std::cmp::Reverse(level.0));
+        inner.run(self.registry, id, None);
+        return Ok(());
+    }
+
+    /// Applies public trades as (price, volume): they trigger stop orders
+    /// and fill resting limit orders they trade through.
+    pub fn update_trades(&self, pair: &str, trades: &[(Decimal, Decimal)]) -> Result<(), Error> {
+        let (id, _) = self.pair(pair)?;
+        let mut inner = self.inner.lock().unwrap();
+        for (price, volume) in trades {
+            inner.books.entry(id.to_string()).or_default().last = Some(*price);
+            inner.trigger(self.registry, id);
+            let mut volume = *volume;
+            for txid in inner.orders_of(self.registry, id) {
+                let order = &inner.account.open[&txid];
+                let triggered = inner.account.triggered.contains(&txid);
+                let limit = match kind(order, triggered) {
+                    Some(Kind::Limit(limit)) => limit,
+                    _ => continue,
+                };
+                if volume.is_zero() || !within(is_buy(order), *price, Some(limit)) {
+                    continue;
+                }
+                let filled = std::cmp::min(volume, remaining(order));
+                volume -= filled;
+                inner.fill(self.registry, &txid, limit, filled, Liquidity::Maker);
+            }
+        }
+        inner.run(self.registry, id, None);
+        return Ok(());
+    }
+
+    /// Fetches the book and the trades since the previous poll of a pair.
+    /// Trades before the first poll are skipped.
+    pub async fn poll(&self, pair: &str) -> Result<(), Error> {
+        let (id, _) = self.pair(pair)?;
+        let since = self.inner.lock().unwrap().account.since.get(id).copied();
+        let (events, last) = exchange::fetch(id, since).await?;
+        for event in events.iter() {
+            self.observe(event)?;
+        }
+        if let Some(last) = last {
+            self.inner
+                .lock()
+                .unwrap()
+                .account
+                .since
+                .insert(id.to_string(), last);
+        }
+        return Ok(());
+    }
+
+    fn place(&self, new: &NewOrder, validate: bool) -> Result<AddOrderResponse, Error> {
+        let (id, pair) = self.pair(&new.pair)?;
+        let ordertype = new.ordertype.as_str();
+        if !ORDER_TYPES.contains(&ordertype) || UNSIMULATED.contains(&ordertype) {
+            return Err(Error::API(
+                "EGeneral:Invalid arguments:ordertype".to_string(),
+            ));
+        }
+        if new.type_ != "buy" && new.type_ != "sell" {
+            return Err(Error::API("EGeneral:Invalid arguments:type".to_string()));
+        }
+        if new.volume < pair.ordermin {
+            return Err(Error::API("EOrder:Order minimum not met".to_string()));
+        }
+        let price = match (new.ordertype.as_str(), new.price) {
+            ("market", _) => Decimal::ZERO,
+            (_, Some(price)) => price,
+            (_, None) => return Err(Error::API("EGeneral:Invalid arguments:price".to_string())),
+        };
+        let price2 = match (new.ordertype.ends_with("-limit"), new.price2) {
+            (false, _) => Decimal::ZERO,
+            (true, Some(price2)) => price2,
+            (true, None) => {
+                return Err(Error::API("EGeneral:Invalid arguments:price2".to_string()))
+            }
+        };
+        let now = Utc::now().timestamp_millis() as f64 / 1000.0;
+        let order = open_order(new, pair, price, price2, now);
+
+        let mut inner = self.inner.lock().unwrap();
+        let (asset, needed) = inner.hold(self.registry, &order)?;
+        let available = inner.available(self.registry, &asset);
+        if needed > available {
+            return Err(Error::API("EOrder:Insufficient funds".to_string()));
+        }
+        let descr = AddOrderDescr {
+            order: order.descr.order.clone(),
+            close: None,
+        };
+        if validate {
+            return Ok(AddOrderResponse { descr, txid: None });
+        }
+        inner.account.next_id += 1;
+        let txid = format!(
+            "O{:05}-PAPER-{:06}",
+            inner.account.next_id, inner.account.next_id
+        );
+        inner.account.open.insert(txid.clone(), order);
+        inner.run(self.registry, id, Some(&txid));
+        return Ok(AddOrderResponse {
+            descr,
+            txid: Some(vec![txid]),
+        });
+    }
+}
+
+impl Inner {
+    /// Open orders of a pair, oldest first.
+    fn orders_of(&self, registry: &AssetRegistry, pair: &str) -> Vec<String> {
+        let mut orders: Vec<(&String, &Order)> = self
+            .account
+            .open
+            .iter()
+            .filter(|(_, order)| registry.pair_id(&order.descr.pair) == Some(pair))
+            .collect();
+        orders.sort_by(|a, b| a.1.opentm.total_cmp(&b.1.opentm).then(a.0.cmp(b.0)));
+        return orders.into_iter().map(|(txid, _)| txid.clone()).collect();
+    }
+
+    /// Returns the asset and the amount an order holds until it closes.
+    fn hold(&self, registry: &AssetRegistry, order: &Order) -> Result<(String, Decimal), Error> {
+        let pair = registry.pair(&order.descr.pair).unwrap();
+        if !is_buy(order) {
+            return Ok((pair.base.clone(), remaining(order)));
+        }
+        let book = self.books.get(registry.pair_id(&order.descr.pair).unwrap());
+        let price = match order.descr.ordertype.as_str() {
+            "market" => book.and_then(|b| b.asks.first().map(|l| l.0).or(b.last)),
+            "stop-loss-limit" | "take-profit-limit" => Some(order.descr.price2),
+            _ => Some(order.descr.price),
+        };
+        let price = price
+            .ok_or_else(|| Error::API(format!("EOrder:No market data for {}", order.descr.pair)))?;
+        let rate = fees::tier(&fees::schedule(pair, Liquidity::Taker), self.account.volume).0;
+        let cost = remaining(order) * price;
+        return Ok((
+            pair.quote.clone(),
+            cost + cost * rate / Decimal::ONE_HUNDRED,
+        ));
+    }
+
+    /// Balance of an asset minus what the open orders hold.
+    fn available(&self, registry: &AssetRegistry, asset: &str) -> Decimal {
+        let balance = self
+            .account
+            .balances
+            .get(asset)
+            .copied()
+            .unwrap_or_default();
+        let held: Decimal = self
+            .account
+            .open
+            .values()
+            .filter_map(|order| self.hold(registry, order).ok())
+            .filter(|(held, _)| held == asset)
+            .map(|(_, amount)| amount)
+            .sum();
+        return balance - held;
+    }
+
+    fn trigger(&mut self, registry: &AssetRegistry, pair: &str) {
+        let reference = match self.books.get(pair).and_then(|b| b.reference()) {
+            Some(reference) => reference,
+            None => return,
+        };
+        for txid in self.orders_of(registry, pair) {
+            if !self.account.triggered.contains(&txid)
+                && triggers(&self.account.open[&txid], reference)
+            {
+                self.account.triggered.push(txid);
+            }
+        }
+    }
+
+    /// Fills the orders of a pair the book allows. `incoming` is a newly
+    /// placed order, which takes liquidity even when limited.
+    fn run(&mut self, registry: &AssetRegistry, pair: &str, incoming: Option<&str>) {
+        self.trigger(registry, pair);
+        for txid in self.orders_of(registry, pair) {
+            let order = &self.account.open[&txid];
+            let triggered = self.account.triggered.contains(&txid);
+            match kind(order, triggered) {
+                Some(Kind::Market) => self.take(registry, pair, &txid, None),
+                Some(Kind::Limit(limit)) if incoming == Some(txid.as_str()) => {
+                    self.take(registry, pair, &txid, Some(limit))
+                }
+                Some(Kind::Limit(limit)) => self.rest(registry, pair, &txid, limit),
+                None => {}
+            }
+        }
+    }
+
+    /// Fills an order level by level at the prices of the book.
+    fn take(&mut self, registry: &AssetRegistry, pair: &str, txid: &str, limit: Option<Decimal>) {
+        let buy = is_buy(&self.account.open[txid]);
+        let mut fills = vec![];
+        let mut wanted = remaining(&self.account.open[txid]);
+        if let Some(book) = self.books.get_mut(pair) {
+            let levels = if buy { &mut book.asks } else { &mut book.bids };
+            for level in levels.iter_mut() {
+                if wanted.is_zero() || !within(buy, level.0, limit) {
+                    break;
+                }
+                let volume = std::cmp::min(level.1, wanted);
+                level.1 -= volume;
+                wanted -= volume;
+                fills.push((level.0, volume));
+            }
+            levels.retain(|level| !level.1.is_zero());
+        }
+        for (price, volume) in fills {
+            self.fill(registry, txid, price, volume, Liquidity::Taker);
+        }
+    }
+
+    /// Fills a resting limit order at its price with the volume the book
+    /// offers through it.
+    fn rest(&mut self, registry: &AssetRegistry, pair: &str, txid: &str, limit: Decimal) {
+        let buy = is_buy(&self.account.open[txid]);
+        let mut wanted = remaining(&self.account.open[txid]);
+        let mut filled = Decimal::ZERO;
+        if let Some(book) = self.books.get_mut(pair) {
+            let levels = if buy { &mut book.asks } else { &mut book.bids };
+            for level in levels.iter_mut() {
+                if wanted.is_zero() || !within(buy, level.0, Some(limit)) {
+                    break;
+                }
+                let volume = std::cmp::min(level.1, wanted);
+                level.1 -= volume;
+                wanted -= volume;
+                filled += volume;
+            }
+            levels.retain(|level| !level.1.is_zero());
+        }
+        if !filled.is_zero() {
+            self.fill(registry, txid, limit, filled, Liquidity::Maker);
+        }
+    }
+
+    fn fill(
+        &mut self,
+        registry: &AssetRegistry,
+        txid: &str,
+        price: Decimal,
+        volume: Decimal,
+        liquidity: Liquidity,
+    ) {
+        let pair_id = registry
+            .pair_id(&self.account.open[txid].descr.pair)
+            .unwrap();
+        let pair = registry.pair(pair_id).unwrap();
+        let now = Utc::now().timestamp_millis() as f64 / 1000.0;
+        self.account
+            .fill(txid, (pair_id, pair), price, volume, liquidity, now);
+    }
+}
+
+#[async_trait]
+impl Exchange for Paper {
+    async fn balance(&self) -> Result<BalanceResponse, Error> {
+        Ok(BalanceResponse(self.account().balances))
+    }
+
+    async fn open_orders(&self) -> Result<OpenOrdersResponse, Error> {
+        Ok(OpenOrdersResponse {
+            open: self.account().open,
+        })
+    }
+
+    async fn closed_orders(&self) -> Result<ClosedOrdersResponse, Error> {
+        Ok(ClosedOrdersResponse {
+            closed: self.account().closed,
+        })
+    }
+
+    async fn query_orders(&self, txids: &[&str]) -> Result<QueryOrdersResponse, Error> {
+        let account = self.account();
+        let orders = txids
+            .iter()
+            .filter_map(|txid| {
+                let order = account.open.get(*txid).or(account.closed.get(*txid))?;
+                Some((txid.to_string(), order.clone()))
+            })
+            .collect();
+        Ok(QueryOrdersResponse(orders))
+    }
+
+    async fn trades_history(&self) -> Result<TradesHistoryResponse, Error> {
+        let trades = self.account().trades;
+        Ok(TradesHistoryResponse {
+            count: trades.len() as u64,
+            trades,
+        })
+    }
+
+    async fn add_order(&self, order: &NewOrder, validate: bool) -> Result<AddOrderResponse, Error> {
+        self.place(order, validate)
+    }
+
+    async fn cancel_order(&self, txid: &str) -> Result<CancelOrderResponse, Error> {
+        match self.inner.lock().unwrap().account.close(txid, "canceled") {
+            Some(()) => Ok(CancelOrderResponse {
+                count: 1,
+                pending: None,
+            }),
+            None => Err(Error::API("EOrder:Unknown order".to_string())),
+        }
+    }
+
+    async fn cancel_all(&self) -> Result<CancelAllResponse, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let txids: Vec<String> = inner.account.open.keys().cloned().collect();
+        for txid in txids.iter() {
+            inner.account.close(txid, "canceled");
+        }
+        Ok(CancelAllResponse {
+            count: txids.len() as u64,
+        })
+    }
+
+    fn observe(&self, event: &MarketEvent) -> Result<(), Error> {
+        match event {
+            MarketEvent::Book { pair, book } => self.update_book(pair, book),
+            MarketEvent::Trades { pair, trades } => {
+                let trades: Vec<(Decimal, Decimal)> = trades.iter().map(|t| (t.0, t.1)).collect();
+                self.update_trades(pair, &trades)
+            }
+            // the simulator follows the live book and trades only.
+            MarketEvent::Candle { .. } => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exchange::Exchange;
+    use crate::public::OrderBook;
+    use crate::testing::{d, order, paper};
+
+    fn book(asks: &[(&str, &str)], bids: &[(&str, &str)]) -> OrderBook {
+        let levels =
+            |levels: &[(&str, &str)]| levels.iter().map(|(p, v)| (d(p), d(v), 0)).collect();
+        return OrderBook {
+            asks: levels(asks),
+            bids: levels(bids),
+        };
+    }
+
+    #[tokio::test]
+    async fn matching_test() {
+        let paper = paper();
+        let book1 = book(&[("100", "1"), ("101", "2")], &[("99", "1"), ("98", "5")]);
+        paper.update_book("XBTUSD", &book1).unwrap();
+
+        // takes two levels of the book
+        let added = paper
+            .add_order(&order("buy", "market", "1.5", None), false)
+            .await
+            .unwrap();
+        let txid = &added.txid.unwrap()[0];
+        let account = paper.account();
+        assert_eq!(account.closed[txid].status, "closed");
+        assert_eq!(account.closed[txid].cost, d("150.5"));
+        assert_eq!(account.closed[txid].fee, d("0.3913"));
+        assert_eq!(account.balances["XXBT"], d("1.5"));
+        assert_eq!(account.balances["ZUSD"], d("9849.1087"));
+
+        // rests, then fills as maker at its price
+        let added = paper
+            .add_order(&order("sell", "limit", "1", Some("105")), false)
+            .await
+            .unwrap();
+        let txid = &added.txid.unwrap()[0];
+        assert_eq!(paper.open_orders().await.unwrap().open.len(), 1);
+        let err = paper
+            .add_order(&order("sell", "limit", "1", Some("110")), false)
+            .await;
+        assert_eq!(
+            format!("{}", err.unwrap_err()),
+            "api error EOrder:Insufficient funds"
+        );
+        paper
+            .update_trades("XBTUSD", &[(d("106"), d("0.4"))])
+            .unwrap();
+        assert_eq!(paper.account().open[txid].vol_exec, d("0.4"));
+        paper
+            .update_book("XBTUSD", &book(&[("106", "1")], &[("105.5", "2")]))
+            .unwrap();
+        let account = paper.account();
+        assert_eq!(account.closed[txid].status, "closed");
+        assert_eq!(account.closed[txid].fee, d("0.168"));
+        assert_eq!(account.balances["XXBT"], d("0.5"));
+        assert_eq!(account.balances["ZUSD"], d("9953.9407"));
+        assert_eq!(paper.trades_history().await.unwrap().count, 4);
+    }
+
+    #[tokio::test]
+    async fn stop_test() {
+        let paper = paper().with_balance("XBT", d("1"));
+        paper
+            .update_book("XBTUSD", &book(&[("95", "1")], &[("94", "1")]))
+            .unwrap();
+        paper
+            .update_trades("XBTUSD", &[(d("100"), d("1"))])
+            .unwrap();
+        let added = paper
+            .add_order(&order("sell", "stop-loss", "0.2", Some("95")), false)
+            .await
+            .unwrap();
+        let txid = &added.txid.unwrap()[0];
+        paper.update_trades("XBTUSD", &[(d("96"), d("1"))]).unwrap();
+        assert!(paper.account().open.contains_key(txid));
+        paper
+            .update_trades("XBTUSD", &[(d("94.5"), d("1"))])
+            .unwrap();
+        let account = paper.account();
+        assert_eq!(account.closed[txid].price, d("94"));
+        assert_eq!(account.balances["XXBT"], d("0.8"));
+        assert!(account.triggered.is_empty());
+    }
+
+    #[tokio::test]
+    async fn orders_test() {
+        let paper = paper();
+        let err = paper
+            .add_order(&order("buy", "limit", "0.00001", Some("50")), false)
+            .await;
+        assert_eq!(
+            format!("{}", err.unwrap_err()),
+            "api error EOrder:Order minimum not met"
+        );
+        let err = paper
+            .add_order(&order("buy", "market", "1", None), false)
+            .await;
+        assert!(err.is_err());
+
+        let validated = paper
+            .add_order(&order("buy", "limit", "1", Some("50")), true)
+            .await
+            .unwrap();
+        assert_eq!(validated.descr.order, "buy 1 XBTUSD @ limit 50");
+        assert!(validated.txid.is_none());
+        assert!(paper.open_orders().await.unwrap().open.is_empty());
+
+        let added = paper
+            .add_order(&order("buy", "limit", "1", Some("50")), false)
+            .await
+            .unwrap();
+        let txid = &added.txid.unwrap()[0];
+        assert_eq!(paper.cancel_order(txid).await.unwrap().count, 1);
+        assert_eq!(
+            paper.closed_orders().await.unwrap().closed[txid].status,
+            "canceled"
+        );
+        assert!(paper.cancel_order(txid).await.is_err());
+        assert_eq!(paper.balance().await.unwrap().0["ZUSD"], d("10000"));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::private::{self, NewOrder};
+use crate::{Credential, Error};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Scopes of an API key that matter to this library. Kraken names them
+/// "Query Funds", "Query Open Orders & Trades", "Query Ledger Entries",
+/// "Create & Modify Orders" and "Cancel/Close Orders".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    QueryFunds,
+    QueryOrders,
+    QueryLedger,
+    Trade,
+    Cancel,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 5] = [
+        Permission::QueryFunds,
+        Permission::QueryOrders,
+        Permission::QueryLedger,
+        Permission::Trade,
+        Permission::Cancel,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        return match self {
+            Permission::QueryFunds => "query-funds",
+            Permission::QueryOrders => "query-orders",
+            Permission::QueryLedger => "query-ledger",
+            Permission::Trade => "trade",
+            Permission::Cancel => "cancel",
+        };
+    }
+
+    /// Returns the permission needed by a private endpoint, `None` for
+    /// public endpoints and endpoints this library does not know.
+    pub fn required_by(path: &str) -> Option<Permission> {
+        let name = path.strip_prefix("/0/private/")?;
+        return match name {
+            "Balance" | "BalanceEx" | "TradeBalance" | "TradeVolume" => {
+                Some(Permission::QueryFunds)
+            }
+            "OpenOrders" | "ClosedOrders" | "QueryOrders" | "TradesHistory" | "QueryTrades"
+            | "OpenPositions" => Some(Permission::QueryOrders),
+            "Ledgers" | "QueryLedgers" => Some(Permission::QueryLedger),
+            "AddOrder" | "EditOrder" => Some(Permission::Trade),
+            "CancelOrder" | "CancelAll" => Some(Permission::Cancel),
+            _ => None,
+        };
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f.write_str(self.label());
+    }
+}
+
+impl FromStr for Permission {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return Permission::ALL
+            .iter()
+            .find(|p| p.label() == s)
+            .copied()
+            .ok_or_else(|| Error::Invalid(format!("permission {}", s)));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "access", content = "message", rename_all = "lowercase")]
+pub enum Access {
+    Granted,
+    Denied,
+    /// The probe failed for another reason, given by the message.
+    Unknown(String),
+}
+
+/// Classifies the outcome of a probe. When `rejected_means_granted` is
+/// set, an order error means the key got past the permission check.
+fn classify<T>(result: Result<T, Error>, rejected_means_granted: bool) -> Result<Access, Error> {
+    return match result {
+        Ok(_) => Ok(Access::Granted),
+        Err(Error::API(message)) if message.contains("Permission denied") => Ok(Access::Denied),
+        // invalid key, nonce or signature.
+        Err(Error::API(message)) if message.starts_with("EAPI:") => Ok(Access::Unknown(message)),
+        Err(Error::API(_)) if rejected_means_granted => Ok(Access::Granted),
+        Err(Error::API(message)) => Ok(Access::Unknown(message)),
+        Err(e) => Err(e),
+    };
+}
+
+/// Detects the permissions of a key with cheap endpoints. Trading is
+/// probed with a validate-only order and cancelling with an unknown
+/// txid, so nothing is placed nor cancelled. Permissions declared on
+/// `cred` are ignored.
+pub async fn probe(cred: &Credential) -> Result<Vec<(Permission, Access)>, Error> {
+    let cred = cred.clone().with_permissions(None);
+    let order = NewOrder {
+        pair: "XBTUSD".to_string(),
+        type_: "buy".to_string(),
+        ordertype: "limit".to_string(),
+        volume: Decimal::new(1, 4),
+        price: Some(Decimal::ONE),
+        ..Default::default()
+    };
+    let mut access = vec![];
+    for permission in Permission::ALL {
+        let result = match permission {
+            Permission::QueryFunds => classify(private::balance(&cred).await, false),
+            Permission::QueryOrders => {
+                classify(private::open_orders(&cred, None, None).await, false)
+            }
+            Permission::QueryLedger => classify(
+                private::ledgers(&cred, None, None, None, None, None, None).await,
+                false,
+            ),
+            Permission::Trade => classify(private::add_order(&cred, &order, true).await, true),
+            Permission::Cancel => classify(
+                private::cancel_order(&cred, "OPROBE-PROBE-PROBE").await,
+                true,
+            ),
+        };
+        access.push((permission, result?));
+    }
+    return Ok(access);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify, Access, Permission};
+    use crate::{private, Credential, Error};
+
+    #[test]
+    fn permission_test() {
+        assert_eq!(
+            Permission::required_by("/0/private/AddOrder"),
+            Some(Permission::Trade)
+        );
+        assert_eq!(Permission::required_by("/0/public/Ticker"), None);
+        assert_eq!(
+            "query-ledger".parse::<Permission>().unwrap(),
+            Permission::QueryLedger
+        );
+        assert!("withdraw".parse::<Permission>().is_err());
+
+        let denied = || Err::<(), _>(Error::API("EGeneral:Permission denied".to_string()));
+        let rejected = || Err::<(), _>(Error::API("EOrder:Unknown order".to_string()));
+        assert_eq!(classify(denied(), true).unwrap(), Access::Denied);
+        assert_eq!(classify(rejected(), true).unwrap(), Access::Granted);
+        assert!(matches!(
+            classify(rejected(), false).unwrap(),
+            Access::Unknown(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn undeclared_test() {
+        let cred =
+            Credential::new("KEY", b"SECRET").with_permissions(Some(vec![Permission::QueryFunds]));
+        // refused before any request is sent.
+        match private::cancel_all(&cred).await {
+            Err(Error::Permission { needed, .. }) => assert_eq!(needed, Permission::Cancel),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use crate::private::Trade;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::str::FromStr;
+
+/// How sells are matched against the lots opened by earlier buys.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    Fifo,
+    Lifo,
+    Average,
+}
+
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.to_lowercase().as_str() {
+            "fifo" => Ok(Method::Fifo),
+            "lifo" => Ok(Method::Lifo),
+            "average" | "avg" => Ok(Method::Average),
+            _ => Err(format!("unknown method {}", s)),
+        };
+    }
+}
+
+/// An open lot. `price` is the net price per unit in the quote
+/// currency: cost plus fee for a long lot, proceeds minus fee for a
+/// short lot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lot {
+    pub time: f64,
+    pub volume: Decimal,
+    pub price: Decimal,
+}
+
+#[derive(Debug, Default)]
+struct Book {
+    /// Positive when the open lots are long, negative when short.
+    side: i8,
+    lots: VecDeque<Lot>,
+    realized: Decimal,
+    fees: Decimal,
+    bought: Decimal,
+    sold: Decimal,
+    trades: u64,
+}
+
+/// Realized and unrealized profit of a pair, in its quote currency.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairPnl {
+    pub pair: String,
+    pub trades: u64,
+    pub bought: Decimal,
+    pub sold: Decimal,
+    pub fees: Decimal,
+    pub realized: Decimal,
+    /// Open inventory, negative for a short position.
+    pub position: Decimal,
+    /// Average net price of the open inventory.
+    pub average_price: Option<Decimal>,
+    pub mark: Option<Decimal>,
+    pub unrealized: Option<Decimal>,
+    pub lots: Vec<Lot>,
+}
+
+impl Book {
+    /// Applies a trade to the open lots. Totals and realized profit are
+    /// only accumulated when `counted` is set, earlier trades just
+    /// provide the cost of the inventory.
+    fn add(&mut self, method: Method, trade: &Trade, counted: bool) {
+        if trade.vol.is_zero() {
+            return;
+        }
+        let side: i8 = if trade.type_ == "buy" { 1 } else { -1 };
+        let price = if side > 0 {
+            (trade.cost + trade.fee) / trade.vol
+        } else {
+            (trade.cost - trade.fee) / trade.vol
+        };
+        if counted {
+            self.trades += 1;
+            self.fees += trade.fee;
+            if side > 0 {
+                self.bought += trade.vol;
+            } else {
+                self.sold += trade.vol;
+            }
+        }
+        let mut remaining = trade.vol;
+        if self.side != 0 && self.side != side {
+            let realized;
+            (remaining, realized) = self.close(method, side, remaining, price);
+            if counted {
+                self.realized += realized;
+            }
+        }
+        if remaining.is_zero() {
+            return;
+        }
+        self.side = side;
+        let lot = Lot {
+            time: trade.time,
+            volume: remaining,
+            price,
+        };
+        match (method, self.lots.front_mut()) {
+            (Method::Average, Some(pooled)) => {
+                let volume = pooled.volume + lot.volume;
+                pooled.price = (pooled.price * pooled.volume + lot.price * lot.volume) / volume;
+                pooled.volume = volume;
+            }
+            _ => self.lots.push_back(lot),
+        }
+    }
+
+    /// Matches `volume` against the open lots and returns what is left
+    /// once the position is flat, with the realized profit.
+    fn close(
+        &mut self,
+        method: Method,
+        side: i8,
+        mut volume: Decimal,
+        price: Decimal,
+    ) -> (Decimal, Decimal) {
+        let mut realized = Decimal::ZERO;
+        while !volume.is_zero() {
+            let lot = match method {
+                Method::Lifo => self.lots.back_mut(),
+                _ => self.lots.front_mut(),
+            };
+            let lot = match lot {
+                Some(lot) => lot,
+                None => break,
+            };
+            let matched = volume.min(lot.volume);
+            // closing a long with a sell earns the sell price, closing a
+            // short with a buy earns the price the short was opened at.
+            realized += match side {
+                -1 => (price - lot.price) * matched,
+                _ => (lot.price - price) * matched,
+            };
+            lot.volume -= matched;
+            volume -= matched;
+            if lot.volume.is_zero() {
+                match method {
+                    Method::Lifo => self.lots.pop_back(),
+                    _ => self.lots.pop_front(),
+                };
+            }
+        }
+        if self.lots.is_empty() {
+            self.side = 0;
+        }
+        return (volume, realized);
+    }
+
+    fn report(&self, pair: &str, mark: Option<Decimal>) -> PairPnl {
+        let volume: Decimal = self.lots.iter().map(|l| l.volume).sum();
+        let position = volume * Decimal::from(self.side);
+        let average_price = if volume.is_zero() {
+            None
+        } else {
+            Some(
+                self.lots
+                    .iter()
+                    .map(|l| l.price * l.volume)
+                    .sum::<Decimal>()
+                    / volume,
+            )
+        };
+        let unrealized = match (mark, average_price) {
+            (Some(mark), Some(average)) => Some((mark - average) * position),
+            (_, None) => Some(Decimal::ZERO),
+            _ => None,
+        };
+        return PairPnl {
+            pair: pair.to_string(),
+            trades: self.trades,
+            bought: self.bought,
+            sold: self.sold,
+            fees: self.fees,
+            realized: self.realized,
+            position,
+            average_price,
+            mark,
+            unrealized,
+            lots: self.lots.iter().cloned().collect(),
+        };
+    }
+}
+
+/// Computes per pair profit and loss from account trades.
+pub struct PnlEngine {
+    method: Method,
+    since: Option<f64>,
+    books: BTreeMap<String, Book>,
+}
+
+impl PnlEngine {
+    pub fn new(method: Method) -> Self {
+        Self {
+            method,
+            since: None,
+            books: BTreeMap::new(),
+        }
+    }
+
+    /// Reports only the trades made from `since` (unix time). Earlier
+    /// trades must still be added, they open the lots later trades close.
+    pub fn with_since(mut self, since: f64) -> Self {
+        self.since = Some(since);
+        return self;
+    }
+
+    /// Adds a single trade. Trades must be added in chronological order,
+    /// see `add_all`.
+    pub fn add(&mut self, trade: &Trade) {
+        let counted = self.since.map(|since| trade.time >= since).unwrap_or(true);
+        self.books
+            .entry(trade.pair.clone())
+            .or_default()
+            .add(self.method, trade, counted);
+    }
+
+    /// Adds trades in chronological order, as returned by
+    /// `private::trades_history_all`.
+    pub fn add_all<'a, I>(&mut self, trades: I)
+    where
+        I: IntoIterator<Item = &'a Trade>,
+    {
+        let mut trades: Vec<&Trade> = trades.into_iter().collect();
+        trades.sort_by(|a, b| a.time.total_cmp(&b.time));
+        for trade in trades {
+            self.add(trade);
+        }
+    }
+
+    /// Pairs that still have open inventory.
+    pub fn open_pairs(&self) -> Vec<String> {
+        return self
+            .books
+            .iter()
+            .filter(|(_, book)| !book.lots.is_empty())
+            .map(|(pair, _)| pair.clone())
+            .collect();
+    }
+
+    /// Returns the pnl of every pair, marking open inventory to the
+    /// prices of `marks` (keyed by pair, like the trades). Pairs without
+    /// reported trades nor open inventory are left out.
+    pub fn report(&self, marks: &HashMap<String, Decimal>) -> Vec<PairPnl> {
+        return self
+            .books
+            .iter()
+            .filter(|(_, book)| book.trades > 0 || !book.lots.is_empty())
+            .map(|(pair, book)| book.report(pair, marks.get(pair).copied()))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Method, PnlEngine};
+    use crate::private::Trade;
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+
+    fn trade(type_: &str, time: f64, vol: i64, price: i64, fee: i64) -> Trade {
+        return serde_json::from_value(serde_json::json!({
+            "ordertxid": "O", "pair": "XXBTZUSD", "time": time, "type": type_,
+            "ordertype": "limit", "price": price.to_string(), "cost": (vol * price).to_string(),
+            "fee": fee.to_string(), "vol": vol.to_string(), "margin": "0", "misc": ""
+        }))
+        .unwrap();
+    }
+
+    fn run(method: Method) -> super::PairPnl {
+        let trades = [
+            trade("buy", 1.0, 1, 100, 1),
+            trade("buy", 2.0, 1, 200, 1),
+            trade("sell", 3.0, 1, 300, 2),
+        ];
+        let mut engine = PnlEngine::new(method);
+        engine.add_all(trades.iter().rev());
+        let mut marks = HashMap::new();
+        marks.insert("XXBTZUSD".to_string(), Decimal::from(250));
+        return engine.report(&marks).remove(0);
+    }
+
+    #[test]
+    fn fifo_test() {
+        let pnl = run(Method::Fifo);
+        // sold at 298 net, first lot cost 101.
+        assert_eq!(pnl.realized, Decimal::from(197));
+        assert_eq!(pnl.position, Decimal::ONE);
+        assert_eq!(pnl.unrealized, Some(Decimal::from(49)));
+        assert_eq!(pnl.fees, Decimal::from(4));
+    }
+
+    #[test]
+    fn lifo_test() {
+        let pnl = run(Method::Lifo);
+        assert_eq!(pnl.realized, Decimal::from(97));
+        assert_eq!(pnl.unrealized, Some(Decimal::from(149)));
+    }
+
+    #[test]
+    fn average_test() {
+        let pnl = run(Method::Average);
+        assert_eq!(pnl.realized, Decimal::from(147));
+        assert_eq!(pnl.average_price, Some(Decimal::from(151)));
+        assert_eq!(pnl.unrealized, Some(Decimal::from(99)));
+    }
+
+    #[test]
+    fn short_test() {
+        let mut engine = PnlEngine::new(Method::Fifo);
+        engine.add(&trade("sell", 1.0, 2, 300, 0));
+        engine.add(&trade("buy", 2.0, 3, 200, 0));
+        let pnl = engine.report(&HashMap::new()).remove(0);
+        assert_eq!(pnl.realized, Decimal::from(200));
+        assert_eq!(pnl.position, Decimal::ONE);
+        assert_eq!(pnl.unrealized, None);
+    }
+
+    #[test]
+    fn since_test() {
+        let mut engine = PnlEngine::new(Method::Fifo).with_since(3.0);
+        engine.add(&trade("buy", 1.0, 1, 100, 1));
+        engine.add(&trade("buy", 2.0, 1, 200, 1));
+        engine.add(&trade("sell", 3.0, 1, 300, 2));
+        let pnl = engine.report(&HashMap::new()).remove(0);
+        // the sell closes the lot bought before `since` at its cost.
+        assert_eq!(pnl.realized, Decimal::from(197));
+        assert_eq!(pnl.trades, 1);
+        assert_eq!(pnl.fees, Decimal::from(2));
+        assert_eq!(pnl.bought, Decimal::ZERO);
+        assert_eq!(pnl.position, Decimal::ONE);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::private::{self, BalanceExResponse};
+use crate::public::{self, TickerResponse};
+use crate::registry::AssetRegistry;
+use crate::{Credential, Error};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Assets used to price holdings that have no direct pair with the
+/// quote currency.
+const INTERMEDIARIES: &[&str] = &["XBT", "USD"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Holding {
+    pub asset: String,
+    pub balance: Decimal,
+    pub hold_trade: Decimal,
+    pub amount: Decimal,
+    pub price: Option<Decimal>,
+    pub value: Option<Decimal>,
+    /// Pairs used to compute the price, empty for the quote currency.
+    pub route: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Portfolio {
+    pub quote: String,
+    pub holdings: Vec<Holding>,
+    pub total: Decimal,
+    /// Assets held for which no price could be found.
+    pub unpriced: Vec<String>,
+}
+
+/// Last traded prices indexed by (base, quote) asset identifiers.
+struct Prices(HashMap<(String, String), (Decimal, String)>);
+
+impl Prices {
+    fn new(tickers: &TickerResponse, registry: &AssetRegistry) -> Self {
+        let mut prices = HashMap::new();
+        for (pair_id, info) in tickers.0.iter() {
+            if let Some(pair) = registry.pair(pair_id) {
+                prices.insert(
+                    (pair.base.clone(), pair.quote.clone()),
+                    (info.c.0, pair_id.clone()),
+                );
+            }
+        }
+        return Self(prices);
+    }
+
+    fn hop(&self, from: &str, to: &str) -> Option<(Decimal, String)> {
+        if let Some((price, pair)) = self.0.get(&(from.to_string(), to.to_string())) {
+            return Some((*price, pair.clone()));
+        }
+        let (price, pair) = self.0.get(&(to.to_string(), from.to_string()))?;
+        if price.is_zero() {
+            return None;
+        }
+        return Some((Decimal::ONE / price, pair.clone()));
+    }
+
+    /// Returns the price of `from` in `to` and the pairs used, going
+    /// through one of the intermediaries when there is no direct pair.
+    fn rate(
+        &self,
+        from: &str,
+        to: &str,
+        intermediaries: &[String],
+    ) -> Option<(Decimal, Vec<String>)> {
+        if from == to {
+            return Some((Decimal::ONE, vec![]));
+        }
+        if let Some((price, pair)) = self.hop(from, to) {
+            return Some((price, vec![pair]));
+        }
+        for mid in intermediaries {
+            if mid == from || mid == to {
+                continue;
+            }
+            if let (Some(first), Some(second)) = (self.hop(from, mid), self.hop(mid, to)) {
+                return Some((first.0 * second.0, vec![first.1, second.1]));
+            }
+        }
+        return None;
+    }
+}
+
+/// Balance keys may carry a suffix such as `.S` (staked) or `.M`
+/// (opt-in rewards); those are priced as the underlying asset.
+fn underlying(registry: &AssetRegistry, key: &str) -> String {
+    let name = key.split('.').next().unwrap_or(key);
+    return registry.asset_id(name).unwrap_or(name).to_string();
+}
+
+fn intermediaries(registry: &AssetRegistry) -> Vec<String> {
+    return INTERMEDIARIES
+        .iter()
+        .filter_map(|name| registry.asset_id(name))
+        .map(|id| id.to_string())
+        .collect();
+}
+
+/// Returns the pairs whose tickers are needed to value `balances` in `quote`.
+pub fn required_pairs(
+    registry: &AssetRegistry,
+    balances: &BalanceExResponse,
+    quote: &str,
+) -> Vec<String> {
+    let mut assets: HashSet<String> = balances
+        .0
+        .keys()
+        .map(|key| underlying(registry, key))
+        .collect();
+    assets.insert(underlying(registry, quote));
+    assets.extend(intermediaries(registry));
+    let mut pairs: Vec<String> = registry
+        .pairs()
+        .iter()
+        .filter(|(id, pair)| {
+            !id.ends_with(".d") && assets.contains(&pair.base) && assets.contains(&pair.quote)
+        })
+        .map(|(id, _)| id.clone())
+        .collect();
+    pairs.sort();
+    return pairs;
+}
+
+/// Values every balance in `quote` using the last traded prices of
+/// `tickers`. When `exclude_hold` is set, amounts held by open orders
+/// are left out.
+pub fn value(
+    registry: &AssetRegistry,
+    balances: &BalanceExResponse,
+    tickers: &TickerResponse,
+    quote: &str,
+    exclude_hold: bool,
+) -> Portfolio {
+    let quote_id = underlying(registry, quote);
+    let prices = Prices::new(tickers, registry);
+    let intermediaries = intermediaries(registry);
+    let mut holdings = vec![];
+    let mut unpriced = vec![];
+    let mut total = Decimal::ZERO;
+    for (asset, balance) in balances.0.iter() {
+        let amount = if exclude_hold {
+            balance.balance - balance.hold_trade
+        } else {
+            balance.balance
+        };
+        if amount.is_zero() {
+            continue;
+        }
+        let rate = prices.rate(&underlying(registry, asset), &quote_id, &intermediaries);
+        let (price, value, route) = match rate {
+            Some((price, route)) => {
+                let value = amount * price;
+                total += value;
+                (Some(price), Some(value), route)
+            }
+            None => {
+                unpriced.push(asset.clone());
+                (None, None, vec![])
+            }
+        };
+        holdings.push(Holding {
+            asset: asset.clone(),
+            balance: balance.balance,
+            hold_trade: balance.hold_trade,
+            amount,
+            price,
+            value,
+            route,
+        });
+    }
+    holdings.sort_by(|a, b| b.value.cmp(&a.value).then(a.asset.cmp(&b.asset)));
+    unpriced.sort();
+    return Portfolio {
+        quote: quote_id,
+        holdings,
+        total,
+        unpriced,
+    };
+}
+
+/// Fetches balances and the tickers they need and values them in `quote`.
+pub async fn valuate(
+    cred: &Credential,
+    quote: &str,
+    exclude_hold: bool,
+) -> Result<Portfolio, Error> {
+    let registry = AssetRegistry::shared().await?;
+    if registry.asset_id(quote).is_none() {
+        return Err(Error::Invalid(format!("asset {}", quote)));
+    }
+    let balances = private::balance_ex(cred).await?;
+    let pairs = required_pairs(registry, &balances, quote);
+    let tickers = if pairs.is_empty() {
+        TickerResponse(HashMap::new())
+    } else {
+        public::ticker(&pairs.join(",")).await?
+    };
+    return Ok(value(registry, &balances, &tickers, quote, exclude_hold));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::value;
+    use crate::private::BalanceExResponse;
+    use crate::public::TickerResponse;
+    use crate::registry::AssetRegistry;
+    use crate::testing::{self, d};
+
+    fn registry() -> AssetRegistry {
+        return testing::registry(
+            &["XXBT", "ZUSD", "XETH", "DOT"],
+            &[
+                ("XXBTZUSD", "XBTUSD", "XBT/USD", "XXBT", "ZUSD"),
+                ("XETHXXBT", "ETHXBT", "ETH/XBT", "XETH", "XXBT"),
+            ],
+        );
+    }
+
+    fn ticker(last: &str) -> String {
+        return format!(
+            r#"{{"a": ["{0}", "1", "1.000"], "b": ["{0}", "1", "1.000"], "c": ["{0}", "0.1"],
+            "v": ["1", "1"], "p": ["{0}", "{0}"], "t": [1, 1], "l": ["{0}", "{0}"],
+            "h": ["{0}", "{0}"], "o": "{0}"}}"#,
+            last
+        );
+    }
+
+    #[test]
+    fn value_test() {
+        let registry = registry();
+        let balances: BalanceExResponse = serde_json::from_str(
+            r#"{
+                "XXBT": {"balance": "1", "hold_trade": "0.25"},
+                "XBT.M": {"balance": "0.5", "hold_trade": "0"},
+                "ETH.F": {"balance": "10", "hold_trade": "0"},
+                "DOT.S": {"balance": "5", "hold_trade": "0"},
+                "ZUSD": {"balance": "100", "hold_trade": "0"}
+            }"#,
+        )
+        .unwrap();
+        let tickers: TickerResponse = serde_json::from_str(&format!(
+            r#"{{"XXBTZUSD": {}, "XETHXXBT": {}}}"#,
+            ticker("40000"),
+            ticker("0.05")
+        ))
+        .unwrap();
+
+        let portfolio = value(&registry, &balances, &tickers, "USD", false);
+        assert_eq!(portfolio.quote, "ZUSD");
+        assert_eq!(portfolio.total, d("80100"));
+        assert_eq!(portfolio.unpriced, vec!["DOT.S".to_string()]);
+        let holding = |asset: &str| {
+            return portfolio
+                .holdings
+                .iter()
+                .find(|h| h.asset == asset)
+                .unwrap();
+        };
+        assert_eq!(holding("XXBT").value, Some(d("40000")));
+        assert_eq!(holding("XBT.M").value, Some(d("20000")));
+        assert_eq!(holding("ETH.F").price, Some(d("2000")));
+        assert_eq!(holding("ETH.F").route, vec!["XETHXXBT", "XXBTZUSD"]);
+        assert_eq!(holding("ZUSD").route, Vec::<String>::new());
+        assert_eq!(holding("DOT.S").value, None);
+
+        let portfolio = value(&registry, &balances, &tickers, "USD", true);
+        assert_eq!(portfolio.total, d("70100"));
+    }
+}
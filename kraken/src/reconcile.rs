@@ -0,0 +1,263 @@
+use crate::private::{BalanceResponse, Ledger};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Issue {
+    /// The balance of an entry does not follow from the previous one,
+    /// usually because entries are missing in between.
+    Gap {
+        asset: String,
+        id: String,
+        refid: String,
+        time: f64,
+        expected: Decimal,
+        balance: Decimal,
+    },
+    /// The same reference appears more than once for an asset.
+    DuplicateRefid {
+        asset: String,
+        refid: String,
+        ids: Vec<String>,
+    },
+    /// The last ledger balance differs from the reported balance.
+    BalanceMismatch {
+        asset: String,
+        ledger: Decimal,
+        balance: Decimal,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetSummary {
+    pub asset: String,
+    pub entries: usize,
+    pub opening: Decimal,
+    pub amount: Decimal,
+    pub fee: Decimal,
+    pub closing: Decimal,
+    pub balance: Option<Decimal>,
+    pub issues: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reconciliation {
+    pub assets: Vec<AssetSummary>,
+    pub issues: Vec<Issue>,
+}
+
+impl Reconciliation {
+    pub fn is_consistent(&self) -> bool {
+        return self.issues.is_empty();
+    }
+}
+
+/// Orders entries booked at the same time, whose ids are random, by
+/// chaining their balances: the next one is the entry whose balance
+/// before it equals the running balance. The first entry of the history
+/// is the one no other entry of its time leads to. Entries that do not
+/// chain are taken as they come.
+fn chain(entries: &mut [(&String, &Ledger)]) {
+    let before = |e: &Ledger| e.balance - e.amount + e.fee;
+    let mut running: Option<Decimal> = None;
+    let mut start = 0;
+    while start < entries.len() {
+        let time = entries[start].1.time;
+        let end = start
+            + entries[start..]
+                .iter()
+                .take_while(|e| e.1.time == time)
+                .count();
+        for i in start..end {
+            let group = &entries[i..end];
+            let next = match running {
+                Some(running) => group.iter().position(|e| before(e.1) == running),
+                None => group.iter().position(|e| {
+                    !group
+                        .iter()
+                        .any(|o| o.0 != e.0 && o.1.balance == before(e.1))
+                }),
+            };
+            entries.swap(i, i + next.unwrap_or(0));
+            running = Some(entries[i].1.balance);
+        }
+        start = end;
+    }
+}
+
+/// Replays ledger entries asset by asset and checks that each balance
+/// equals the previous one plus amount minus fee, that no reference is
+/// booked twice for an asset and, when `balances` is given, that the
+/// last balance of each asset matches it. Balances must only be given
+/// when the entries cover the whole history of the account.
+pub fn reconcile(
+    ledgers: &HashMap<String, Ledger>,
+    balances: Option<&BalanceResponse>,
+) -> Reconciliation {
+    let mut by_asset: BTreeMap<&str, Vec<(&String, &Ledger)>> = BTreeMap::new();
+    for (id, entry) in ledgers.iter() {
+        by_asset.entry(&entry.asset).or_default().push((id, entry));
+    }
+    let mut assets = vec![];
+    let mut issues = vec![];
+    for (asset, mut entries) in by_asset {
+        entries.sort_by(|a, b| a.1.time.total_cmp(&b.1.time).then(a.0.cmp(b.0)));
+        chain(&mut entries);
+        let mut asset_issues = vec![];
+        let mut refids: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        let first = entries[0].1;
+        let opening = first.balance - first.amount + first.fee;
+        let mut running = opening;
+        let mut amount = Decimal::ZERO;
+        let mut fee = Decimal::ZERO;
+        for (id, entry) in entries.iter() {
+            let expected = running + entry.amount - entry.fee;
+            if expected != entry.balance {
+                asset_issues.push(Issue::Gap {
+                    asset: asset.to_string(),
+                    id: id.to_string(),
+                    refid: entry.refid.clone(),
+                    time: entry.time,
+                    expected,
+                    balance: entry.balance,
+                });
+            }
+            refids.entry(&entry.refid).or_default().push(id.to_string());
+            running = entry.balance;
+            amount += entry.amount;
+            fee += entry.fee;
+        }
+        for (refid, ids) in refids {
+            if ids.len() > 1 {
+                asset_issues.push(Issue::DuplicateRefid {
+                    asset: asset.to_string(),
+                    refid: refid.to_string(),
+                    ids,
+                });
+            }
+        }
+        let balance = balances.map(|b| b.0.get(asset).copied().unwrap_or_default());
+        if let Some(balance) = balance {
+            if balance != running {
+                asset_issues.push(Issue::BalanceMismatch {
+                    asset: asset.to_string(),
+                    ledger: running,
+                    balance,
+                });
+            }
+        }
+        assets.push(AssetSummary {
+            asset: asset.to_string(),
+            entries: entries.len(),
+            opening,
+            amount,
+            fee,
+            closing: running,
+            balance,
+            issues: asset_issues.len(),
+        });
+        issues.extend(asset_issues);
+    }
+    // balances held without any ledger entry.
+    if let Some(balances) = balances {
+        let known: BTreeSet<&str> = assets.iter().map(|a| a.asset.as_str()).collect();
+        let mut missing: Vec<(&String, &Decimal)> = balances
+            .0
+            .iter()
+            .filter(|(asset, balance)| !balance.is_zero() && !known.contains(asset.as_str()))
+            .collect();
+        missing.sort();
+        for (asset, balance) in missing {
+            issues.push(Issue::BalanceMismatch {
+                asset: asset.clone(),
+                ledger: Decimal::ZERO,
+                balance: *balance,
+            });
+        }
+    }
+    return Reconciliation { assets, issues };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reconcile, Issue};
+    use crate::private::{BalanceResponse, Ledger};
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+
+    fn entry(
+        id: &str,
+        refid: &str,
+        time: f64,
+        amount: i64,
+        fee: i64,
+        balance: i64,
+    ) -> (String, Ledger) {
+        let ledger = serde_json::from_value(serde_json::json!({
+            "refid": refid, "time": time, "type": "trade", "subtype": "", "aclass": "currency",
+            "asset": "ZUSD", "amount": amount.to_string(), "fee": fee.to_string(),
+            "balance": balance.to_string()
+        }))
+        .unwrap();
+        return (id.to_string(), ledger);
+    }
+
+    #[test]
+    fn consistent_test() {
+        let ledgers: HashMap<String, Ledger> = vec![
+            entry("L1", "R1", 1.0, 100, 0, 100),
+            entry("L2", "R2", 2.0, -50, 1, 49),
+        ]
+        .into_iter()
+        .collect();
+        let balances = BalanceResponse(
+            vec![("ZUSD".to_string(), Decimal::from(49))]
+                .into_iter()
+                .collect(),
+        );
+        let report = reconcile(&ledgers, Some(&balances));
+        assert!(report.is_consistent());
+        assert_eq!(report.assets[0].closing, Decimal::from(49));
+    }
+
+    #[test]
+    fn issues_test() {
+        let ledgers: HashMap<String, Ledger> = vec![
+            entry("L1", "R1", 1.0, 100, 0, 100),
+            entry("L2", "R2", 2.0, -50, 0, 30),
+            entry("L3", "R2", 3.0, 10, 0, 40),
+        ]
+        .into_iter()
+        .collect();
+        let balances = BalanceResponse(
+            vec![("ZUSD".to_string(), Decimal::from(45))]
+                .into_iter()
+                .collect(),
+        );
+        let report = reconcile(&ledgers, Some(&balances));
+        assert_eq!(report.issues.len(), 3);
+        assert!(matches!(&report.issues[0], Issue::Gap { id, .. } if id == "L2"));
+        assert!(matches!(&report.issues[1], Issue::DuplicateRefid { refid, .. } if refid == "R2"));
+        assert!(matches!(report.issues[2], Issue::BalanceMismatch { .. }));
+    }
+
+    #[test]
+    fn chain_test() {
+        // both sides of trades booked in the same second, the ids sorting
+        // against the order of the balances.
+        let ledgers: HashMap<String, Ledger> = vec![
+            entry("LB", "R1", 1.0, 100, 0, 100),
+            entry("LA", "R2", 1.0, -30, 0, 70),
+            entry("LD", "R3", 2.0, -50, 0, 20),
+            entry("LC", "R4", 2.0, 20, 0, 40),
+        ]
+        .into_iter()
+        .collect();
+        let report = reconcile(&ledgers, None);
+        assert!(report.is_consistent());
+        assert_eq!(report.assets[0].opening, Decimal::ZERO);
+        assert_eq!(report.assets[0].closing, Decimal::from(40));
+    }
+}
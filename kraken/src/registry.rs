@@ -0,0 +1,199 @@
+use crate::public::{self, Asset, AssetPair, AssetPairResponse, AssetsResponse};
+use crate::Error;
+use std::collections::HashMap;
+use tokio::sync::OnceCell;
+
+/// Names used outside of Kraken for assets that Kraken lists
+/// under a different altname.
+const COMMON_NAMES: &[(&str, &str)] = &[("BTC", "XBT"), ("DOGE", "XDG")];
+
+/// Separators found in pair spellings such as `XBT/USD` or `BTC-USD`.
+const PAIR_SEPARATORS: &[char] = &['/', '-', '_', ':'];
+
+static SHARED: OnceCell<AssetRegistry> = OnceCell::const_new();
+
+/// Resolves the many spellings Kraken uses for assets (`XXBT`, `XBT`,
+/// `BTC`) and pairs (`XXBTZUSD`, `XBTUSD`, `XBT/USD`, `BTC/USD`) to the
+/// canonical identifiers used as keys in API responses.
+#[derive(Debug)]
+pub struct AssetRegistry {
+    assets: HashMap<String, Asset>,
+    pairs: HashMap<String, AssetPair>,
+    asset_aliases: HashMap<String, String>,
+    pair_aliases: HashMap<String, String>,
+    pairs_by_assets: HashMap<(String, String), String>,
+}
+
+fn normalize(name: &str) -> String {
+    return name.trim().to_uppercase();
+}
+
+impl AssetRegistry {
+    pub fn new(assets: AssetsResponse, pairs: AssetPairResponse) -> Self {
+        let mut asset_aliases = HashMap::new();
+        for (key, asset) in assets.0.iter() {
+            asset_aliases.insert(normalize(&asset.altname), key.clone());
+            asset_aliases.insert(normalize(key), key.clone());
+        }
+        for (common, altname) in COMMON_NAMES {
+            if let Some(key) = asset_aliases.get(*altname).cloned() {
+                asset_aliases.entry(common.to_string()).or_insert(key);
+            }
+        }
+        let mut pair_aliases = HashMap::new();
+        let mut pairs_by_assets = HashMap::new();
+        for (key, pair) in pairs.0.iter() {
+            pair_aliases.insert(normalize(&pair.altname), key.clone());
+            pair_aliases.insert(normalize(&pair.wsname), key.clone());
+            pair_aliases.insert(normalize(key), key.clone());
+            // darkpool pairs share base and quote with the regular pair.
+            if !key.ends_with(".d") {
+                pairs_by_assets.insert((pair.base.clone(), pair.quote.clone()), key.clone());
+            }
+        }
+        return Self {
+            assets: assets.0,
+            pairs: pairs.0,
+            asset_aliases,
+            pair_aliases,
+            pairs_by_assets,
+        };
+    }
+
+    /// Fetches every asset and pair from the public endpoints.
+    pub async fn load() -> Result<Self, Error> {
+        let assets = public::assets(None, None).await?;
+        let pairs = public::asset_pair(&[], None).await?;
+        return Ok(Self::new(assets, pairs));
+    }
+
+    /// Returns a registry loaded once per process and shared afterwards.
+    pub async fn shared() -> Result<&'static Self, Error> {
+        return SHARED.get_or_try_init(Self::load).await;
+    }
+
+    /// Returns the canonical identifier of an asset (e.g. `XXBT` for `BTC`).
+    pub fn asset_id(&self, name: &str) -> Option<&str> {
+        return self.asset_aliases.get(&normalize(name)).map(|v| v.as_str());
+    }
+
+    /// Returns the canonical identifier of a pair (e.g. `XXBTZUSD` for
+    /// `BTC/USD`). Pairs that are not listed under the given spelling are
+    /// resolved from their base and quote assets.
+    pub fn pair_id(&self, name: &str) -> Option<&str> {
+        let name = normalize(name);
+        if let Some(id) = self.pair_aliases.get(&name) {
+            return Some(id);
+        }
+        if let Some((base, quote)) = name.split_once(PAIR_SEPARATORS) {
+            return self.pair_by_assets(base, quote);
+        }
+        // no separator, try every split point (BTCUSD, XBTEUR, DOGEUSDT...).
+        for (idx, _) in name.char_indices().skip(1) {
+            let (base, quote) = name.split_at(idx);
+            if let Some(id) = self.pair_by_assets(base, quote) {
+                return Some(id);
+            }
+        }
+        return None;
+    }
+
+    /// Returns the identifier of the pair trading `base` against `quote`.
+    pub fn pair_by_assets(&self, base: &str, quote: &str) -> Option<&str> {
+        let base = self.asset_id(base)?.to_string();
+        let quote = self.asset_id(quote)?.to_string();
+        return self.pairs_by_assets.get(&(base, quote)).map(|v| v.as_str());
+    }
+
+    pub fn asset(&self, name: &str) -> Option<&Asset> {
+        return self.assets.get(self.asset_id(name)?);
+    }
+
+    pub fn pair(&self, name: &str) -> Option<&AssetPair> {
+        return self.pairs.get(self.pair_id(name)?);
+    }
+
+    pub fn assets(&self) -> &HashMap<String, Asset> {
+        return &self.assets;
+    }
+
+    pub fn pairs(&self) -> &HashMap<String, AssetPair> {
+        return &self.pairs;
+    }
+
+    /// Looks up an asset keyed map (such as `BalanceResponse`) by any alias.
+    pub fn get_asset<'a, V>(&self, map: &'a HashMap<String, V>, name: &str) -> Option<&'a V> {
+        if let Some(val) = map.get(name) {
+            return Some(val);
+        }
+        let id = self.asset_id(name)?;
+        if let Some(val) = map.get(id) {
+            return Some(val);
+        }
+        let altname = &self.assets.get(id)?.altname;
+        return map.get(altname);
+    }
+
+    /// Looks up a pair keyed map (such as `TickerResponse`) by any alias.
+    pub fn get_pair<'a, V>(&self, map: &'a HashMap<String, V>, name: &str) -> Option<&'a V> {
+        if let Some(val) = map.get(name) {
+            return Some(val);
+        }
+        let id = self.pair_id(name)?;
+        if let Some(val) = map.get(id) {
+            return Some(val);
+        }
+        let pair = self.pairs.get(id)?;
+        return map.get(&pair.altname).or_else(|| map.get(&pair.wsname));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AssetRegistry;
+    use crate::testing;
+    use std::collections::HashMap;
+
+    fn registry() -> AssetRegistry {
+        return testing::registry(
+            &["XXBT", "ZUSD", "XXDG", "USDT"],
+            &[
+                ("XXBTZUSD", "XBTUSD", "XBT/USD", "XXBT", "ZUSD"),
+                ("XDGUSDT", "XDGUSDT", "XDG/USDT", "XXDG", "USDT"),
+            ],
+        );
+    }
+
+    #[test]
+    fn resolve_asset_test() {
+        let registry = registry();
+        assert_eq!(registry.asset_id("XXBT"), Some("XXBT"));
+        assert_eq!(registry.asset_id("xbt"), Some("XXBT"));
+        assert_eq!(registry.asset_id("BTC"), Some("XXBT"));
+        assert_eq!(registry.asset_id("DOGE"), Some("XXDG"));
+        assert_eq!(registry.asset_id("ETH"), None);
+    }
+
+    #[test]
+    fn resolve_pair_test() {
+        let registry = registry();
+        for name in [
+            "XXBTZUSD", "XBTUSD", "XBT/USD", "btc/usd", "BTC-USD", "BTCUSD",
+        ] {
+            assert_eq!(registry.pair_id(name), Some("XXBTZUSD"), "{}", name);
+        }
+        assert_eq!(registry.pair_id("DOGEUSDT"), Some("XDGUSDT"));
+        assert_eq!(registry.pair_id("BTC/EUR"), None);
+    }
+
+    #[test]
+    fn lookup_test() {
+        let registry = registry();
+        let mut balance = HashMap::new();
+        balance.insert("XXBT".to_string(), 1);
+        assert_eq!(registry.get_asset(&balance, "BTC"), Some(&1));
+        let mut ticker = HashMap::new();
+        ticker.insert("XXBTZUSD".to_string(), 2);
+        assert_eq!(registry.get_pair(&ticker, "XBT/USD"), Some(&2));
+    }
+}
@@ -0,0 +1,662 @@
+use crate::exchange::{self, Exchange, MarketEvent};
+use crate::private::{NewOrder, Order};
+use crate::Error;
+use async_trait::async_trait;
+use chrono::Utc;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Errors of the exchange after which an order may still have been
+/// placed. Such orders stay pending until `reconcile` finds out.
+const UNCERTAIN: &[&str] = &[
+    "EService:Unavailable",
+    "EService:Busy",
+    "EGeneral:Internal error",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OrderState {
+    /// Sent without an answer from the exchange yet.
+    Pending,
+    Open,
+    PartiallyFilled,
+    Closed,
+    Canceled,
+    Expired,
+    Rejected,
+}
+
+impl OrderState {
+    pub fn is_final(&self) -> bool {
+        return matches!(
+            self,
+            OrderState::Closed | OrderState::Canceled | OrderState::Expired | OrderState::Rejected
+        );
+    }
+}
+
+/// An order placed through an `OrderManager`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackedOrder {
+    pub userref: i32,
+    pub txid: Option<String>,
+    pub order: NewOrder,
+    pub state: OrderState,
+    pub vol_exec: Decimal,
+    pub cost: Decimal,
+    pub fee: Decimal,
+    /// Why the order was rejected.
+    pub error: Option<String>,
+}
+
+/// Changes of the tracked orders, in the order they were seen.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Update {
+    Placed {
+        userref: i32,
+        txid: String,
+    },
+    /// Volume, cost and fee executed since the previous update.
+    Filled {
+        userref: i32,
+        txid: String,
+        volume: Decimal,
+        cost: Decimal,
+        fee: Decimal,
+    },
+    /// The order was closed, canceled or expired.
+    Done {
+        userref: i32,
+        txid: String,
+        state: OrderState,
+    },
+    Rejected {
+        userref: i32,
+        error: String,
+    },
+}
+
+/// Tracks orders from submission to their final state by txid and
+/// userref. Each order gets a userref, so an order whose submission
+/// failed on the network can still be found on the exchange by
+/// `reconcile`.
+#[derive(Serialize, Deserialize)]
+pub struct OrderManager {
+    orders: HashMap<i32, TrackedOrder>,
+    txids: HashMap<String, i32>,
+    updates: Vec<Update>,
+    next_userref: i32,
+}
+
+impl Default for OrderManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrderManager {
+    /// Userrefs start from the current time, so the orders of a
+    /// restarted bot do not reuse those of the previous run.
+    pub fn new() -> Self {
+        return Self::starting_at((Utc::now().timestamp() % i32::MAX as i64) as i32);
+    }
+
+    /// Userrefs are assigned from `userref + 1` on.
+    pub fn starting_at(userref: i32) -> Self {
+        Self {
+            orders: HashMap::new(),
+            txids: HashMap::new(),
+            updates: vec![],
+            next_userref: userref,
+        }
+    }
+
+    pub fn get(&self, userref: i32) -> Option<&TrackedOrder> {
+        return self.orders.get(&userref);
+    }
+
+    pub fn by_txid(&self, txid: &str) -> Option<&TrackedOrder> {
+        return self.orders.get(self.txids.get(txid)?);
+    }
+
+    /// Orders that did not reach a final state.
+    pub fn active(&self) -> Vec<&TrackedOrder> {
+        let mut orders: Vec<&TrackedOrder> = self
+            .orders
+            .values()
+            .filter(|t| !t.state.is_final())
+            .collect();
+        orders.sort_by_key(|t| t.userref);
+        return orders;
+    }
+
+    /// Returns the updates seen since the previous call.
+    pub fn drain(&mut self) -> Vec<Update> {
+        return std::mem::take(&mut self.updates);
+    }
+
+    /// Places an order and returns its userref, the one of the order when
+    /// set. Orders refused by the exchange are rejected; on network and
+    /// service errors the order stays pending until `reconcile` finds out
+    /// what happened.
+    pub async fn submit(
+        &mut self,
+        exchange: &dyn Exchange,
+        order: &NewOrder,
+    ) -> Result<i32, Error> {
+        let userref = self.prepare(order)?;
+        self.send(exchange, userref).await?;
+        return Ok(userref);
+    }
+
+    /// Tracks an order as pending without placing it, so its userref can
+    /// be saved before `send` and found on the exchange after a crash.
+    pub fn prepare(&mut self, order: &NewOrder) -> Result<i32, Error> {
+        let userref = match order.userref {
+            Some(userref) if self.orders.contains_key(&userref) => {
+                return Err(Error::Invalid(format!(
+                    "userref, {} is already tracked",
+                    userref
+                )));
+            }
+            Some(userref) => userref,
+            None => {
+                self.next_userref += 1;
+                self.next_userref
+            }
+        };
+        let mut order = order.clone();
+        order.userref = Some(userref);
+        let tracked = TrackedOrder {
+            userref,
+            txid: None,
+            order: order.clone(),
+            state: OrderState::Pending,
+            vol_exec: Decimal::ZERO,
+            cost: Decimal::ZERO,
+            fee: Decimal::ZERO,
+            error: None,
+        };
+        self.orders.insert(userref, tracked);
+        return Ok(userref);
+    }
+
+    /// Places an order tracked by `prepare`.
+    pub async fn send(&mut self, exchange: &dyn Exchange, userref: i32) -> Result<(), Error> {
+        let order = self
+            .orders
+            .get(&userref)
+            .filter(|t| t.state == OrderState::Pending && t.txid.is_none())
+            .map(|t| t.order.clone())
+            .ok_or_else(|| Error::Invalid(format!("userref, order {} is not prepared", userref)))?;
+        let txid = match exchange.add_order(&order, false).await {
+            Ok(response) => response.txid.and_then(|txids| txids.into_iter().next()),
+            Err(Error::API(error)) if !UNCERTAIN.iter().any(|e| error.starts_with(e)) => {
+                self.reject(userref, &error);
+                return Err(Error::API(error));
+            }
+            Err(e) => return Err(e),
+        };
+        match txid {
+            Some(txid) => self.placed(userref, &txid),
+            None => self.reject(userref, "no txid returned"),
+        }
+        return Ok(());
+    }
+
+    /// Cancels an order and applies its state once canceled, including
+    /// what was filled meanwhile.
+    pub async fn cancel(&mut self, exchange: &dyn Exchange, userref: i32) -> Result<(), Error> {
+        let txid = self
+            .orders
+            .get(&userref)
+            .and_then(|t| t.txid.clone())
+            .ok_or_else(|| Error::Invalid(format!("userref, order {} was not placed", userref)))?;
+        exchange.cancel_order(&txid).await?;
+        for (txid, order) in exchange.query_orders(&[&txid]).await?.0.iter() {
+            self.apply(txid, order);
+        }
+        return Ok(());
+    }
+
+    fn placed(&mut self, userref: i32, txid: &str) {
+        let tracked = self.orders.get_mut(&userref).unwrap();
+        tracked.txid = Some(txid.to_string());
+        tracked.state = OrderState::Open;
+        self.txids.insert(txid.to_string(), userref);
+        self.updates.push(Update::Placed {
+            userref,
+            txid: txid.to_string(),
+        });
+    }
+
+    fn reject(&mut self, userref: i32, error: &str) {
+        let tracked = self.orders.get_mut(&userref).unwrap();
+        tracked.state = OrderState::Rejected;
+        tracked.error = Some(error.to_string());
+        self.updates.push(Update::Rejected {
+            userref,
+            error: error.to_string(),
+        });
+    }
+
+    /// Applies the state of an order as returned by the exchange. Orders
+    /// that were not placed through the manager are ignored.
+    pub fn apply(&mut self, txid: &str, order: &Order) {
+        let userref = match self.txids.get(txid) {
+            Some(userref) => *userref,
+            None => {
+                // a submission whose answer was lost.
+                let pending = order.userref.filter(
+                    |u| matches!(self.orders.get(u), Some(t) if t.state == OrderState::Pending),
+                );
+                match pending {
+                    Some(userref) => {
+                        self.placed(userref, txid);
+                        userref
+                    }
+                    None => return,
+                }
+            }
+        };
+        let tracked = self.orders.get_mut(&userref).unwrap();
+        if order.vol_exec > tracked.vol_exec {
+            self.updates.push(Update::Filled {
+                userref,
+                txid: txid.to_string(),
+                volume: order.vol_exec - tracked.vol_exec,
+                cost: order.cost - tracked.cost,
+                fee: order.fee - tracked.fee,
+            });
+            tracked.vol_exec = order.vol_exec;
+            tracked.cost = order.cost;
+            tracked.fee = order.fee;
+        }
+        let state = match order.status.as_str() {
+            "closed" => OrderState::Closed,
+            "canceled" => OrderState::Canceled,
+            "expired" => OrderState::Expired,
+            _ if !order.vol_exec.is_zero() => OrderState::PartiallyFilled,
+            _ => OrderState::Open,
+        };
+        if state != tracked.state && !tracked.state.is_final() {
+            tracked.state = state;
+            if state.is_final() {
+                self.updates.push(Update::Done {
+                    userref,
+                    txid: txid.to_string(),
+                    state,
+                });
+            }
+        }
+    }
+
+    /// Brings the tracked orders up to date with the exchange, after a
+    /// reconnect or on each round of a polling loop: open orders first,
+    /// then the orders that left the book, then pending submissions,
+    /// which are rejected when the exchange does not know them.
+    pub async fn reconcile(&mut self, exchange: &dyn Exchange) -> Result<(), Error> {
+        let open = exchange.open_orders().await?.open;
+        for (txid, order) in open.iter() {
+            self.apply(txid, order);
+        }
+        let gone: Vec<String> = self
+            .active()
+            .iter()
+            .filter_map(|t| t.txid.clone())
+            .filter(|txid| !open.contains_key(txid))
+            .collect();
+        if !gone.is_empty() {
+            let txids: Vec<&str> = gone.iter().map(|t| t.as_str()).collect();
+            for (txid, order) in exchange.query_orders(&txids).await?.0.iter() {
+                self.apply(txid, order);
+            }
+        }
+        let pending = |orders: &HashMap<i32, TrackedOrder>| -> Vec<i32> {
+            orders
+                .values()
+                .filter(|t| t.state == OrderState::Pending)
+                .map(|t| t.userref)
+                .collect()
+        };
+        if pending(&self.orders).is_empty() {
+            return Ok(());
+        }
+        for (txid, order) in exchange.closed_orders().await?.closed.iter() {
+            self.apply(txid, order);
+        }
+        for userref in pending(&self.orders) {
+            self.reject(userref, "order not found on the exchange");
+        }
+        return Ok(());
+    }
+}
+
+/// What a strategy can act on: the exchange and its orders.
+pub struct Context<'a> {
+    pub exchange: &'a dyn Exchange,
+    pub orders: &'a mut OrderManager,
+}
+
+impl<'a> Context<'a> {
+    pub async fn submit(&mut self, order: &NewOrder) -> Result<i32, Error> {
+        return self.orders.submit(self.exchange, order).await;
+    }
+
+    pub async fn cancel(&mut self, userref: i32) -> Result<(), Error> {
+        return self.orders.cancel(self.exchange, userref).await;
+    }
+}
+
+/// Trading logic driven by a `Runtime`. Errors stop the runtime.
+#[async_trait]
+pub trait Strategy: Send {
+    async fn on_market(&mut self, event: &MarketEvent, ctx: &mut Context<'_>) -> Result<(), Error>;
+
+    async fn on_order(&mut self, _update: &Update, _ctx: &mut Context<'_>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Polls the market data of some pairs and feeds it, with the updates of
+/// the orders, to a strategy. The exchange is either `exchange::Live` or
+/// `paper::Paper`.
+pub struct Runtime {
+    exchange: Box<dyn Exchange>,
+    orders: OrderManager,
+    pairs: Vec<String>,
+    since: HashMap<String, i64>,
+    interval: Duration,
+}
+
+impl Runtime {
+    pub fn new(exchange: Box<dyn Exchange>, pairs: &[&str]) -> Self {
+        Self {
+            exchange,
+            orders: OrderManager::new(),
+            pairs: pairs.iter().map(|p| p.to_string()).collect(),
+            since: HashMap::new(),
+            interval: Duration::from_secs(5),
+        }
+    }
+
+    /// Time between two rounds of `run`, 5 seconds by default.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        return self;
+    }
+
+    pub fn with_orders(mut self, orders: OrderManager) -> Self {
+        self.orders = orders;
+        return self;
+    }
+
+    pub fn orders(&self) -> &OrderManager {
+        return &self.orders;
+    }
+
+    pub fn exchange(&self) -> &dyn Exchange {
+        return self.exchange.as_ref();
+    }
+
+    /// Runs rounds until the strategy fails. Network errors are retried
+    /// at the next round, which reconciles the orders first.
+    pub async fn run<S: Strategy>(&mut self, strategy: &mut S) -> Result<(), Error> {
+        loop {
+            match self.step(strategy).await {
+                Ok(()) | Err(Error::Request(_)) => {}
+                Err(e) => return Err(e),
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    /// Fetches the market data of every pair and processes it.
+    pub async fn step<S: Strategy>(&mut self, strategy: &mut S) -> Result<(), Error> {
+        let mut events = vec![];
+        for pair in self.pairs.iter() {
+            let (fetched, last) = exchange::fetch(pair, self.since.get(pair).copied()).await?;
+            events.extend(fetched);
+            if let Some(last) = last {
+                self.since.insert(pair.clone(), last);
+            }
+        }
+        return self.process(strategy, &events).await;
+    }
+
+    /// Shows the events to the exchange, reconciles the orders and hands
+    /// the events to the strategy, each followed by the order updates.
+    pub async fn process<S: Strategy>(
+        &mut self,
+        strategy: &mut S,
+        events: &[MarketEvent],
+    ) -> Result<(), Error> {
+        for event in events {
+            self.exchange.observe(event)?;
+        }
+        self.orders.reconcile(self.exchange.as_ref()).await?;
+        self.deliver(strategy).await?;
+        for event in events {
+            let mut ctx = Context {
+                exchange: self.exchange.as_ref(),
+                orders: &mut self.orders,
+            };
+            strategy.on_market(event, &mut ctx).await?;
+            self.deliver(strategy).await?;
+        }
+        return Ok(());
+    }
+
+    async fn deliver<S: Strategy>(&mut self, strategy: &mut S) -> Result<(), Error> {
+        loop {
+            let updates = self.orders.drain();
+            if updates.is_empty() {
+                return Ok(());
+            }
+            for update in updates.iter() {
+                let mut ctx = Context {
+                    exchange: self.exchange.as_ref(),
+                    orders: &mut self.orders,
+                };
+                strategy.on_order(update, &mut ctx).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Context, OrderManager, OrderState, Runtime, Strategy, Update};
+    use crate::exchange::{Exchange, MarketEvent};
+    use crate::private::OpenOrdersResponse;
+    use crate::testing::{book_event, d, order, paper, Unreliable};
+    use crate::Error;
+    use async_trait::async_trait;
+
+    #[tokio::test]
+    async fn order_manager_test() {
+        let paper = paper();
+        paper.observe(&book_event("98", "100")).unwrap();
+        let mut orders = OrderManager::starting_at(100);
+
+        let userref = orders
+            .submit(&paper, &order("buy", "limit", "0.5", Some("99")))
+            .await
+            .unwrap();
+        assert_eq!(userref, 101);
+        let txid = orders.get(101).unwrap().txid.clone().unwrap();
+        assert_eq!(
+            orders.drain(),
+            vec![Update::Placed {
+                userref,
+                txid: txid.clone()
+            }]
+        );
+
+        let trades = vec![(d("99"), d("0.2"), 0.0)];
+        let event = MarketEvent::Trades {
+            pair: "XXBTZUSD".to_string(),
+            trades,
+        };
+        paper.observe(&event).unwrap();
+        orders.reconcile(&paper).await.unwrap();
+        assert_eq!(
+            orders.by_txid(&txid).unwrap().state,
+            OrderState::PartiallyFilled
+        );
+        let fee = d("19.8") * d("0.0016");
+        let filled = Update::Filled {
+            userref,
+            txid: txid.clone(),
+            volume: d("0.2"),
+            cost: d("19.8"),
+            fee,
+        };
+        assert_eq!(orders.drain(), vec![filled]);
+
+        orders.cancel(&paper, userref).await.unwrap();
+        let done = Update::Done {
+            userref,
+            txid,
+            state: OrderState::Canceled,
+        };
+        assert_eq!(orders.drain(), vec![done]);
+        assert!(orders.active().is_empty());
+
+        // filled on submission, seen by the next reconcile.
+        let userref = orders
+            .submit(&paper, &order("buy", "market", "0.3", None))
+            .await
+            .unwrap();
+        orders.reconcile(&paper).await.unwrap();
+        let updates = orders.drain();
+        assert_eq!(updates.len(), 3);
+        assert!(matches!(updates[1], Update::Filled { volume, .. } if volume == d("0.3")));
+        assert_eq!(orders.get(userref).unwrap().state, OrderState::Closed);
+
+        assert!(orders
+            .submit(&paper, &order("sell", "limit", "100", Some("200")))
+            .await
+            .is_err());
+        let rejected = Update::Rejected {
+            userref: 103,
+            error: "EOrder:Insufficient funds".to_string(),
+        };
+        assert_eq!(orders.drain(), vec![rejected]);
+    }
+
+    /// Buys once on the first book and records the order updates.
+    struct Buyer {
+        updates: Vec<Update>,
+    }
+
+    #[async_trait]
+    impl Strategy for Buyer {
+        async fn on_market(
+            &mut self,
+            event: &MarketEvent,
+            ctx: &mut Context<'_>,
+        ) -> Result<(), Error> {
+            if matches!(event, MarketEvent::Book { .. })
+                && ctx.orders.active().is_empty()
+                && self.updates.is_empty()
+            {
+                ctx.submit(&order("buy", "limit", "0.1", Some("95")))
+                    .await?;
+            }
+            Ok(())
+        }
+
+        async fn on_order(&mut self, update: &Update, _ctx: &mut Context<'_>) -> Result<(), Error> {
+            self.updates.push(update.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn runtime_test() {
+        let mut runtime =
+            Runtime::new(Box::new(paper()), &["XBTUSD"]).with_orders(OrderManager::starting_at(0));
+        let mut buyer = Buyer { updates: vec![] };
+        runtime
+            .process(&mut buyer, &[book_event("98", "100")])
+            .await
+            .unwrap();
+        assert_eq!(buyer.updates.len(), 1);
+        runtime
+            .process(&mut buyer, &[book_event("94", "95")])
+            .await
+            .unwrap();
+        assert!(matches!(
+            &buyer.updates[1],
+            Update::Filled { userref: 1, .. }
+        ));
+        assert!(matches!(
+            &buyer.updates[2],
+            Update::Done {
+                state: OrderState::Closed,
+                ..
+            }
+        ));
+        let balance = runtime.exchange().balance().await.unwrap();
+        assert_eq!(balance.0["XXBT"], d("0.1"));
+    }
+
+    #[tokio::test]
+    async fn unavailable_test() {
+        let paper = paper();
+        paper.observe(&book_event("98", "100")).unwrap();
+        let exchange = Unreliable::new(paper).with_add_order("EService:Unavailable");
+        let mut orders = OrderManager::starting_at(0);
+
+        let error = orders
+            .submit(&exchange, &order("buy", "limit", "0.5", Some("99")))
+            .await;
+        assert!(matches!(error, Err(Error::API(e)) if e == "EService:Unavailable"));
+        assert_eq!(orders.get(1).unwrap().state, OrderState::Pending);
+        assert!(orders.drain().is_empty());
+
+        orders.reconcile(&exchange).await.unwrap();
+        assert_eq!(orders.get(1).unwrap().state, OrderState::Open);
+        assert!(matches!(
+            &orders.drain()[..],
+            [Update::Placed { userref: 1, .. }]
+        ));
+
+        // a refused order is rejected at once.
+        let error = orders
+            .submit(&exchange, &order("sell", "limit", "100", Some("200")))
+            .await;
+        assert!(matches!(error, Err(Error::API(e)) if e == "EOrder:Insufficient funds"));
+        assert_eq!(orders.get(2).unwrap().state, OrderState::Rejected);
+    }
+
+    #[test]
+    fn lost_submission_test() {
+        let mut orders = OrderManager::starting_at(0);
+        let userref = orders
+            .prepare(&order("buy", "limit", "1.25", Some("30010")))
+            .unwrap();
+        // the exchange sends the userref as a number.
+        let fixture: serde_json::Value =
+            serde_json::from_str(include_str!("../tests/fixtures/private-OpenOrders.json"))
+                .unwrap();
+        let mut result = fixture["response"]["result"].clone();
+        let txid = "OQCLML-BW3P3-BUCMWZ";
+        result["open"][txid]["userref"] = serde_json::json!(userref);
+        let open: OpenOrdersResponse = serde_json::from_value(result).unwrap();
+        assert_eq!(open.open[txid].userref, Some(userref));
+
+        orders.apply(txid, &open.open[txid]);
+        let tracked = orders.get(userref).unwrap();
+        assert_eq!(tracked.txid.as_deref(), Some(txid));
+        assert_eq!(tracked.state, OrderState::PartiallyFilled);
+        assert!(matches!(
+            &orders.drain()[..],
+            [Update::Placed { .. }, Update::Filled { .. }]
+        ));
+    }
+}
@@ -0,0 +1,521 @@
+use crate::private::Ledger;
+use crate::public::{self, Trade};
+use crate::registry::AssetRegistry;
+use crate::{retry_throttled, Error};
+use chrono::DateTime;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::time::Duration;
+
+/// Prices are looked up per hour, from the trades of the following day.
+const PRICE_BUCKET: i64 = 3600;
+const PRICE_WINDOW: f64 = 86400.0;
+
+/// Values an asset in the reporting currency at a given time. Used for
+/// crypto to crypto trades, deposits and staking rewards.
+pub trait PriceSource {
+    fn price(&self, asset: &str, time: f64) -> Option<Decimal>;
+}
+
+impl<F> PriceSource for F
+where
+    F: Fn(&str, f64) -> Option<Decimal>,
+{
+    fn price(&self, asset: &str, time: f64) -> Option<Decimal> {
+        return self(asset, time);
+    }
+}
+
+/// A price source that knows no price; acquisitions it would have valued
+/// get a zero cost basis and a note in the report.
+pub struct NoPrices;
+
+impl PriceSource for NoPrices {
+    fn price(&self, _asset: &str, _time: f64) -> Option<Decimal> {
+        return None;
+    }
+}
+
+/// Prices taken from the public trades of the pair between an asset and
+/// the reporting currency: the first trade of the hour a price is needed
+/// in, or of the day after for illiquid pairs.
+pub struct MarketPrices(HashMap<(String, i64), Decimal>);
+
+impl MarketPrices {
+    /// Fetches every price `events` needs to value `ledgers` in
+    /// `currency`, waiting `delay` between requests. Assets without a
+    /// pair against `currency` stay unpriced.
+    pub async fn fetch(
+        registry: &AssetRegistry,
+        ledgers: &HashMap<String, Ledger>,
+        currency: &str,
+        delay: Duration,
+    ) -> Result<Self, Error> {
+        let needed = RefCell::new(BTreeSet::new());
+        events(ledgers, currency, &|asset: &str, time: f64| {
+            needed
+                .borrow_mut()
+                .insert((asset.to_string(), bucket(time)));
+            return None;
+        });
+        let mut prices = HashMap::new();
+        for (asset, start) in needed.into_inner() {
+            let (pair, inverse) = match (
+                registry.pair_by_assets(&asset, currency),
+                registry.pair_by_assets(currency, &asset),
+            ) {
+                (Some(pair), _) => (pair, false),
+                (None, Some(pair)) => (pair, true),
+                _ => continue,
+            };
+            let price = retry_throttled(delay * 4, || first_trade(pair, start)).await?;
+            match price {
+                Some(price) if inverse && !price.is_zero() => {
+                    prices.insert((asset, start), Decimal::ONE / price);
+                }
+                Some(price) if !inverse => {
+                    prices.insert((asset, start), price);
+                }
+                _ => {}
+            }
+            tokio::time::sleep(delay).await;
+        }
+        return Ok(Self(prices));
+    }
+}
+
+impl PriceSource for MarketPrices {
+    fn price(&self, asset: &str, time: f64) -> Option<Decimal> {
+        return self.0.get(&(asset.to_string(), bucket(time))).copied();
+    }
+}
+
+fn bucket(time: f64) -> i64 {
+    let time = time.trunc() as i64;
+    return time - time.rem_euclid(PRICE_BUCKET);
+}
+
+/// Returns the price of the first trade of `pair` from `start` (unix
+/// time), if it happened within `PRICE_WINDOW`.
+async fn first_trade(pair: &str, start: i64) -> Result<Option<Decimal>, Error> {
+    let response = public::trades(pair, Some(start * 1_000_000_000)).await?;
+    let first = response
+        .0
+        .into_values()
+        .filter_map(|item| match item {
+            Trade::Pair(data) => Some(data.0),
+            Trade::Last(_) => None,
+        })
+        .flatten()
+        .filter(|trade| trade.2 >= start as f64 && trade.2 < start as f64 + PRICE_WINDOW)
+        .min_by(|a, b| a.2.total_cmp(&b.2));
+    return Ok(first.map(|trade| trade.0));
+}
+
+/// A quantity of an asset acquired at a given time and cost.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaxLot {
+    pub acquired: f64,
+    pub volume: Decimal,
+    pub cost: Decimal,
+    /// False when the cost could not be determined and defaults to zero.
+    pub priced: bool,
+}
+
+/// Decides which lots are consumed by a disposal.
+pub trait Strategy {
+    /// Orders the events of a single (UTC) day before they are applied.
+    /// Events are in chronological order by default.
+    fn arrange(&self, _events: &mut [Event]) {}
+
+    /// Returns the indexes of `lots` in the order they are consumed by a
+    /// disposal happening at `time`.
+    fn order(&self, lots: &[TaxLot], time: f64) -> Vec<usize>;
+}
+
+/// First in, first out.
+pub struct Fifo;
+
+impl Strategy for Fifo {
+    fn order(&self, lots: &[TaxLot], _time: f64) -> Vec<usize> {
+        return (0..lots.len()).collect();
+    }
+}
+
+/// Disposals are matched first with acquisitions of the same day, even
+/// when acquired later that day, then first in, first out.
+pub struct SameDay;
+
+impl Strategy for SameDay {
+    fn arrange(&self, events: &mut [Event]) {
+        events.sort_by_key(|e| !matches!(e.kind, EventKind::Acquire(_) | EventKind::MoveIn));
+    }
+
+    fn order(&self, lots: &[TaxLot], time: f64) -> Vec<usize> {
+        let day = date(time);
+        let (mut same, other): (Vec<usize>, Vec<usize>) =
+            (0..lots.len()).partition(|idx| date(lots[*idx].acquired) == day);
+        same.extend(other);
+        return same;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    /// Acquisition with its cost in the reporting currency, if known.
+    Acquire(Option<Decimal>),
+    /// Disposal with its proceeds in the reporting currency, if known.
+    Dispose(Option<Decimal>),
+    /// Units coming back from a withdrawal or a transfer, keeping the
+    /// cost basis of the lots that left.
+    MoveIn,
+    /// Units leaving without being disposed of (withdrawal, transfer).
+    MoveOut,
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub refid: String,
+    pub asset: String,
+    pub time: f64,
+    pub volume: Decimal,
+    pub kind: EventKind,
+}
+
+/// A row of the capital gains report.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Disposal {
+    #[serde(rename = "Description")]
+    pub asset: String,
+    #[serde(rename = "Amount")]
+    pub volume: Decimal,
+    #[serde(rename = "Date Acquired")]
+    pub acquired: String,
+    #[serde(rename = "Date Sold")]
+    pub disposed: String,
+    #[serde(rename = "Proceeds")]
+    pub proceeds: Decimal,
+    #[serde(rename = "Cost Basis")]
+    pub cost_basis: Decimal,
+    #[serde(rename = "Gain")]
+    pub gain: Decimal,
+    #[serde(rename = "Reference")]
+    pub refid: String,
+    #[serde(rename = "Notes")]
+    pub notes: String,
+    /// Unix time of the disposal.
+    #[serde(skip)]
+    pub time: f64,
+}
+
+fn date(time: f64) -> String {
+    let datetime = DateTime::from_timestamp(time.trunc() as i64, 0).unwrap_or_default();
+    return datetime.format("%Y-%m-%d").to_string();
+}
+
+/// Staked and reward balances (`DOT.S`, `XBT.M`) are the same asset.
+fn normalize(asset: &str) -> &str {
+    return asset.split('.').next().unwrap_or(asset);
+}
+
+/// Turns ledger entries into acquisition and disposal events. Trades are
+/// valued with their leg in `currency` (an asset id such as `ZEUR`) when
+/// there is one, with `prices` otherwise.
+pub fn events(
+    ledgers: &HashMap<String, Ledger>,
+    currency: &str,
+    prices: &dyn PriceSource,
+) -> Vec<Event> {
+    let mut entries: Vec<&Ledger> = ledgers.values().collect();
+    entries.sort_by(|a, b| a.time.total_cmp(&b.time));
+    let mut trades: BTreeMap<&str, Vec<&Ledger>> = BTreeMap::new();
+    let mut events = vec![];
+    for entry in entries {
+        match entry.type_.as_str() {
+            "trade" | "spend" | "receive" | "sale" => {
+                trades.entry(&entry.refid).or_default().push(entry)
+            }
+            "deposit" | "withdrawal" | "transfer" => {
+                if entry.asset == currency {
+                    continue;
+                }
+                let kind = if entry.amount.is_sign_positive() {
+                    EventKind::MoveIn
+                } else {
+                    EventKind::MoveOut
+                };
+                events.push(Event {
+                    refid: entry.refid.clone(),
+                    asset: normalize(&entry.asset).to_string(),
+                    time: entry.time,
+                    volume: (entry.amount - entry.fee).abs(),
+                    kind,
+                });
+            }
+            "staking" | "dividend" | "credit" if entry.amount.is_sign_positive() => {
+                let asset = normalize(&entry.asset);
+                let volume = entry.amount - entry.fee;
+                events.push(Event {
+                    refid: entry.refid.clone(),
+                    asset: asset.to_string(),
+                    time: entry.time,
+                    volume,
+                    kind: EventKind::Acquire(prices.price(asset, entry.time).map(|p| p * volume)),
+                });
+            }
+            _ => {}
+        }
+    }
+    for (refid, legs) in trades {
+        // value of the trade in the reporting currency, fees included.
+        let fiat = legs.iter().find(|l| l.asset == currency);
+        let value = fiat.map(|l| {
+            if l.amount.is_sign_negative() {
+                -l.amount + l.fee
+            } else {
+                l.amount - l.fee
+            }
+        });
+        for leg in legs.iter().filter(|l| l.asset != currency) {
+            let asset = normalize(&leg.asset);
+            let volume = if leg.amount.is_sign_negative() {
+                -leg.amount + leg.fee
+            } else {
+                leg.amount - leg.fee
+            };
+            let value = value.or_else(|| prices.price(asset, leg.time).map(|p| p * volume));
+            let kind = if leg.amount.is_sign_negative() {
+                EventKind::Dispose(value)
+            } else {
+                EventKind::Acquire(value)
+            };
+            events.push(Event {
+                refid: refid.to_string(),
+                asset: asset.to_string(),
+                time: leg.time,
+                volume,
+                kind,
+            });
+        }
+    }
+    events.sort_by(|a, b| a.time.total_cmp(&b.time));
+    return events;
+}
+
+#[derive(Default)]
+struct Holdings {
+    lots: Vec<TaxLot>,
+    /// Lots that left the account and may come back with a deposit.
+    moved: Vec<TaxLot>,
+}
+
+/// Removes `volume` from `lots` in the given order and returns the
+/// consumed parts.
+fn take(lots: &mut Vec<TaxLot>, order: &[usize], mut volume: Decimal) -> Vec<TaxLot> {
+    let mut taken = vec![];
+    for idx in order {
+        if volume.is_zero() {
+            break;
+        }
+        let lot = &mut lots[*idx];
+        let matched = volume.min(lot.volume);
+        let cost = lot.cost * matched / lot.volume;
+        taken.push(TaxLot {
+            acquired: lot.acquired,
+            volume: matched,
+            cost,
+            priced: lot.priced,
+        });
+        lot.volume -= matched;
+        lot.cost -= cost;
+        volume -= matched;
+    }
+    lots.retain(|lot| !lot.volume.is_zero());
+    return taken;
+}
+
+impl Holdings {
+    fn apply(&mut self, strategy: &dyn Strategy, event: &Event, disposals: &mut Vec<Disposal>) {
+        match &event.kind {
+            EventKind::Acquire(cost) => self.lots.push(TaxLot {
+                acquired: event.time,
+                volume: event.volume,
+                cost: cost.unwrap_or_default(),
+                priced: cost.is_some(),
+            }),
+            EventKind::MoveOut => {
+                let order = strategy.order(&self.lots, event.time);
+                let taken = take(&mut self.lots, &order, event.volume);
+                self.moved.extend(taken);
+            }
+            EventKind::MoveIn => {
+                let order: Vec<usize> = (0..self.moved.len()).collect();
+                let taken = take(&mut self.moved, &order, event.volume);
+                let returned: Decimal = taken.iter().map(|l| l.volume).sum();
+                self.lots.extend(taken);
+                // units never seen before are acquired with an unknown cost.
+                if returned < event.volume {
+                    self.lots.push(TaxLot {
+                        acquired: event.time,
+                        volume: event.volume - returned,
+                        cost: Decimal::ZERO,
+                        priced: false,
+                    });
+                }
+            }
+            EventKind::Dispose(proceeds) => {
+                let order = strategy.order(&self.lots, event.time);
+                let mut taken = take(&mut self.lots, &order, event.volume);
+                let matched: Decimal = taken.iter().map(|l| l.volume).sum();
+                if matched < event.volume {
+                    taken.push(TaxLot {
+                        acquired: f64::NAN,
+                        volume: event.volume - matched,
+                        cost: Decimal::ZERO,
+                        priced: false,
+                    });
+                }
+                for lot in taken {
+                    let share = proceeds.unwrap_or_default() * lot.volume / event.volume;
+                    let mut notes = vec![];
+                    if proceeds.is_none() {
+                        notes.push("missing proceeds");
+                    }
+                    if lot.acquired.is_nan() {
+                        notes.push("no matching acquisition");
+                    } else if !lot.priced {
+                        notes.push("missing cost basis");
+                    }
+                    disposals.push(Disposal {
+                        asset: event.asset.clone(),
+                        volume: lot.volume,
+                        acquired: if lot.acquired.is_nan() {
+                            String::new()
+                        } else {
+                            date(lot.acquired)
+                        },
+                        disposed: date(event.time),
+                        proceeds: share,
+                        cost_basis: lot.cost,
+                        gain: share - lot.cost,
+                        refid: event.refid.clone(),
+                        notes: notes.join(", "),
+                        time: event.time,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Matches disposals against acquisitions, asset by asset, and returns
+/// the resulting capital gains rows in chronological order.
+pub fn disposals(events: &[Event], strategy: &dyn Strategy) -> Vec<Disposal> {
+    let mut by_asset: BTreeMap<&str, Vec<Event>> = BTreeMap::new();
+    for event in events {
+        by_asset
+            .entry(&event.asset)
+            .or_default()
+            .push(event.clone());
+    }
+    let mut disposals = vec![];
+    for (_, mut events) in by_asset {
+        let mut holdings = Holdings::default();
+        let mut start = 0;
+        while start < events.len() {
+            let day = date(events[start].time);
+            let end = start
+                + events[start..]
+                    .iter()
+                    .take_while(|e| date(e.time) == day)
+                    .count();
+            strategy.arrange(&mut events[start..end]);
+            for event in &events[start..end] {
+                holdings.apply(strategy, event, &mut disposals);
+            }
+            start = end;
+        }
+    }
+    disposals.sort_by(|a, b| a.disposed.cmp(&b.disposed).then(a.refid.cmp(&b.refid)));
+    return disposals;
+}
+
+/// Writes the report as CSV with Form 8949 like columns.
+pub fn write_csv<W: Write>(disposals: &[Disposal], writer: W) -> Result<(), Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for disposal in disposals {
+        writer.serialize(disposal).map_err(Error::storage)?;
+    }
+    writer.flush()?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disposals, events, Fifo, NoPrices, SameDay};
+    use crate::private::Ledger;
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+
+    fn entry(refid: &str, time: f64, asset: &str, amount: i64, fee: i64) -> (String, Ledger) {
+        let ledger = serde_json::from_value(serde_json::json!({
+            "refid": refid, "time": time, "type": "trade", "subtype": "", "aclass": "currency",
+            "asset": asset, "amount": amount.to_string(), "fee": fee.to_string(), "balance": "0"
+        }))
+        .unwrap();
+        return (format!("L{}{}", refid, asset), ledger);
+    }
+
+    fn ledgers() -> HashMap<String, Ledger> {
+        const DAY: f64 = 86400.0;
+        return vec![
+            entry("T1", DAY, "ZEUR", -100, 1),
+            entry("T1", DAY, "XXBT", 1, 0),
+            entry("T2", 2.0 * DAY, "XXBT", -1, 0),
+            entry("T2", 2.0 * DAY, "ZEUR", 300, 0),
+            entry("T3", 2.0 * DAY + 60.0, "ZEUR", -250, 0),
+            entry("T3", 2.0 * DAY + 60.0, "XXBT", 1, 0),
+        ]
+        .into_iter()
+        .collect();
+    }
+
+    #[test]
+    fn fifo_test() {
+        let events = events(&ledgers(), "ZEUR", &NoPrices);
+        let rows = disposals(&events, &Fifo);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].acquired, "1970-01-02");
+        assert_eq!(rows[0].disposed, "1970-01-03");
+        assert_eq!(rows[0].cost_basis, Decimal::from(101));
+        assert_eq!(rows[0].gain, Decimal::from(199));
+        assert_eq!(rows[0].notes, "");
+    }
+
+    #[test]
+    fn same_day_test() {
+        let events = events(&ledgers(), "ZEUR", &NoPrices);
+        let rows = disposals(&events, &SameDay);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].acquired, "1970-01-03");
+        assert_eq!(rows[0].cost_basis, Decimal::from(250));
+        assert_eq!(rows[0].gain, Decimal::from(50));
+    }
+
+    #[test]
+    fn unmatched_test() {
+        let mut ledgers = ledgers();
+        ledgers.extend(vec![
+            entry("T4", 10.0 * 86400.0, "XXBT", -2, 0),
+            entry("T4", 10.0 * 86400.0, "ZEUR", 600, 0),
+        ]);
+        let events = events(&ledgers, "ZEUR", &NoPrices);
+        let rows = disposals(&events, &Fifo);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2].notes, "no matching acquisition");
+        assert_eq!(rows[2].proceeds, Decimal::from(300));
+    }
+}
@@ -0,0 +1,207 @@
+//! Markets and orders shared by the unit tests.
+
+use crate::backfill::Candle;
+use crate::exchange::{Exchange, MarketEvent};
+use crate::paper::Paper;
+use crate::private::{
+    AddOrderResponse, BalanceResponse, CancelAllResponse, CancelOrderResponse,
+    ClosedOrdersResponse, NewOrder, OpenOrdersResponse, QueryOrdersResponse, TradesHistoryResponse,
+};
+use crate::public::{AssetPair, OrderBook};
+use crate::registry::AssetRegistry;
+use crate::Error;
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+pub fn d(v: &str) -> Decimal {
+    return Decimal::from_str(v).unwrap();
+}
+
+/// Returns the JSON of a pair with the precision, limits and fee tiers of
+/// XBTUSD.
+pub fn pair_json(altname: &str, wsname: &str, base: &str, quote: &str) -> String {
+    return format!(
+        r#"{{"altname": "{}", "wsname": "{}", "aclass_base": "currency", "base": "{}",
+        "aclass_quote": "currency", "quote": "{}", "lot": "unit", "pair_decimals": 1,
+        "lot_decimals": 8, "lot_multiplier": 1, "leverage_buy": [2, 3], "leverage_sell": [2],
+        "fees": [[0, 0.26], [50000, 0.24], [100000, 0.22]],
+        "fees_maker": [[0, 0.16], [50000, 0.14], [100000, 0.12]],
+        "fee_volume_currency": "ZUSD", "margin_call": 80, "margin_stop": 40, "ordermin": "0.0001"}}"#,
+        altname, wsname, base, quote
+    );
+}
+
+pub fn pair() -> AssetPair {
+    return serde_json::from_str(&pair_json("XBTUSD", "XBT/USD", "XXBT", "ZUSD")).unwrap();
+}
+
+/// Builds a registry of the given assets and pairs, the pairs given as
+/// `(id, altname, wsname, base, quote)`.
+pub fn registry(assets: &[&str], pairs: &[(&str, &str, &str, &str, &str)]) -> AssetRegistry {
+    let assets = assets
+        .iter()
+        .map(|id| {
+            let (altname, decimals, display_decimals) = match *id {
+                "XXBT" => ("XBT", 10, 5),
+                "XETH" => ("ETH", 10, 5),
+                "XXDG" => ("XDG", 8, 2),
+                "ZUSD" => ("USD", 4, 2),
+                "USDT" => ("USDT", 8, 4),
+                "DOT" => ("DOT", 10, 8),
+                _ => panic!("unknown test asset {}", id),
+            };
+            let asset = serde_json::json!({
+                "aclass": "currency",
+                "altname": altname,
+                "decimals": decimals,
+                "display_decimals": display_decimals,
+            });
+            return (id.to_string(), asset);
+        })
+        .collect::<serde_json::Map<_, _>>();
+    let pairs = pairs
+        .iter()
+        .map(|(id, altname, wsname, base, quote)| {
+            let pair = serde_json::from_str(&pair_json(altname, wsname, base, quote)).unwrap();
+            return (id.to_string(), pair);
+        })
+        .collect::<serde_json::Map<_, serde_json::Value>>();
+    let assets = serde_json::from_value(assets.into()).unwrap();
+    let pairs = serde_json::from_value(pairs.into()).unwrap();
+    return AssetRegistry::new(assets, pairs);
+}
+
+/// Returns a simulated account holding 10000 USD, trading XBTUSD.
+pub fn paper() -> Paper {
+    let registry = registry(
+        &["XXBT", "ZUSD"],
+        &[("XXBTZUSD", "XBTUSD", "XBT/USD", "XXBT", "ZUSD")],
+    );
+    return Paper::new(Box::leak(Box::new(registry))).with_balance("USD", d("10000"));
+}
+
+pub fn order(type_: &str, ordertype: &str, volume: &str, price: Option<&str>) -> NewOrder {
+    return NewOrder {
+        pair: "XBTUSD".to_string(),
+        type_: type_.to_string(),
+        ordertype: ordertype.to_string(),
+        volume: d(volume),
+        price: price.map(d),
+        ..Default::default()
+    };
+}
+
+/// Returns a book holding 5 XBT at the best bid and at the best ask.
+pub fn book(bid: &str, ask: &str) -> OrderBook {
+    return OrderBook {
+        asks: vec![(d(ask), d("5"), 0)],
+        bids: vec![(d(bid), d("5"), 0)],
+    };
+}
+
+/// Returns a `book` update of XXBTZUSD.
+pub fn book_event(bid: &str, ask: &str) -> MarketEvent {
+    return MarketEvent::Book {
+        pair: "XXBTZUSD".to_string(),
+        book: book(bid, ask),
+    };
+}
+
+/// Returns a candle of a single trade, its vwap at the close.
+pub fn candle(time: u64, open: &str, high: &str, low: &str, close: &str, volume: &str) -> Candle {
+    return Candle {
+        time,
+        open: d(open),
+        high: d(high),
+        low: d(low),
+        close: d(close),
+        vwap: d(close),
+        volume: d(volume),
+        count: 1,
+    };
+}
+
+/// Returns a candle update of XXBTZUSD.
+pub fn candle_event(candle: Candle) -> MarketEvent {
+    return MarketEvent::Candle {
+        pair: "XXBTZUSD".to_string(),
+        candle,
+    };
+}
+
+/// A simulated account behind an unreliable connection: placed orders
+/// lose their answer and cancels fail without reaching the account.
+pub struct Unreliable {
+    paper: Paper,
+    add_order: Option<&'static str>,
+    cancel_order: Option<&'static str>,
+}
+
+impl Unreliable {
+    pub fn new(paper: Paper) -> Self {
+        Self {
+            paper,
+            add_order: None,
+            cancel_order: None,
+        }
+    }
+
+    /// Error returned once an order is placed.
+    pub fn with_add_order(mut self, error: &'static str) -> Self {
+        self.add_order = Some(error);
+        return self;
+    }
+
+    /// Error returned instead of canceling.
+    pub fn with_cancel_order(mut self, error: &'static str) -> Self {
+        self.cancel_order = Some(error);
+        return self;
+    }
+}
+
+#[async_trait]
+impl Exchange for Unreliable {
+    async fn balance(&self) -> Result<BalanceResponse, Error> {
+        self.paper.balance().await
+    }
+
+    async fn open_orders(&self) -> Result<OpenOrdersResponse, Error> {
+        self.paper.open_orders().await
+    }
+
+    async fn closed_orders(&self) -> Result<ClosedOrdersResponse, Error> {
+        self.paper.closed_orders().await
+    }
+
+    async fn query_orders(&self, txids: &[&str]) -> Result<QueryOrdersResponse, Error> {
+        self.paper.query_orders(txids).await
+    }
+
+    async fn trades_history(&self) -> Result<TradesHistoryResponse, Error> {
+        self.paper.trades_history().await
+    }
+
+    async fn add_order(&self, order: &NewOrder, validate: bool) -> Result<AddOrderResponse, Error> {
+        let response = self.paper.add_order(order, validate).await?;
+        match self.add_order {
+            Some(error) => Err(Error::API(error.to_string())),
+            None => Ok(response),
+        }
+    }
+
+    async fn cancel_order(&self, txid: &str) -> Result<CancelOrderResponse, Error> {
+        match self.cancel_order {
+            Some(error) => Err(Error::API(error.to_string())),
+            None => self.paper.cancel_order(txid).await,
+        }
+    }
+
+    async fn cancel_all(&self) -> Result<CancelAllResponse, Error> {
+        self.paper.cancel_all().await
+    }
+
+    fn observe(&self, event: &MarketEvent) -> Result<(), Error> {
+        self.paper.observe(event)
+    }
+}
@@ -0,0 +1,230 @@
+use crate::private::NewOrder;
+use crate::public::AssetPair;
+use rust_decimal::{Decimal, RoundingStrategy};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ValidationError {
+    #[error("unknown order side {0}")]
+    Side(String),
+    #[error("volume {0} must be positive")]
+    Volume(Decimal),
+    #[error("price {0} must be positive")]
+    Price(Decimal),
+    #[error("order type {0} requires a price")]
+    MissingPrice(String),
+    #[error("order type {0} requires a secondary price")]
+    MissingPrice2(String),
+    #[error("price {price} has more than {decimals} decimals")]
+    PricePrecision { price: Decimal, decimals: u64 },
+    #[error("volume {volume} has more than {decimals} decimals")]
+    VolumePrecision { volume: Decimal, decimals: u64 },
+    #[error("volume {volume} is below the minimum order size {ordermin}")]
+    BelowMinimum { volume: Decimal, ordermin: Decimal },
+    #[error("leverage {leverage} is not allowed, allowed values: {allowed:?}")]
+    Leverage { leverage: u64, allowed: Vec<u64> },
+    #[error("order type {0} is not supported")]
+    OrderType(String),
+}
+
+/// Checks orders against the precision, minimum size and leverage
+/// rules of a pair before they are sent to the exchange.
+pub struct Validator<'a> {
+    pair: &'a AssetPair,
+    round: bool,
+}
+
+/// Order types accepted by the exchange.
+pub const ORDER_TYPES: &[&str] = &[
+    "market",
+    "limit",
+    "stop-loss",
+    "take-profit",
+    "stop-loss-limit",
+    "take-profit-limit",
+    "trailing-stop",
+    "trailing-stop-limit",
+    "settle-position",
+];
+
+fn decimals(val: &Decimal) -> u64 {
+    return val.normalize().scale() as u64;
+}
+
+fn requires_price(ordertype: &str) -> bool {
+    return !matches!(ordertype, "market" | "settle-position");
+}
+
+fn requires_price2(ordertype: &str) -> bool {
+    return matches!(
+        ordertype,
+        "stop-loss-limit" | "take-profit-limit" | "trailing-stop-limit"
+    );
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(pair: &'a AssetPair) -> Self {
+        Self { pair, round: false }
+    }
+
+    /// When enabled, prices are rounded to the nearest tick and volumes
+    /// are rounded down to the lot precision instead of being rejected.
+    pub fn round(mut self, round: bool) -> Self {
+        self.round = round;
+        return self;
+    }
+
+    fn check_price(&self, price: Decimal) -> Result<Decimal, ValidationError> {
+        if price <= Decimal::ZERO {
+            return Err(ValidationError::Price(price));
+        }
+        let max = self.pair.pair_decimals;
+        if decimals(&price) <= max {
+            return Ok(price);
+        }
+        if !self.round {
+            return Err(ValidationError::PricePrecision {
+                price,
+                decimals: max,
+            });
+        }
+        let rounded =
+            price.round_dp_with_strategy(max as u32, RoundingStrategy::MidpointAwayFromZero);
+        if rounded <= Decimal::ZERO {
+            return Err(ValidationError::Price(rounded));
+        }
+        return Ok(rounded);
+    }
+
+    fn check_volume(&self, volume: Decimal) -> Result<Decimal, ValidationError> {
+        if volume <= Decimal::ZERO {
+            return Err(ValidationError::Volume(volume));
+        }
+        let max = self.pair.lot_decimals;
+        let volume = if decimals(&volume) <= max {
+            volume
+        } else if self.round {
+            volume.round_dp_with_strategy(max as u32, RoundingStrategy::ToZero)
+        } else {
+            return Err(ValidationError::VolumePrecision {
+                volume,
+                decimals: max,
+            });
+        };
+        if volume < self.pair.ordermin {
+            return Err(ValidationError::BelowMinimum {
+                volume,
+                ordermin: self.pair.ordermin,
+            });
+        }
+        return Ok(volume);
+    }
+
+    fn check_leverage(&self, side: &str, leverage: u64) -> Result<(), ValidationError> {
+        // a leverage of 1 is the same as no leverage.
+        if leverage <= 1 {
+            return Ok(());
+        }
+        let allowed = match side {
+            "buy" => &self.pair.leverage_buy,
+            _ => &self.pair.leverage_sell,
+        };
+        if allowed.contains(&leverage) {
+            return Ok(());
+        }
+        return Err(ValidationError::Leverage {
+            leverage,
+            allowed: allowed.clone(),
+        });
+    }
+
+    /// Returns the order with its price and volume rounded (when rounding
+    /// is enabled) or the first rule it breaks.
+    pub fn validate(&self, order: &NewOrder) -> Result<NewOrder, ValidationError> {
+        if !matches!(order.type_.as_str(), "buy" | "sell") {
+            return Err(ValidationError::Side(order.type_.clone()));
+        }
+        if !ORDER_TYPES.contains(&order.ordertype.as_str()) {
+            return Err(ValidationError::OrderType(order.ordertype.clone()));
+        }
+        let mut order = order.clone();
+        if requires_price(&order.ordertype) && order.price.is_none() {
+            return Err(ValidationError::MissingPrice(order.ordertype));
+        }
+        if requires_price2(&order.ordertype) && order.price2.is_none() {
+            return Err(ValidationError::MissingPrice2(order.ordertype));
+        }
+        if let Some(price) = order.price {
+            order.price = Some(self.check_price(price)?);
+        }
+        if let Some(price2) = order.price2 {
+            order.price2 = Some(self.check_price(price2)?);
+        }
+        order.volume = self.check_volume(order.volume)?;
+        if let Some(leverage) = order.leverage {
+            self.check_leverage(&order.type_, leverage)?;
+        }
+        return Ok(order);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ValidationError, Validator};
+    use crate::testing::{d, order, pair};
+
+    #[test]
+    fn precision_test() {
+        let pair = pair();
+        let validator = Validator::new(&pair);
+        assert!(validator
+            .validate(&order("buy", "limit", "1.25", Some("37500.10")))
+            .is_ok());
+        assert_eq!(
+            validator
+                .validate(&order("buy", "limit", "1.25", Some("37500.15")))
+                .unwrap_err(),
+            ValidationError::PricePrecision {
+                price: d("37500.15"),
+                decimals: 1,
+            }
+        );
+        let rounded = validator
+            .round(true)
+            .validate(&order("buy", "limit", "0.123456789", Some("37500.15")))
+            .unwrap();
+        assert_eq!(rounded.price, Some(d("37500.2")));
+        assert_eq!(rounded.volume, d("0.12345678"));
+    }
+
+    #[test]
+    fn limits_test() {
+        let pair = pair();
+        let validator = Validator::new(&pair);
+        assert!(matches!(
+            validator.validate(&order("buy", "limit", "0.00001", Some("37500"))),
+            Err(ValidationError::BelowMinimum { .. })
+        ));
+        let mut leveraged = order("buy", "limit", "1", Some("37500"));
+        leveraged.leverage = Some(3);
+        assert!(validator.validate(&leveraged).is_ok());
+        leveraged.type_ = "sell".to_string();
+        assert!(matches!(
+            validator.validate(&leveraged),
+            Err(ValidationError::Leverage { leverage: 3, .. })
+        ));
+        let mut market = order("buy", "limit", "1", Some("1"));
+        market.ordertype = "stop-loss-limit".to_string();
+        assert_eq!(
+            validator.validate(&market).unwrap_err(),
+            ValidationError::MissingPrice2("stop-loss-limit".to_string())
+        );
+        // a price does not make an unknown type valid.
+        assert_eq!(
+            validator
+                .validate(&order("buy", "stop", "1", Some("1")))
+                .unwrap_err(),
+            ValidationError::OrderType("stop".to_string())
+        );
+    }
+}
@@ -0,0 +1,153 @@
+#![allow(clippy::needless_return)]
+
+use kraken::permissions::{self, Access, Permission};
+use kraken::private::{NewOrder, OrderEdit};
+use kraken::public::{Trade, OHLC};
+use kraken::transport::Replay;
+use kraken::{private, public, Credential};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+fn setup() -> Credential {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+    kraken::set_transport(Replay::load(dir).unwrap());
+    return Credential::new("KEY", b"SECRET");
+}
+
+#[tokio::test]
+async fn public_test() {
+    setup();
+    assert!(public::time().await.is_ok());
+    let ticker = public::ticker("XBTUSD").await.unwrap();
+    let info = ticker.0.get("XXBTZUSD").unwrap();
+    assert_eq!(info.c.0, Decimal::from_str("30303.2").unwrap());
+    // requests without fixture fail instead of reaching the network.
+    assert!(public::ticker("ETHUSD").await.is_err());
+}
+
+#[tokio::test]
+async fn market_test() {
+    setup();
+    let assets = public::assets(None, None).await.unwrap();
+    assert_eq!(assets.0.get("XXBT").unwrap().altname, "XBT");
+    let pairs = public::asset_pair(&["XBTUSD"], Some("info")).await.unwrap();
+    assert_eq!(
+        pairs.0.get("XXBTZUSD").unwrap().ordermin,
+        Decimal::from_str("0.0001").unwrap()
+    );
+    match public::ohcl("XBTUSD", Some(1), None)
+        .await
+        .unwrap()
+        .0
+        .get("XXBTZUSD")
+    {
+        Some(OHLC::Pair(ticks)) => assert_eq!(ticks.len(), 2),
+        _ => panic!("missing candles"),
+    }
+    let depth = public::depth("XBTUSD", None).await.unwrap();
+    let book = depth.0.get("XXBTZUSD").unwrap();
+    assert!(book.asks[0].0 > book.bids[0].0);
+    match public::trades("XBTUSD", None).await.unwrap().0.get("last") {
+        Some(Trade::Last(last)) => assert_eq!(last, "1688671969993150842"),
+        _ => panic!("missing cursor"),
+    }
+    assert!(public::spread("XBTUSD", None).await.is_ok());
+}
+
+#[tokio::test]
+async fn private_test() {
+    let cred = setup();
+    let balance = private::balance(&cred).await.unwrap();
+    assert_eq!(
+        balance.0.get("XXBT"),
+        Some(&Decimal::from_str("0.0011").unwrap())
+    );
+    let orders = private::open_orders(&cred, None, None).await.unwrap();
+    let order = orders.open.get("OQCLML-BW3P3-BUCMWZ").unwrap();
+    assert_eq!(order.descr.pair, "XBTUSD");
+    assert_eq!(order.vol_exec, Decimal::from_str("0.375").unwrap());
+}
+
+#[tokio::test]
+async fn history_test() {
+    let cred = setup();
+    let balance = private::balance_ex(&cred).await.unwrap();
+    assert_eq!(
+        balance.0.get("ZUSD").unwrap().hold_trade,
+        Decimal::from_str("11253.7").unwrap()
+    );
+    assert!(private::trade_balance(&cred, None).await.is_ok());
+    let closed = private::closed_orders(&cred, Some(false), None, None, None, None, Some("both"))
+        .await
+        .unwrap();
+    assert_eq!(
+        closed.closed.get("O37652-RJWRT-IMO74O").unwrap().status,
+        "closed"
+    );
+    let history = private::trades_history(&cred, Some("all"), Some(false), None, None, None)
+        .await
+        .unwrap();
+    let trade = history.trades.get("TCCCTY-WE2O6-P3NB37").unwrap();
+    assert_eq!(trade.ordertxid, "OQCLML-BW3P3-BUCMWZ");
+    let ledgers = private::ledgers(
+        &cred,
+        Some(&["all"]),
+        Some("currency"),
+        Some("all"),
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(ledgers.count, 2);
+    let volume = private::trade_volume(&cred, Some(&["XXBTZUSD"]), Some(true))
+        .await
+        .unwrap();
+    let fee = volume.fees.unwrap().remove("XXBTZUSD").unwrap();
+    assert_eq!(fee.fee, Decimal::from_str("0.26").unwrap());
+}
+
+#[tokio::test]
+async fn trading_test() {
+    let cred = setup();
+    let order = NewOrder {
+        pair: "XXBTZUSD".to_string(),
+        type_: "buy".to_string(),
+        ordertype: "limit".to_string(),
+        volume: Decimal::from_str("0.01").unwrap(),
+        price: Some(Decimal::from_str("30000").unwrap()),
+        ..Default::default()
+    };
+    let added = private::add_order(&cred, &order, false).await.unwrap();
+    assert_eq!(added.txid.unwrap(), vec!["OUF4EM-FRGI2-MQMWZD"]);
+    let edit = OrderEdit {
+        price: Some(Decimal::from_str("30100").unwrap()),
+        ..Default::default()
+    };
+    let edited = private::edit_order(&cred, "OQCLML-BW3P3-BUCMWZ", "XBTUSD", &edit, false)
+        .await
+        .unwrap();
+    assert_eq!(edited.originaltxid.unwrap(), "OQCLML-BW3P3-BUCMWZ");
+    let canceled = private::cancel_order(&cred, "OQCLML-BW3P3-BUCMWZ")
+        .await
+        .unwrap();
+    assert_eq!(canceled.count, 1);
+    assert_eq!(private::cancel_all(&cred).await.unwrap().count, 1);
+}
+
+#[tokio::test]
+async fn probe_test() {
+    let cred = setup();
+    let access = permissions::probe(&cred).await.unwrap();
+    assert_eq!(
+        access,
+        vec![
+            (Permission::QueryFunds, Access::Granted),
+            (Permission::QueryOrders, Access::Granted),
+            (Permission::QueryLedger, Access::Denied),
+            (Permission::Trade, Access::Granted),
+            (Permission::Cancel, Access::Granted),
+        ]
+    );
+}